@@ -1,10 +1,10 @@
 use serde_json::Value;
 use std::{
-    cmp::max, collections::HashMap, io::{stdout, Write}, path::Path, time::Duration
+    cell::RefCell, cmp::max, collections::HashMap, io::{stdout, Write}, path::Path, time::Duration
 };
 use utils::Table;
 use zstd::{
-    bulk::{compress, decompress, Compressor, Decompressor},
+    bulk::{compress, Compressor, Decompressor},
     dict::from_samples,
 };
 
@@ -12,6 +12,9 @@ use crate::utils::{get_size_unit, get_test_files, get_test_files_as_array, json_
 
 mod utils;
 
+type EncodeFn = fn(&Value, &str, bool, Option<&Vec<u8>>) -> (Vec<u8>, Duration);
+type DecodeFn = fn(&[u8], &str, bool, Option<&Vec<u8>>) -> (Value, Duration);
+
 const TEST_FILE_NAMES_SMALL: &[&str] = &[
     "MediaContent.json.zst",
     "reddit_comments_1.json.zst",
@@ -45,9 +48,9 @@ enum CompressionConfig {
 }
 
 fn main() {
-    let table_small_files = benchmark_size_relative(&TEST_FILE_NAMES_SMALL);
-    let table_big_files = benchmark_size_relative(&TEST_FILE_NAMES_BIG);
-    let (table_files_avg, table_encode_times, table_decode_times) = benchmark_size_relative_avg(&TEST_FILE_NAMES_FOR_AVG);
+    let table_small_files = benchmark_size_relative(TEST_FILE_NAMES_SMALL);
+    let table_big_files = benchmark_size_relative(TEST_FILE_NAMES_BIG);
+    let (table_files_avg, table_encode_times, table_decode_times) = benchmark_size_relative_avg(TEST_FILE_NAMES_FOR_AVG);
     println!(
         "\nRELATIVE SIZE RESULTS (SMALL):\n{}",
         table_small_files.to_markdown()
@@ -68,14 +71,15 @@ fn main() {
         "DECODE TIME RESULTS (AVERAGE):\n{}",
         table_decode_times.to_markdown()
     );
+    let table_key_counts = benchmark_key_count();
+    println!(
+        "ENCODE TIME RESULTS (KEY COUNT):\n{}",
+        table_key_counts.to_markdown()
+    );
 }
 
 fn benchmark_size_relative(file_names: &[&str]) -> Table {
-    let test_functions: &[(
-        &str,
-        fn(&Value, &String, bool, Option<&Vec<u8>>) -> (Vec<u8>, Duration),
-        fn(&Vec<u8>, &String, bool, Option<&Vec<u8>>) -> (Value, Duration),
-    )] = &[
+    let test_functions: &[(&str, EncodeFn, DecodeFn)] = &[
         ("plain text", encode_json, decode_json),
         // ("UBJSON", encode_ubjson, decode_ubjson),
         ("MessagePack", encode_messagepack, decode_messagepack),
@@ -89,19 +93,13 @@ fn benchmark_size_relative(file_names: &[&str]) -> Table {
         ),
     ];
 
-    let test_functions: Vec<(
-        &str,
-        fn(&Value, &String, bool, Option<&Vec<u8>>) -> (Vec<u8>, Duration),
-        fn(&Vec<u8>, &String, bool, Option<&Vec<u8>>) -> (Value, Duration),
-        bool,
-    )> = test_functions
+    let test_functions: Vec<(&str, EncodeFn, DecodeFn, bool)> = test_functions
         .iter()
         .map(|(name, encode, decode)| (*name, *encode, *decode, false))
         .chain(
             test_functions
                 .iter()
-                .map(|(name, encode, decode)| (*name, *encode, *decode, true))
-                .into_iter(),
+                .map(|(name, encode, decode)| (*name, *encode, *decode, true)),
         )
         .collect();
     let test_files = get_test_files(file_names);
@@ -111,7 +109,7 @@ fn benchmark_size_relative(file_names: &[&str]) -> Table {
     let mut plain_text_sizes: Vec<usize> = Vec::new();
     let mut longest_log_line = 0;
     for (path, value) in test_files.iter() {
-        let log_line = format!("{}", path);
+        let log_line = path.to_string();
         longest_log_line = longest_log_line.max(log_line.len());
         print!("\r{:<1$}", log_line, longest_log_line + 1);
         stdout().flush().unwrap();
@@ -167,15 +165,11 @@ fn benchmark_size_relative(file_names: &[&str]) -> Table {
     }
     println!();
 
-    return Table { header, rows };
+    Table { header, rows }
 }
 
 fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
-    let test_function_presets: &[(
-        &str,
-        fn(&Value, &String, bool, Option<&Vec<u8>>) -> (Vec<u8>, Duration),
-        fn(&Vec<u8>, &String, bool, Option<&Vec<u8>>) -> (Value, Duration),
-    )] = &[
+    let test_function_presets: &[(&str, EncodeFn, DecodeFn)] = &[
         ("plain text", encode_json, decode_json),
         //("UBJSON", encode_ubjson, decode_ubjson),
         ("MessagePack", encode_messagepack, decode_messagepack),
@@ -205,9 +199,8 @@ fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
             )
         }))
         .collect();
-    let uses_trained_dicts = test_functions.iter().any(|(_, _, _, config)| match config {
-        CompressionConfig::ZstdCompressionWithTrainedDict => true,
-        _ => false,
+    let uses_trained_dicts = test_functions.iter().any(|(_, _, _, config)| {
+        matches!(config, CompressionConfig::ZstdCompressionWithTrainedDict)
     });
 
     let test_files = get_test_files_as_array(file_names);
@@ -251,16 +244,15 @@ fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
             longest_log_line = longest_log_line.max(log_line.len());
             print!("\r{:<1$}", log_line, longest_log_line + 1);
             stdout().flush().unwrap();
-            let dict: Vec<u8>;
-            if uses_trained_dicts {
+            let dict: Vec<u8> = if uses_trained_dicts {
                 let encoded_values: Vec<Vec<u8>> = values
                     .iter()
                     .map(|value| encode(value, path, false, None).0)
                     .collect();
-                dict = from_samples(&encoded_values, DICT_SIZE).unwrap();
+                from_samples(&encoded_values, DICT_SIZE).unwrap()
             } else {
-                dict = Vec::new();
-            }
+                Vec::new()
+            };
             trained_dicts.insert((name.to_string(), path.clone()), dict);
         }
     }
@@ -272,18 +264,13 @@ fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
         let mut sizes_row: Vec<String> = Vec::new();
         let mut encode_time_row: Vec<String> = Vec::new();
         let mut decode_time_row: Vec<String> = Vec::new();
-        let row_name: String;
-        match compression_config {
-            CompressionConfig::NoCompression => {
-                row_name = name.to_string();
-            }
-            CompressionConfig::ZstdCompression => {
-                row_name = format!("{} (+zstd)", name);
-            }
+        let row_name: String = match compression_config {
+            CompressionConfig::NoCompression => name.to_string(),
+            CompressionConfig::ZstdCompression => format!("{} (+zstd)", name),
             CompressionConfig::ZstdCompressionWithTrainedDict => {
-                row_name = format!("{} (+zstd +trained dict)", name);
+                format!("{} (+zstd +trained dict)", name)
             }
-        }
+        };
         sizes_row.push(row_name.clone());
         encode_time_row.push(row_name.clone());
         decode_time_row.push(row_name);
@@ -342,7 +329,7 @@ fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
             }
             let size_avg = encoded_size_total as f64 / encode_total_count as f64;
             let plain_text_size = plain_text_sizes[i_file];
-            let ratio = size_avg as f64 / plain_text_size as f64 * 100.0;
+            let ratio = size_avg / plain_text_size * 100.0;
             let mut ratio = format!("{:.1}%", ratio);
             if i_func == 0 {
                 let (size_div, size_unit) = get_size_unit(plain_text_size as usize);
@@ -373,7 +360,7 @@ fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
     }
     println!();
 
-    return (
+    (
         Table {
             header: sizes_header.clone(),
             rows: sizes_rows,
@@ -386,7 +373,60 @@ fn benchmark_size_relative_avg(file_names: &[&str]) -> (Table, Table, Table) {
             header: sizes_header,
             rows: decode_time_rows,
         },
-    );
+    )
+}
+
+/// Encodes documents of the same size with growing numbers of distinct keys. With hashed
+/// key lookups the encode time should stay roughly constant.
+fn benchmark_key_count() -> Table {
+    const KEY_COUNTS: &[usize] = &[100, 1_000, 10_000, 65_535];
+    const OBJECT_COUNT: usize = 200;
+    const KEYS_PER_OBJECT: usize = 50;
+    const REPEAT_COUNT: u32 = 10;
+
+    let header: Vec<String> = ["", "global table", "local table"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for &key_count in KEY_COUNTS {
+        print!("\rkey count {:<10}", key_count);
+        stdout().flush().unwrap();
+        let keys: Vec<String> = (0..key_count).map(|i| format!("key_{}", i)).collect();
+        // spread the used keys over the whole table
+        let value = Value::Array(
+            (0..OBJECT_COUNT)
+                .map(|i_object| {
+                    let object = (0..KEYS_PER_OBJECT)
+                        .map(|i_key| {
+                            let key_index = (i_object * KEYS_PER_OBJECT + i_key) * 7919 % key_count;
+                            (keys[key_index].clone(), Value::from(i_key))
+                        })
+                        .collect();
+                    Value::Object(object)
+                })
+                .collect(),
+        );
+        let global_table = frac_json::GlobalKeysTable::new(keys);
+        let global_encoder = frac_json::Encoder::new(Some(global_table), None, None).unwrap();
+        let local_encoder = frac_json::Encoder::new(None, None, None).unwrap();
+
+        let mut row = vec![format!("{} keys", key_count)];
+        for encoder in [&global_encoder, &local_encoder] {
+            // warm up, the global table builds its lookup index on first use
+            encoder.encode(&value).unwrap();
+            let mut total_duration = Duration::new(0, 0);
+            for _ in 0..REPEAT_COUNT {
+                let (_, duration) = measure(|| encoder.encode(&value).unwrap());
+                total_duration += duration;
+            }
+            row.push(format!("{:.1?}", total_duration / REPEAT_COUNT));
+        }
+        rows.push(row);
+    }
+    println!();
+
+    Table { header, rows }
 }
 
 fn measure<T, F: Fn() -> T>(function: F) -> (T, Duration) {
@@ -405,13 +445,10 @@ fn optionally_compress(
         match trained_dict {
             Some(trained_dict) => {
                 let mut compressor =
-                    Compressor::with_dictionary(COMPRESSION_LEVEL, &trained_dict).unwrap();
+                    Compressor::with_dictionary(COMPRESSION_LEVEL, trained_dict).unwrap();
                 compressor.compress(data).unwrap()
             }
-            None => {
-                let compressed = compress(data, COMPRESSION_LEVEL).unwrap();
-                compressed
-            }
+            None => compress(data, COMPRESSION_LEVEL).unwrap(),
         }
     } else {
         data.to_vec()
@@ -451,7 +488,7 @@ fn try_decompress(bytes: &[u8], buffer_size: usize, dict: Option<&Vec<u8>>, atte
 
 fn encode_json(
     value: &Value,
-    _: &String,
+    _: &str,
     compress: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Vec<u8>, Duration) {
@@ -463,22 +500,21 @@ fn encode_json(
 }
 
 fn decode_json(
-    bytes: &Vec<u8>,
-    _: &String,
+    bytes: &[u8],
+    _: &str,
     uses_compression: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Value, Duration) {
     measure(|| {
         let bytes = optionally_decompress(bytes, uses_compression, trained_dict);
         let str = std::str::from_utf8(&bytes).unwrap();
-        let value = serde_json::from_str(str).unwrap();
-        value
+        serde_json::from_str(str).unwrap()
     })
 }
 
 fn encode_smile(
     value: &impl serde::ser::Serialize,
-    _: &String,
+    _: &str,
     compress: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Vec<u8>, Duration) {
@@ -494,8 +530,8 @@ fn encode_smile(
 }
 
 fn decode_smile(
-    bytes: &Vec<u8>,
-    _: &String,
+    bytes: &[u8],
+    _: &str,
     uses_compression: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Value, Duration) {
@@ -507,7 +543,7 @@ fn decode_smile(
 
 fn encode_smile_shared(
     value: &impl serde::ser::Serialize,
-    _: &String,
+    _: &str,
     compress: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Vec<u8>, Duration) {
@@ -524,7 +560,7 @@ fn encode_smile_shared(
 
 fn encode_messagepack(
     value: &Value,
-    _: &String,
+    _: &str,
     compress: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Vec<u8>, Duration) {
@@ -535,8 +571,8 @@ fn encode_messagepack(
 }
 
 fn decode_messagepack(
-    bytes: &Vec<u8>,
-    _: &String,
+    bytes: &[u8],
+    _: &str,
     uses_compression: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Value, Duration) {
@@ -546,14 +582,14 @@ fn decode_messagepack(
     })
 }
 
-// fn encode_ubjson(value: &Value, _: &String, compress: bool, trained_dict: Option<&Vec<u8>>) -> (Vec<u8>, Duration) {
+// fn encode_ubjson(value: &Value, _: &str, compress: bool, trained_dict: Option<&Vec<u8>>) -> (Vec<u8>, Duration) {
 //     measure(|| {
 //         let bytes = serde_ub_json::to_bytes(value).unwrap();
 //         optionally_compress(&bytes, compress, trained_dict)
 //     })
 // }
 
-// fn decode_ubjson(bytes: &Vec<u8>, _: &String, uses_compression: bool, trained_dict: Option<&Vec<u8>>) -> (Value, Duration) {
+// fn decode_ubjson(bytes: &Vec<u8>, _: &str, uses_compression: bool, trained_dict: Option<&Vec<u8>>) -> (Value, Duration) {
 //     measure(|| {
 //         let bytes = optionally_decompress(bytes, uses_compression, trained_dict);
 //         serde_ub_json::from_bytes(&bytes).unwrap()
@@ -562,7 +598,7 @@ fn decode_messagepack(
 
 fn encode_frac_json(
     value: &Value,
-    _: &String,
+    _: &str,
     compress: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Vec<u8>, Duration) {
@@ -573,57 +609,49 @@ fn encode_frac_json(
 }
 
 fn decode_frac_json(
-    bytes: &Vec<u8>,
-    _: &String,
+    bytes: &[u8],
+    _: &str,
     uses_compression: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Value, Duration) {
     measure(|| {
         let bytes = optionally_decompress(bytes, uses_compression, trained_dict);
-        let value = frac_json::decode(&bytes, None, None).unwrap();
-        value
+        frac_json::decode(&bytes, None, None).unwrap()
     })
 }
 
-static mut CACHED_KEYS_TABLES: Option<HashMap<String, Vec<u8>>> = None;
-fn get_cached_keys_tables() -> &'static mut HashMap<String, Vec<u8>> {
-    unsafe {
-        if CACHED_KEYS_TABLES.is_none() {
-            CACHED_KEYS_TABLES = Some(HashMap::new());
-        }
-        CACHED_KEYS_TABLES.as_mut().unwrap()
-    }
+thread_local! {
+    static CACHED_KEYS_TABLES: RefCell<HashMap<String, Vec<u8>>> = RefCell::new(HashMap::new());
 }
 fn encode_frac_json_global_keys_table(
     value: &Value,
-    path: &String,
+    path: &str,
     compress: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Vec<u8>, Duration) {
-    let cached_keys_tables = get_cached_keys_tables();
-    if !cached_keys_tables.contains_key(path) {
-        let keys_table = frac_json::global_table_from_json(value).unwrap();
-        cached_keys_tables.insert(path.clone(), keys_table);
-    }
-    let keys_table_bytes = cached_keys_tables.get(path).unwrap();
+    let keys_table_bytes = CACHED_KEYS_TABLES.with_borrow_mut(|cached_keys_tables| {
+        cached_keys_tables
+            .entry(path.to_string())
+            .or_insert_with(|| frac_json::global_table_from_json(value).unwrap())
+            .clone()
+    });
     measure(|| {
         let bytes =
-            frac_json::encode(value, Some(keys_table_bytes), None, None).unwrap();
+            frac_json::encode(value, Some(&keys_table_bytes), None, None).unwrap();
         optionally_compress(&bytes, compress, trained_dict)
     })
 }
 
 fn decode_frac_json_global_keys_table(
-    bytes: &Vec<u8>,
-    path: &String,
+    bytes: &[u8],
+    path: &str,
     uses_compression: bool,
     trained_dict: Option<&Vec<u8>>,
 ) -> (Value, Duration) {
-    let cached_keys_tables = get_cached_keys_tables();
-    let global_keys_table = cached_keys_tables.get(path).unwrap();
+    let global_keys_table = CACHED_KEYS_TABLES
+        .with_borrow(|cached_keys_tables| cached_keys_tables.get(path).unwrap().clone());
     measure(|| {
         let bytes = optionally_decompress(bytes, uses_compression, trained_dict);
-        let value = frac_json::decode(&bytes, Some(global_keys_table), None).unwrap();
-        value
+        frac_json::decode(&bytes, Some(&global_keys_table), None).unwrap()
     })
 }
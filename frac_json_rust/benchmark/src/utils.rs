@@ -70,10 +70,8 @@ pub fn get_test_files_as_array(file_names: &[&str]) -> Vec<(String, Vec<Value>)>
                 Value::Array(array) => array,
                 Value::Object(object) => {
                     for key in FALLBACK_KEYS {
-                        if let Some(array) = object.get(*key) {
-                            if let Value::Array(array) = array {
-                                return (path, array.clone());
-                            }
+                        if let Some(Value::Array(array)) = object.get(*key) {
+                            return (path, array.clone());
                         }
                     }
                     panic!("Expected array in file {}", path);
@@ -144,7 +142,7 @@ pub struct Table {
 }
 
 impl Table {
-    pub fn to_markdown(self: &Self) -> String {
+    pub fn to_markdown(&self) -> String {
         let mut max_col_widths = vec![0; self.header.len()];
         for row in self.rows.iter().chain(std::iter::once(&self.header)) {
             for (i, cell) in row.iter().enumerate() {
@@ -153,37 +151,38 @@ impl Table {
         }
         let mut table_str = String::new();
         // Header
-        table_str.push_str("|");
+        table_str.push('|');
         for (i, cell) in self.header.iter().enumerate() {
             table_str.push_str(&format!(" {:<1$} |", cell, max_col_widths[i]));
         }
-        table_str.push_str("\n");
+        table_str.push('\n');
         // Separator
-        table_str.push_str("|");
+        table_str.push('|');
         for width in &max_col_widths {
             table_str.push_str(&format!("-{:-<1$}-|", "", width));
         }
-        table_str.push_str("\n");
+        table_str.push('\n');
         // Rows
         for row in &self.rows {
-            table_str.push_str("|");
+            table_str.push('|');
             for (i, cell) in row.iter().enumerate() {
                 table_str.push_str(&format!(" {:<1$} |", cell, max_col_widths[i]));
             }
-            table_str.push_str("\n");
+            table_str.push('\n');
         }
         table_str
     }
 
-    pub fn to_csv(self: &Self) -> String {
+    #[allow(dead_code)]
+    pub fn to_csv(&self) -> String {
         let mut table_str = String::new();
         // Header
         table_str.push_str(&self.header.join(","));
-        table_str.push_str("\n");
+        table_str.push('\n');
         // Rows
         for row in &self.rows {
             table_str.push_str(&row.join(","));
-            table_str.push_str("\n");
+            table_str.push('\n');
         }
         table_str
     }
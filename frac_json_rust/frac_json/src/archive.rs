@@ -0,0 +1,606 @@
+use std::{
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use zstd::bulk::{compress, Decompressor};
+
+use crate::{
+    byte_stream::{ByteReader, ByteWriter, SliceByteReader},
+    codec::{Decoder, Encoder},
+    error::{FracJsonError, Limit},
+    stream::read_full,
+};
+
+const ARCHIVE_MAGIC: &[u8; 2] = b"FA";
+const ARCHIVE_VERSION: u8 = 0;
+const ARCHIVE_HEADER_SIZE: usize = 3;
+/// Config bit of archives whose records are stored in zstd compressed blocks
+const BLOCKS_FLAG: u8 = 0b00010000;
+/// Offset of the index as a little endian u64, followed by the magic bytes
+const TRAILER_SIZE: usize = 10;
+
+/// Writes an archive of frac_json records, which can be read in any order with
+/// `ArchiveReader`.
+///
+/// An archive starts with the magic bytes "FA" and a config byte, followed by the records.
+/// Each record is a complete frac_json file, with its own header. After the last record
+/// the index is written, followed by a trailer holding the offset of the index and the
+/// magic bytes again.
+///
+/// With `with_blocks`, records are grouped into blocks that are compressed as one zstd
+/// frame each. Small records compress much better together, at the cost of decompressing
+/// the whole block to read one of them.
+///
+/// The index is only written by `close`. An archive that is not closed can't be read.
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    encoder: Encoder,
+    /// Number of bytes written to the archive
+    offset: u64,
+    /// Offsets of the records in the archive, or in their decompressed block
+    record_offsets: Vec<u64>,
+    blocks: Option<BlockWriter>,
+}
+
+struct BlockWriter {
+    records_per_block: usize,
+    compression_level: i32,
+    /// Records of the current block
+    buffer: Vec<u8>,
+    buffered_records: usize,
+    entries: Vec<BlockEntry>,
+}
+
+/// Index entry of a compressed block
+#[derive(Debug, Clone, Copy)]
+struct BlockEntry {
+    offset: u64,
+    compressed_size: u32,
+    size: u32,
+    records: u32,
+}
+
+/// Size of a `BlockEntry` in the index
+const BLOCK_ENTRY_SIZE: usize = 20;
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Starts a new archive by writing its header to `writer`. Records are stored as they
+    /// are encoded by `encoder`.
+    pub fn new(writer: W, encoder: Encoder) -> Result<ArchiveWriter<W>, FracJsonError> {
+        return ArchiveWriter::write_header(writer, encoder, None);
+    }
+
+    /// Starts a new archive whose records are compressed in blocks of `records_per_block`
+    /// records. `encoder` can't compress the records itself, an encoder with a
+    /// `compression_level` fails with `InvalidOptions`.
+    pub fn with_blocks(
+        writer: W,
+        encoder: Encoder,
+        records_per_block: usize,
+        compression_level: i32,
+    ) -> Result<ArchiveWriter<W>, FracJsonError> {
+        if records_per_block == 0 {
+            return Err(FracJsonError::InvalidOptions(
+                "records_per_block has to be at least 1".to_string(),
+            ));
+        }
+        if encoder.compression_level().is_some() {
+            return Err(FracJsonError::InvalidOptions(
+                "records of an archive with blocks can't be compressed by the encoder".to_string(),
+            ));
+        }
+        let blocks = BlockWriter {
+            records_per_block,
+            compression_level,
+            buffer: Vec::new(),
+            buffered_records: 0,
+            entries: Vec::new(),
+        };
+        return ArchiveWriter::write_header(writer, encoder, Some(blocks));
+    }
+
+    fn write_header(
+        mut writer: W,
+        encoder: Encoder,
+        blocks: Option<BlockWriter>,
+    ) -> Result<ArchiveWriter<W>, FracJsonError> {
+        let mut config = ARCHIVE_VERSION;
+        if blocks.is_some() {
+            config |= BLOCKS_FLAG;
+        }
+        writer.write_all(ARCHIVE_MAGIC).map_err(FracJsonError::Io)?;
+        writer.write_all(&[config]).map_err(FracJsonError::Io)?;
+        return Ok(ArchiveWriter {
+            writer,
+            encoder,
+            offset: ARCHIVE_HEADER_SIZE as u64,
+            record_offsets: Vec::new(),
+            blocks,
+        });
+    }
+
+    /// Number of records written so far
+    pub fn len(&self) -> usize {
+        self.record_offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.record_offsets.is_empty()
+    }
+
+    /// Encodes `json` and appends it as a record.
+    pub fn write(&mut self, json: &Value) -> Result<(), FracJsonError> {
+        let record = self.encoder.encode(json)?;
+        return self.write_encoded(&record);
+    }
+
+    /// Serializes `value` and appends it as a record, see `Encoder::to_vec`.
+    pub fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        let record = self.encoder.to_vec(value)?;
+        return self.write_encoded(&record);
+    }
+
+    /// Appends an already encoded frac_json file as a record.
+    pub fn write_encoded(&mut self, frac_json_bytes: &[u8]) -> Result<(), FracJsonError> {
+        let Some(blocks) = &mut self.blocks else {
+            self.writer
+                .write_all(frac_json_bytes)
+                .map_err(FracJsonError::Io)?;
+            self.record_offsets.push(self.offset);
+            self.offset += frac_json_bytes.len() as u64;
+            return Ok(());
+        };
+        let size = blocks.buffer.len() + frac_json_bytes.len();
+        if size > u32::MAX as usize {
+            return Err(FracJsonError::LimitExceeded {
+                offset: self.offset as usize,
+                limit: Limit::ValueLength,
+                value: size,
+                max: u32::MAX as usize,
+            });
+        }
+        self.record_offsets.push(blocks.buffer.len() as u64);
+        blocks.buffer.extend_from_slice(frac_json_bytes);
+        blocks.buffered_records += 1;
+        if blocks.buffered_records == blocks.records_per_block {
+            self.write_block()?;
+        }
+        return Ok(());
+    }
+
+    /// Compresses and writes the records of the current block.
+    fn write_block(&mut self) -> Result<(), FracJsonError> {
+        let Some(blocks) = &mut self.blocks else {
+            return Ok(());
+        };
+        if blocks.buffered_records == 0 {
+            return Ok(());
+        }
+        let compressed = compress(&blocks.buffer, blocks.compression_level)
+            .map_err(FracJsonError::Compression)?;
+        let Ok(compressed_size) = u32::try_from(compressed.len()) else {
+            return Err(FracJsonError::LimitExceeded {
+                offset: self.offset as usize,
+                limit: Limit::ValueLength,
+                value: compressed.len(),
+                max: u32::MAX as usize,
+            });
+        };
+        self.writer
+            .write_all(&compressed)
+            .map_err(FracJsonError::Io)?;
+        blocks.entries.push(BlockEntry {
+            offset: self.offset,
+            compressed_size,
+            size: blocks.buffer.len() as u32,
+            records: blocks.buffered_records as u32,
+        });
+        self.offset += compressed.len() as u64;
+        blocks.buffer.clear();
+        blocks.buffered_records = 0;
+        return Ok(());
+    }
+
+    /// Writes the last block, the index and the trailer. Returns the flushed writer.
+    pub fn close(mut self) -> Result<W, FracJsonError> {
+        self.write_block()?;
+        let record_count = self.record_offsets.len();
+        if record_count > u32::MAX as usize {
+            return Err(FracJsonError::LimitExceeded {
+                offset: self.offset as usize,
+                limit: Limit::ContainerLength,
+                value: record_count,
+                max: u32::MAX as usize,
+            });
+        }
+        let mut index = Vec::new();
+        index.write_u32(record_count as u32)?;
+        match &self.blocks {
+            Some(blocks) => {
+                index.write_u32(blocks.entries.len() as u32)?;
+                for entry in &blocks.entries {
+                    index.write_u64(entry.offset)?;
+                    index.write_u32(entry.compressed_size)?;
+                    index.write_u32(entry.size)?;
+                    index.write_u32(entry.records)?;
+                }
+                for &offset in &self.record_offsets {
+                    index.write_u32(offset as u32)?;
+                }
+            }
+            None => {
+                for &offset in &self.record_offsets {
+                    index.write_u64(offset)?;
+                }
+            }
+        }
+        index.write_u64(self.offset)?;
+        index.extend_from_slice(ARCHIVE_MAGIC);
+        self.writer.write_all(&index).map_err(FracJsonError::Io)?;
+        self.writer.flush().map_err(FracJsonError::Io)?;
+        return Ok(self.writer);
+    }
+}
+
+/// Reads the records of an archive written by `ArchiveWriter` by their record number.
+///
+/// Only the header and the index are read when the archive is opened. For block archives
+/// the last decompressed block is kept, so reading records in order decompresses each
+/// block once.
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    decoder: Decoder,
+    index: ArchiveIndex,
+    /// Last record read, or the decompressed block containing it
+    buffer: Vec<u8>,
+    /// Block currently in `buffer`
+    buffered_block: Option<usize>,
+    /// Position of the last record read in `buffer`
+    record: Range<usize>,
+}
+
+enum ArchiveIndex {
+    Records {
+        offsets: Vec<u64>,
+        /// End of the last record, where the index starts
+        end: u64,
+    },
+    Blocks {
+        entries: Vec<BlockEntry>,
+        /// Number of the first record of each block
+        first_records: Vec<usize>,
+        /// Offsets of the records in their decompressed block
+        offsets: Vec<u32>,
+    },
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Reads the header and the index of the archive in `reader`.
+    pub fn new(mut reader: R, decoder: Decoder) -> Result<ArchiveReader<R>, FracJsonError> {
+        let mut header = [0u8; ARCHIVE_HEADER_SIZE];
+        read_at(&mut reader, 0, &mut header)?;
+        if header[..2] != *ARCHIVE_MAGIC {
+            return Err(FracJsonError::InvalidMagic {
+                offset: 0,
+                magic: [header[0], header[1]],
+            });
+        }
+        let version = header[2] & 0b00001111;
+        if version > ARCHIVE_VERSION {
+            return Err(FracJsonError::UnsupportedVersion { offset: 2, version });
+        }
+        let has_blocks = header[2] & BLOCKS_FLAG != 0;
+
+        let size = reader.seek(SeekFrom::End(0)).map_err(FracJsonError::Io)?;
+        if size < (ARCHIVE_HEADER_SIZE + TRAILER_SIZE) as u64 {
+            return Err(FracJsonError::UnexpectedEof {
+                offset: ARCHIVE_HEADER_SIZE,
+                needed: TRAILER_SIZE,
+                available: size as usize - ARCHIVE_HEADER_SIZE,
+            });
+        }
+        let trailer_offset = size - TRAILER_SIZE as u64;
+        let mut trailer = [0u8; TRAILER_SIZE];
+        read_at(&mut reader, trailer_offset, &mut trailer)?;
+        if trailer[8..] != *ARCHIVE_MAGIC {
+            return Err(FracJsonError::InvalidMagic {
+                offset: trailer_offset as usize + 8,
+                magic: [trailer[8], trailer[9]],
+            });
+        }
+        let index_offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        if index_offset < ARCHIVE_HEADER_SIZE as u64 || index_offset > trailer_offset {
+            return Err(FracJsonError::InvalidArchiveIndex {
+                offset: trailer_offset as usize,
+            });
+        }
+        let mut index_bytes = vec![0u8; (trailer_offset - index_offset) as usize];
+        read_at(&mut reader, index_offset, &mut index_bytes)?;
+        let mut bytes = SliceByteReader::make_at(&index_bytes, index_offset as usize);
+        let index = if has_blocks {
+            ArchiveIndex::read_blocks(
+                &mut bytes,
+                index_offset,
+                decoder.limits().max_decompressed_size,
+            )?
+        } else {
+            ArchiveIndex::read_records(&mut bytes, index_offset)?
+        };
+        if bytes.remaining() != 0 {
+            return Err(FracJsonError::InvalidArchiveIndex {
+                offset: bytes.offset(),
+            });
+        }
+        return Ok(ArchiveReader {
+            reader,
+            decoder,
+            index,
+            buffer: Vec::new(),
+            buffered_block: None,
+            record: 0..0,
+        });
+    }
+
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Number of records in the archive
+    pub fn len(&self) -> usize {
+        match &self.index {
+            ArchiveIndex::Records { offsets, .. } => offsets.len(),
+            ArchiveIndex::Blocks { offsets, .. } => offsets.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of record `n`, a complete frac_json file, without decoding it. Returns `None`
+    /// if there is no such record.
+    pub fn get_record(&mut self, n: usize) -> Result<Option<&[u8]>, FracJsonError> {
+        if !self.read_record(n)? {
+            return Ok(None);
+        }
+        return Ok(Some(&self.buffer[self.record.clone()]));
+    }
+
+    /// Decodes record `n` into a `Value`.
+    pub fn get(&mut self, n: usize) -> Result<Option<Value>, FracJsonError> {
+        if !self.read_record(n)? {
+            return Ok(None);
+        }
+        return Ok(Some(
+            self.decoder.decode(&self.buffer[self.record.clone()])?,
+        ));
+    }
+
+    /// Deserializes record `n` into a `T`, see `Decoder::from_slice`.
+    pub fn get_deserialized<T: DeserializeOwned>(
+        &mut self,
+        n: usize,
+    ) -> Result<Option<T>, FracJsonError> {
+        if !self.read_record(n)? {
+            return Ok(None);
+        }
+        return Ok(Some(
+            self.decoder.from_slice(&self.buffer[self.record.clone()])?,
+        ));
+    }
+
+    /// Iterates over the decoded records with numbers in `range`, which is clamped to the
+    /// records in the archive.
+    pub fn range(&mut self, range: Range<usize>) -> ArchiveRecords<'_, R> {
+        let end = range.end.min(self.len());
+        ArchiveRecords {
+            archive: self,
+            next: range.start,
+            end,
+        }
+    }
+
+    /// Iterates over all decoded records.
+    pub fn iter(&mut self) -> ArchiveRecords<'_, R> {
+        self.range(0..usize::MAX)
+    }
+
+    /// Loads record `n` into `self.buffer`. Returns false if there is no such record.
+    fn read_record(&mut self, n: usize) -> Result<bool, FracJsonError> {
+        if n >= self.len() {
+            return Ok(false);
+        }
+        match &self.index {
+            ArchiveIndex::Records { offsets, end } => {
+                let start = offsets[n];
+                let end = offsets.get(n + 1).copied().unwrap_or(*end);
+                self.buffer.resize((end - start) as usize, 0);
+                self.buffered_block = None;
+                read_at(&mut self.reader, start, &mut self.buffer)?;
+                self.record = 0..self.buffer.len();
+            }
+            ArchiveIndex::Blocks {
+                entries,
+                first_records,
+                offsets,
+            } => {
+                let block = first_records.partition_point(|&first| first <= n) - 1;
+                let entry = entries[block];
+                let last_record = first_records[block] + entry.records as usize - 1;
+                let start = offsets[n] as usize;
+                let end = if n < last_record {
+                    offsets[n + 1] as usize
+                } else {
+                    entry.size as usize
+                };
+                if self.buffered_block != Some(block) {
+                    self.buffered_block = None;
+                    self.buffer = read_block(&mut self.reader, &entry)?;
+                    self.buffered_block = Some(block);
+                }
+                self.record = start..end;
+            }
+        }
+        return Ok(true);
+    }
+}
+
+impl ArchiveIndex {
+    fn read_records(
+        bytes: &mut SliceByteReader,
+        index_offset: u64,
+    ) -> Result<ArchiveIndex, FracJsonError> {
+        let count = bytes.read_u32()? as usize;
+        let mut offsets = Vec::with_capacity(bytes.capacity_hint(count, 8));
+        let mut previous = ARCHIVE_HEADER_SIZE as u64;
+        for _ in 0..count {
+            let entry_offset = bytes.offset();
+            let offset = bytes.read_u64()?;
+            if offset < previous || offset > index_offset {
+                return Err(FracJsonError::InvalidArchiveIndex {
+                    offset: entry_offset,
+                });
+            }
+            offsets.push(offset);
+            previous = offset;
+        }
+        return Ok(ArchiveIndex::Records {
+            offsets,
+            end: index_offset,
+        });
+    }
+
+    fn read_blocks(
+        bytes: &mut SliceByteReader,
+        index_offset: u64,
+        max_block_size: usize,
+    ) -> Result<ArchiveIndex, FracJsonError> {
+        let record_count = bytes.read_u32()? as usize;
+        let block_count = bytes.read_u32()? as usize;
+        let mut entries = Vec::with_capacity(bytes.capacity_hint(block_count, BLOCK_ENTRY_SIZE));
+        let mut first_records = Vec::with_capacity(entries.capacity());
+        let mut previous_end = ARCHIVE_HEADER_SIZE as u64;
+        let mut records = 0;
+        for _ in 0..block_count {
+            let entry_offset = bytes.offset();
+            let entry = BlockEntry {
+                offset: bytes.read_u64()?,
+                compressed_size: bytes.read_u32()?,
+                size: bytes.read_u32()?,
+                records: bytes.read_u32()?,
+            };
+            let end = entry.offset.saturating_add(entry.compressed_size as u64);
+            if entry.offset < previous_end || end > index_offset || entry.records == 0 {
+                return Err(FracJsonError::InvalidArchiveIndex {
+                    offset: entry_offset,
+                });
+            }
+            if entry.size as usize > max_block_size {
+                return Err(FracJsonError::LimitExceeded {
+                    offset: entry_offset,
+                    limit: Limit::DecompressedSize,
+                    value: entry.size as usize,
+                    max: max_block_size,
+                });
+            }
+            entries.push(entry);
+            first_records.push(records);
+            previous_end = end;
+            records += entry.records as usize;
+        }
+        if records != record_count {
+            return Err(FracJsonError::InvalidArchiveIndex {
+                offset: bytes.offset(),
+            });
+        }
+        let mut offsets = Vec::with_capacity(bytes.capacity_hint(record_count, 4));
+        for entry in &entries {
+            let mut previous = 0;
+            for i in 0..entry.records {
+                let entry_offset = bytes.offset();
+                let offset = bytes.read_u32()?;
+                let is_valid = match i {
+                    0 => offset == 0,
+                    _ => offset >= previous && offset <= entry.size,
+                };
+                if !is_valid {
+                    return Err(FracJsonError::InvalidArchiveIndex {
+                        offset: entry_offset,
+                    });
+                }
+                offsets.push(offset);
+                previous = offset;
+            }
+        }
+        return Ok(ArchiveIndex::Blocks {
+            entries,
+            first_records,
+            offsets,
+        });
+    }
+}
+
+/// Iterator over decoded records of an archive, see `ArchiveReader::range`.
+pub struct ArchiveRecords<'r, R: Read + Seek> {
+    archive: &'r mut ArchiveReader<R>,
+    next: usize,
+    end: usize,
+}
+
+impl<R: Read + Seek> Iterator for ArchiveRecords<'_, R> {
+    type Item = Result<Value, FracJsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let n = self.next;
+        self.next += 1;
+        self.archive.get(n).transpose()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.end.saturating_sub(self.next);
+        (remaining, Some(remaining))
+    }
+}
+
+/// Reads `buf.len()` bytes at `offset`.
+fn read_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<(), FracJsonError> {
+    reader
+        .seek(SeekFrom::Start(offset))
+        .map_err(FracJsonError::Io)?;
+    let available = read_full(reader, buf)?;
+    if available < buf.len() {
+        return Err(FracJsonError::UnexpectedEof {
+            offset: offset as usize,
+            needed: buf.len(),
+            available,
+        });
+    }
+    return Ok(());
+}
+
+fn read_block<R: Read + Seek>(
+    reader: &mut R,
+    entry: &BlockEntry,
+) -> Result<Vec<u8>, FracJsonError> {
+    let mut compressed = vec![0u8; entry.compressed_size as usize];
+    read_at(reader, entry.offset, &mut compressed)?;
+    let block = Decompressor::new()
+        .and_then(|mut decompressor| decompressor.decompress(&compressed, entry.size as usize))
+        .map_err(FracJsonError::Compression)?;
+    if block.len() != entry.size as usize {
+        return Err(FracJsonError::InvalidArchiveIndex {
+            offset: entry.offset as usize,
+        });
+    }
+    return Ok(block);
+}
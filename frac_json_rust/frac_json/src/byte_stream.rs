@@ -1,202 +1,650 @@
-// LE
-pub struct ByteReader<'a> {
-    bytes: &'a Vec<u8>,
-    pos: usize,
+use std::{
+    borrow::Cow,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+};
+
+use crate::{
+    error::{FracJsonError, Limit},
+    options::{DecodeLimits, Utf8Validation},
+};
+
+/// Decoding state shared by all ByteReader implementations.
+pub struct DecodeState {
+    utf8_validation: Utf8Validation,
+    limits: DecodeLimits,
+    depth: usize,
+    total_elements: usize,
 }
 
-impl<'a> ByteReader<'a> {
-    pub fn make(items: &Vec<u8>) -> ByteReader {
-        ByteReader {
-            bytes: items,
-            pos: 0,
+impl DecodeState {
+    fn new() -> DecodeState {
+        DecodeState {
+            utf8_validation: Utf8Validation::Strict,
+            limits: DecodeLimits::default(),
+            depth: 0,
+            total_elements: 0,
+        }
+    }
+
+    fn str_from_slice<'b>(
+        &self,
+        bytes: &'b [u8],
+        offset: usize,
+    ) -> Result<Cow<'b, str>, FracJsonError> {
+        let string = match self.utf8_validation {
+            Utf8Validation::Strict => Cow::Borrowed(
+                simdutf8::basic::from_utf8(bytes)
+                    .map_err(|_| FracJsonError::InvalidUtf8 { offset })?,
+            ),
+            Utf8Validation::Lossy => String::from_utf8_lossy(bytes),
+            // SAFETY: only reachable through the unsafe Utf8Validation::trusted()
+            Utf8Validation::Trusted(_) => {
+                Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(bytes) })
+            }
+        };
+        return Ok(string);
+    }
+
+    fn string_from_vec(&self, bytes: Vec<u8>, offset: usize) -> Result<String, FracJsonError> {
+        let string = match self.utf8_validation {
+            Utf8Validation::Strict => {
+                simdutf8::basic::from_utf8(&bytes)
+                    .map_err(|_| FracJsonError::InvalidUtf8 { offset })?;
+                // SAFETY: validated above
+                unsafe { String::from_utf8_unchecked(bytes) }
+            }
+            Utf8Validation::Lossy => match String::from_utf8(bytes) {
+                Ok(string) => string,
+                Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+            },
+            // SAFETY: only reachable through the unsafe Utf8Validation::trusted()
+            Utf8Validation::Trusted(_) => unsafe { String::from_utf8_unchecked(bytes) },
+        };
+        return Ok(string);
+    }
+}
+
+// LE
+pub trait ByteReader {
+    /// Offset of the next byte to be read, used for error reporting.
+    fn offset(&self) -> usize;
+    fn state(&self) -> &DecodeState;
+    fn state_mut(&mut self) -> &mut DecodeState;
+    /// Fills `buf` completely or fails with `UnexpectedEof`.
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<(), FracJsonError>;
+    fn read_string(&mut self, count: usize) -> Result<String, FracJsonError>;
+    /// Advances past `count` bytes without validating them.
+    fn skip(&mut self, count: usize) -> Result<(), FracJsonError>;
+    /// Number of entries to preallocate for a container claiming `length` entries of at
+    /// least `min_entry_size` bytes each. Length prefixes are untrusted, so this should
+    /// not exceed what the remaining data can hold.
+    fn capacity_hint(&self, length: usize, min_entry_size: usize) -> usize;
+
+    fn with_utf8_validation(mut self, utf8_validation: Utf8Validation) -> Self
+    where
+        Self: Sized,
+    {
+        self.state_mut().utf8_validation = utf8_validation;
+        self
+    }
+
+    fn with_limits(mut self, limits: DecodeLimits) -> Self
+    where
+        Self: Sized,
+    {
+        self.state_mut().limits = limits;
+        self
+    }
+
+    fn limit_exceeded(&self, limit: Limit, value: usize, max: usize) -> FracJsonError {
+        FracJsonError::LimitExceeded {
+            offset: self.offset(),
+            limit,
+            value,
+            max,
         }
     }
 
-    fn check_read_will_error(&self, count: usize) -> Result<(), String> {
-        if self.pos + count > self.bytes.len() {
-            let error_str = format!(
-                "Cannot read {} items from ByteStream! Only {} items left",
-                count,
-                self.bytes.len()
-            );
-            return Err(error_str);
+    /// Checks the limits for an object or array with `length` entries and increases the
+    /// nesting depth. Must be paired with `exit_container`.
+    fn enter_container(&mut self, length: usize) -> Result<(), FracJsonError> {
+        let state = self.state();
+        if length > state.limits.max_container_length {
+            return Err(self.limit_exceeded(
+                Limit::ContainerLength,
+                length,
+                state.limits.max_container_length,
+            ));
+        }
+        let total_elements = state.total_elements.saturating_add(length);
+        if total_elements > state.limits.max_total_elements {
+            return Err(self.limit_exceeded(
+                Limit::TotalElements,
+                total_elements,
+                state.limits.max_total_elements,
+            ));
         }
+        if state.depth >= state.limits.max_depth {
+            return Err(self.limit_exceeded(Limit::Depth, state.depth + 1, state.limits.max_depth));
+        }
+        let state = self.state_mut();
+        state.total_elements = total_elements;
+        state.depth += 1;
         return Ok(());
     }
 
-    pub fn read(&mut self, count: usize) -> Result<&[u8], String> {
-        self.check_read_will_error(count)?;
-        let slice = &self.bytes[self.pos..self.pos + count];
-        self.pos += count;
-        return Ok(slice);
+    fn exit_container(&mut self) {
+        self.state_mut().depth -= 1;
     }
 
-    pub fn read_remaining(&mut self) -> Result<&[u8], String> {
-        let slice = &self.bytes[self.pos..];
-        self.pos = self.bytes.len();
-        return Ok(slice);
+    fn check_string_length(&self, length: usize) -> Result<(), FracJsonError> {
+        let max_string_length = self.state().limits.max_string_length;
+        if length > max_string_length {
+            return Err(self.limit_exceeded(Limit::StringLength, length, max_string_length));
+        }
+        return Ok(());
     }
 
-    pub fn read1(&mut self) -> Result<[u8; 1], String> {
-        self.check_read_will_error(1)?;
-        let slice = [self.bytes[self.pos]];
-        self.pos += 1;
-        return Ok(slice);
+    fn read1(&mut self) -> Result<[u8; 1], FracJsonError> {
+        let mut buf = [0; 1];
+        self.read_into(&mut buf)?;
+        return Ok(buf);
     }
 
-    pub fn read2(&mut self) -> Result<[u8; 2], String> {
-        self.check_read_will_error(2)?;
-        let slice = [self.bytes[self.pos], self.bytes[self.pos + 1]];
-        self.pos += 2;
-        return Ok(slice);
+    fn read2(&mut self) -> Result<[u8; 2], FracJsonError> {
+        let mut buf = [0; 2];
+        self.read_into(&mut buf)?;
+        return Ok(buf);
     }
 
-    pub fn read4(&mut self) -> Result<[u8; 4], String> {
-        self.check_read_will_error(4)?;
-        let slice = [
-            self.bytes[self.pos],
-            self.bytes[self.pos + 1],
-            self.bytes[self.pos + 2],
-            self.bytes[self.pos + 3],
-        ];
-        self.pos += 4;
-        return Ok(slice);
+    fn read4(&mut self) -> Result<[u8; 4], FracJsonError> {
+        let mut buf = [0; 4];
+        self.read_into(&mut buf)?;
+        return Ok(buf);
     }
 
-    pub fn read8(&mut self) -> Result<[u8; 8], String> {
-        self.check_read_will_error(8)?;
-        let slice = [
-            self.bytes[self.pos + 0],
-            self.bytes[self.pos + 1],
-            self.bytes[self.pos + 2],
-            self.bytes[self.pos + 3],
-            self.bytes[self.pos + 4],
-            self.bytes[self.pos + 5],
-            self.bytes[self.pos + 6],
-            self.bytes[self.pos + 7],
-        ];
-        self.pos += 8;
-        return Ok(slice);
+    fn read8(&mut self) -> Result<[u8; 8], FracJsonError> {
+        let mut buf = [0; 8];
+        self.read_into(&mut buf)?;
+        return Ok(buf);
     }
 
-    pub fn read_u8(&mut self) -> Result<u8, String> {
+    fn read_u8(&mut self) -> Result<u8, FracJsonError> {
         return Ok(u8::from_le_bytes(self.read1()?));
     }
 
-    pub fn read_i8(&mut self) -> Result<i8, String> {
+    fn read_i8(&mut self) -> Result<i8, FracJsonError> {
         return Ok(i8::from_le_bytes(self.read1()?));
     }
 
-    pub fn read_u16(&mut self) -> Result<u16, String> {
+    fn read_u16(&mut self) -> Result<u16, FracJsonError> {
         return Ok(u16::from_le_bytes(self.read2()?));
     }
 
-    pub fn read_i16(&mut self) -> Result<i16, String> {
+    fn read_i16(&mut self) -> Result<i16, FracJsonError> {
         return Ok(i16::from_le_bytes(self.read2()?));
     }
 
-    pub fn read_u32(&mut self) -> Result<u32, String> {
+    fn read_u32(&mut self) -> Result<u32, FracJsonError> {
         return Ok(u32::from_le_bytes(self.read4()?));
     }
 
-    pub fn read_i32(&mut self) -> Result<i32, String> {
+    fn read_i32(&mut self) -> Result<i32, FracJsonError> {
         return Ok(i32::from_le_bytes(self.read4()?));
     }
 
-    pub fn read_u64(&mut self) -> Result<u64, String> {
+    fn read_u64(&mut self) -> Result<u64, FracJsonError> {
         return Ok(u64::from_le_bytes(self.read8()?));
     }
 
-    pub fn read_i64(&mut self) -> Result<i64, String> {
+    fn read_i64(&mut self) -> Result<i64, FracJsonError> {
         return Ok(i64::from_le_bytes(self.read8()?));
     }
 
-    pub fn read_f32(&mut self) -> Result<f32, String> {
+    fn read_f32(&mut self) -> Result<f32, FracJsonError> {
         return Ok(f32::from_le_bytes(self.read4()?));
     }
 
-    pub fn read_f64(&mut self) -> Result<f64, String> {
+    fn read_f64(&mut self) -> Result<f64, FracJsonError> {
         return Ok(f64::from_le_bytes(self.read8()?));
     }
+}
+
+/// ByteReader over an in-memory slice.
+pub struct SliceByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    base_offset: usize,
+    state: DecodeState,
+}
+
+impl<'a> SliceByteReader<'a> {
+    pub fn make(items: &'a [u8]) -> SliceByteReader<'a> {
+        SliceByteReader::make_at(items, 0)
+    }
+
+    /// Reader whose reported offsets start at `base_offset` instead of 0.
+    pub fn make_at(items: &'a [u8], base_offset: usize) -> SliceByteReader<'a> {
+        SliceByteReader {
+            bytes: items,
+            pos: 0,
+            base_offset,
+            state: DecodeState::new(),
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn check_read_will_error(&self, count: usize) -> Result<(), FracJsonError> {
+        let available = self.remaining();
+        if count > available {
+            return Err(FracJsonError::UnexpectedEof {
+                offset: self.offset(),
+                needed: count,
+                available,
+            });
+        }
+        return Ok(());
+    }
 
-    pub fn read_string(&mut self, count: usize) -> Result<String, String> {
+    pub fn read(&mut self, count: usize) -> Result<&'a [u8], FracJsonError> {
+        self.check_read_will_error(count)?;
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        return Ok(slice);
+    }
+
+    /// Reads a string that borrows from the underlying bytes when they are valid UTF-8.
+    pub fn read_str(&mut self, count: usize) -> Result<Cow<'a, str>, FracJsonError> {
+        let offset = self.offset();
         let slice = self.read(count)?;
-        let string = unsafe { String::from_utf8_unchecked(slice.to_vec()) };
-        return Ok(string);
+        return self.state.str_from_slice(slice, offset);
+    }
+
+    pub fn read_remaining(&mut self) -> Result<&'a [u8], FracJsonError> {
+        let slice = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        return Ok(slice);
+    }
+}
+
+impl<'a> ByteReader for SliceByteReader<'a> {
+    fn offset(&self) -> usize {
+        self.base_offset + self.pos
+    }
+
+    fn state(&self) -> &DecodeState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut DecodeState {
+        &mut self.state
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<(), FracJsonError> {
+        buf.copy_from_slice(self.read(buf.len())?);
+        return Ok(());
+    }
+
+    fn read_string(&mut self, count: usize) -> Result<String, FracJsonError> {
+        let offset = self.offset();
+        let slice = self.read(count)?;
+        return Ok(self.state.str_from_slice(slice, offset)?.into_owned());
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), FracJsonError> {
+        self.read(count)?;
+        return Ok(());
+    }
+
+    fn capacity_hint(&self, length: usize, min_entry_size: usize) -> usize {
+        length.min(self.remaining() / min_entry_size)
+    }
+}
+
+/// ByteReader that can return strings borrowed from input that lives for `'de`.
+pub trait StrRead<'de>: ByteReader {
+    fn read_str(&mut self, count: usize) -> Result<Cow<'de, str>, FracJsonError>;
+}
+
+impl<'de> StrRead<'de> for SliceByteReader<'de> {
+    fn read_str(&mut self, count: usize) -> Result<Cow<'de, str>, FracJsonError> {
+        SliceByteReader::read_str(self, count)
+    }
+}
+
+/// Wraps a ByteReader whose strings can't be borrowed for `'de`, such as one over
+/// decompressed bytes or a stream.
+pub struct OwnedStrings<R: ByteReader>(pub R);
+
+impl<R: ByteReader> ByteReader for OwnedStrings<R> {
+    fn offset(&self) -> usize {
+        self.0.offset()
+    }
+
+    fn state(&self) -> &DecodeState {
+        self.0.state()
+    }
+
+    fn state_mut(&mut self) -> &mut DecodeState {
+        self.0.state_mut()
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<(), FracJsonError> {
+        self.0.read_into(buf)
+    }
+
+    fn read_string(&mut self, count: usize) -> Result<String, FracJsonError> {
+        self.0.read_string(count)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), FracJsonError> {
+        self.0.skip(count)
+    }
+
+    fn capacity_hint(&self, length: usize, min_entry_size: usize) -> usize {
+        self.0.capacity_hint(length, min_entry_size)
+    }
+}
+
+impl<'de, R: ByteReader> StrRead<'de> for OwnedStrings<R> {
+    fn read_str(&mut self, count: usize) -> Result<Cow<'de, str>, FracJsonError> {
+        Ok(Cow::Owned(self.0.read_string(count)?))
+    }
+}
+
+/// Buffered ByteReader over any `io::Read`.
+pub struct IoByteReader<R: Read> {
+    reader: BufReader<R>,
+    pos: usize,
+    base_offset: usize,
+    /// Maximum number of bytes that may be read, reported as exceeding `Limit::DecompressedSize`
+    max_size: usize,
+    state: DecodeState,
+}
+
+/// Upper bound for preallocating containers, since the remaining size of a stream is unknown.
+const IO_MAX_CAPACITY_HINT: usize = 1024;
+
+impl<R: Read> IoByteReader<R> {
+    pub fn make(reader: R) -> IoByteReader<R> {
+        IoByteReader::make_at(reader, 0)
+    }
+
+    /// Reader whose reported offsets start at `base_offset` instead of 0.
+    pub fn make_at(reader: R, base_offset: usize) -> IoByteReader<R> {
+        IoByteReader {
+            reader: BufReader::new(reader),
+            pos: 0,
+            base_offset,
+            max_size: usize::MAX,
+            state: DecodeState::new(),
+        }
+    }
+
+    /// Fails with `Limit::DecompressedSize` when more than `max_size` bytes are read.
+    pub fn with_max_size(mut self, max_size: usize) -> IoByteReader<R> {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the underlying reader, including bytes that are buffered but not read yet.
+    pub fn into_inner(self) -> BufReader<R> {
+        self.reader
+    }
+
+    fn check_max_size(&self, count: usize) -> Result<(), FracJsonError> {
+        let size = self.pos.saturating_add(count);
+        if size > self.max_size {
+            return Err(self.limit_exceeded(Limit::DecompressedSize, size, self.max_size));
+        }
+        return Ok(());
+    }
+
+    fn eof_error(&self, needed: usize, available: usize) -> FracJsonError {
+        FracJsonError::UnexpectedEof {
+            offset: self.offset(),
+            needed,
+            available,
+        }
+    }
+}
+
+impl<R: Read> ByteReader for IoByteReader<R> {
+    fn offset(&self) -> usize {
+        self.base_offset + self.pos
+    }
+
+    fn state(&self) -> &DecodeState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut DecodeState {
+        &mut self.state
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<(), FracJsonError> {
+        self.check_max_size(buf.len())?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => return Err(self.eof_error(buf.len(), filled)),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(FracJsonError::Io(err)),
+            }
+        }
+        self.pos += filled;
+        return Ok(());
+    }
+
+    fn read_string(&mut self, count: usize) -> Result<String, FracJsonError> {
+        self.check_max_size(count)?;
+        let offset = self.offset();
+        // grow the buffer while reading, instead of trusting the length prefix
+        let mut bytes = Vec::with_capacity(count.min(8 * 1024));
+        (&mut self.reader)
+            .take(count as u64)
+            .read_to_end(&mut bytes)
+            .map_err(FracJsonError::Io)?;
+        if bytes.len() < count {
+            return Err(self.eof_error(count, bytes.len()));
+        }
+        self.pos += count;
+        return self.state.string_from_vec(bytes, offset);
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), FracJsonError> {
+        self.check_max_size(count)?;
+        let skipped = io::copy(&mut (&mut self.reader).take(count as u64), &mut io::sink())
+            .map_err(FracJsonError::Io)? as usize;
+        if skipped < count {
+            return Err(self.eof_error(count, skipped));
+        }
+        self.pos += count;
+        return Ok(());
+    }
+
+    fn capacity_hint(&self, length: usize, _min_entry_size: usize) -> usize {
+        length.min(IO_MAX_CAPACITY_HINT)
     }
 }
 
 pub trait ByteWriter {
-    fn write(&mut self, bytes: &[u8]);
-    fn write_u8(&mut self, value: u8);
-    fn write_i8(&mut self, value: i8);
-    fn write_u16(&mut self, value: u16);
-    fn write_i16(&mut self, value: i16);
-    fn write_u32(&mut self, value: u32);
-    fn write_i32(&mut self, value: i32);
-    fn write_u64(&mut self, value: u64);
-    fn write_i64(&mut self, value: i64);
-    fn write_f32(&mut self, value: f32);
-    fn write_f64(&mut self, value: f64);
-    fn write_string(&mut self, value: &str);
+    /// Number of bytes written so far, used for error reporting.
+    fn position(&self) -> usize;
+    fn write(&mut self, bytes: &[u8]) -> Result<(), FracJsonError>;
+    fn write_u8(&mut self, value: u8) -> Result<(), FracJsonError>;
+    fn write_i8(&mut self, value: i8) -> Result<(), FracJsonError>;
+    fn write_u16(&mut self, value: u16) -> Result<(), FracJsonError>;
+    fn write_i16(&mut self, value: i16) -> Result<(), FracJsonError>;
+    fn write_u32(&mut self, value: u32) -> Result<(), FracJsonError>;
+    fn write_i32(&mut self, value: i32) -> Result<(), FracJsonError>;
+    fn write_u64(&mut self, value: u64) -> Result<(), FracJsonError>;
+    fn write_i64(&mut self, value: i64) -> Result<(), FracJsonError>;
+    fn write_f32(&mut self, value: f32) -> Result<(), FracJsonError>;
+    fn write_f64(&mut self, value: f64) -> Result<(), FracJsonError>;
+    fn write_string(&mut self, value: &str) -> Result<(), FracJsonError>;
 }
 
 impl ByteWriter for Vec<u8> {
-    fn write(&mut self, bytes: &[u8]) {
+    fn position(&self) -> usize {
+        self.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), FracJsonError> {
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_u8(&mut self, value: u8) {
+    fn write_u8(&mut self, value: u8) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_i8(&mut self, value: i8) {
+    fn write_i8(&mut self, value: i8) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_u16(&mut self, value: u16) {
+    fn write_u16(&mut self, value: u16) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_i16(&mut self, value: i16) {
+    fn write_i16(&mut self, value: i16) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_u32(&mut self, value: u32) {
+    fn write_u32(&mut self, value: u32) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_i32(&mut self, value: i32) {
+    fn write_i32(&mut self, value: i32) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_u64(&mut self, value: u64) {
+    fn write_u64(&mut self, value: u64) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_i64(&mut self, value: i64) {
+    fn write_i64(&mut self, value: i64) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_f32(&mut self, value: f32) {
+    fn write_f32(&mut self, value: f32) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_f64(&mut self, value: f64) {
+    fn write_f64(&mut self, value: f64) -> Result<(), FracJsonError> {
         let bytes = value.to_le_bytes();
         self.extend(bytes);
+        return Ok(());
     }
 
-    fn write_string(&mut self, value: &str) {
+    fn write_string(&mut self, value: &str) -> Result<(), FracJsonError> {
         self.extend(value.as_bytes());
+        return Ok(());
+    }
+}
+
+/// Buffered ByteWriter over any `io::Write`.
+pub struct IoByteWriter<W: Write> {
+    writer: BufWriter<W>,
+    position: usize,
+}
+
+impl<W: Write> IoByteWriter<W> {
+    pub fn make(writer: W) -> IoByteWriter<W> {
+        IoByteWriter::make_at(writer, 0)
+    }
+
+    /// Writer whose reported positions start at `position` instead of 0.
+    pub fn make_at(writer: W, position: usize) -> IoByteWriter<W> {
+        IoByteWriter {
+            writer: BufWriter::new(writer),
+            position,
+        }
+    }
+
+    /// Flushes all buffered bytes and returns the underlying writer.
+    pub fn finish(self) -> Result<W, FracJsonError> {
+        return self
+            .writer
+            .into_inner()
+            .map_err(|err| FracJsonError::Io(err.into_error()));
+    }
+}
+
+impl<W: Write> ByteWriter for IoByteWriter<W> {
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), FracJsonError> {
+        self.writer.write_all(bytes).map_err(FracJsonError::Io)?;
+        self.position += bytes.len();
+        return Ok(());
+    }
+
+    fn write_u8(&mut self, value: u8) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_i8(&mut self, value: i8) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_i16(&mut self, value: i16) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, value: i32) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_i64(&mut self, value: i64) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_f32(&mut self, value: f32) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_f64(&mut self, value: f64) -> Result<(), FracJsonError> {
+        return self.write(&value.to_le_bytes());
+    }
+
+    fn write_string(&mut self, value: &str) -> Result<(), FracJsonError> {
+        return self.write(value.as_bytes());
     }
 }
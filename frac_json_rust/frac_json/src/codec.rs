@@ -0,0 +1,698 @@
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use zstd::{
+    bulk::{compress, Compressor, Decompressor},
+    dict::{DecoderDictionary, EncoderDictionary},
+};
+
+use crate::{
+    byte_stream::{
+        ByteReader, ByteWriter, IoByteReader, IoByteWriter, OwnedStrings, SliceByteReader, StrRead,
+    },
+    config::{Config, FINGERPRINT_OFFSET},
+    cursor::FracDocument,
+    de::DeserializeBody,
+    error::{FracJsonError, Limit},
+    extract::extract_many_from,
+    frac_value::{FracValue, FracValueBody},
+    json_types::value::{read_value, write_value},
+    keys_table::{
+        DecodeKeysTables, DecodeSession, EncodeKeysTables, EncodeSession, GlobalKeysTable,
+        GlobalKeysTableSet,
+    },
+    options::{DecodeLimits, DecodeOptions, EncodeOptions, Utf8Validation},
+    ser::SerializeBody,
+    transcode::{JsonBody, JsonTextBody},
+};
+
+/// Largest ratio of decompressed to compressed size for which the content size stored in a
+/// zstd frame is trusted to allocate the output up front
+const MAX_PREALLOCATION_RATIO: u64 = 64;
+
+/// Content of a file after the header.
+pub(crate) trait EncodeBody {
+    fn write_body<'a, W: ByteWriter>(
+        &'a self,
+        bytes: &mut W,
+        keys_table: &mut EncodeKeysTables<'a>,
+    ) -> Result<(), FracJsonError>;
+}
+
+impl EncodeBody for Value {
+    fn write_body<'a, W: ByteWriter>(
+        &'a self,
+        bytes: &mut W,
+        keys_table: &mut EncodeKeysTables<'a>,
+    ) -> Result<(), FracJsonError> {
+        write_value(self, bytes, keys_table)
+    }
+}
+
+/// Reads the content of a file after the header.
+pub(crate) trait DecodeBody<'de> {
+    type Output;
+
+    fn read_body<R: StrRead<'de>>(
+        self,
+        bytes: R,
+        keys_table: DecodeKeysTables,
+    ) -> Result<Self::Output, FracJsonError>;
+}
+
+pub(crate) struct ValueBody;
+
+impl<'de> DecodeBody<'de> for ValueBody {
+    type Output = Value;
+
+    fn read_body<R: StrRead<'de>>(
+        self,
+        mut bytes: R,
+        mut keys_table: DecodeKeysTables,
+    ) -> Result<Value, FracJsonError> {
+        read_value(&mut bytes, &mut keys_table)
+    }
+}
+
+/// Reusable encoder, for encoding many values with the same keys table and settings.
+///
+/// The keys table is parsed and the zstd dictionary is digested only once, when the
+/// encoder is created. Encoders are `Send + Sync` and can be shared between threads.
+pub struct Encoder {
+    global_keys_table: Option<GlobalKeysTable>,
+    compression_level: Option<i32>,
+    zstd_dict: Option<EncoderDictionary<'static>>,
+    keys_table_fingerprint: bool,
+    /// fingerprints of the layers of the `GlobalKeysTableSet`, if the table is one
+    keys_table_layers: Vec<u32>,
+}
+
+impl Encoder {
+    pub fn new(
+        global_keys_table: Option<GlobalKeysTable>,
+        compression_level: Option<i32>,
+        zstd_dict: Option<&[u8]>,
+    ) -> Result<Encoder, FracJsonError> {
+        let zstd_dict = match (zstd_dict, compression_level) {
+            (Some(dict), Some(level)) => Some(EncoderDictionary::copy(dict, level)),
+            (Some(_), None) => {
+                return Err(FracJsonError::InvalidOptions(
+                    "zstd_dict is set but compression_level is not set".to_string(),
+                ))
+            }
+            (None, _) => None,
+        };
+        return Ok(Encoder {
+            global_keys_table,
+            compression_level,
+            zstd_dict,
+            keys_table_fingerprint: false,
+            keys_table_layers: Vec::new(),
+        });
+    }
+
+    pub fn from_options(options: &EncodeOptions) -> Result<Encoder, FracJsonError> {
+        let global_keys_table = match options.global_keys_table_bytes {
+            Some(bytes) => Some(GlobalKeysTable::from_bytes(bytes)?),
+            None => None,
+        };
+        let encoder = Encoder::new(
+            global_keys_table,
+            options.compression_level,
+            options.zstd_dict,
+        )?
+        .with_keys_table_fingerprint(options.keys_table_fingerprint);
+        match keys_table_set_from_options(
+            options.global_keys_table_bytes,
+            options.global_keys_table_layers,
+        )? {
+            Some(keys_table_set) => return Ok(encoder.with_keys_table_set(keys_table_set)),
+            None => return Ok(encoder),
+        }
+    }
+
+    /// Uses the layers of `keys_table_set` as the global keys table, replacing any other
+    /// table. The fingerprints of all layers are stored in each file, regardless of
+    /// `with_keys_table_fingerprint`.
+    pub fn with_keys_table_set(mut self, keys_table_set: GlobalKeysTableSet) -> Encoder {
+        self.global_keys_table = Some(keys_table_set.to_table());
+        self.keys_table_layers = keys_table_set.fingerprints();
+        self
+    }
+
+    /// Stores the fingerprint of the global keys table in each file, so decoding it with a
+    /// different table fails with `KeysTableMismatch` instead of returning wrong keys. Has
+    /// no effect without a global keys table.
+    pub fn with_keys_table_fingerprint(mut self, keys_table_fingerprint: bool) -> Encoder {
+        self.keys_table_fingerprint = keys_table_fingerprint;
+        self
+    }
+
+    pub fn encode(&self, json: &Value) -> Result<Vec<u8>, FracJsonError> {
+        self.encode_body(json)
+    }
+
+    /// Encodes `json` directly into `writer`.
+    ///
+    /// Output is buffered internally and compressed as a zstd stream, so memory usage does
+    /// not grow with the size of the document.
+    pub fn encode_to_writer<W: Write>(&self, json: &Value, writer: W) -> Result<(), FracJsonError> {
+        self.encode_body_to_writer(json, writer)
+    }
+
+    /// Serializes `value` without building an intermediate `serde_json::Value`.
+    pub fn to_vec<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, FracJsonError> {
+        self.encode_body(&SerializeBody(value))
+    }
+
+    /// Like `to_vec`, but writes the file directly into `writer`.
+    pub fn to_writer<T: Serialize + ?Sized, W: Write>(
+        &self,
+        value: &T,
+        writer: W,
+    ) -> Result<(), FracJsonError> {
+        self.encode_body_to_writer(&SerializeBody(value), writer)
+    }
+
+    /// Encodes JSON text read from `reader` directly into `writer`, see
+    /// `transcode_from_json`.
+    pub fn transcode_from_json<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: W,
+    ) -> Result<(), FracJsonError> {
+        self.encode_body_to_writer(&JsonBody::new(reader), writer)
+    }
+
+    pub(crate) fn compression_level(&self) -> Option<i32> {
+        self.compression_level
+    }
+
+    /// Encodes `body` with the local keys table of `session` instead of an empty one.
+    pub(crate) fn encode_in_session<B: EncodeBody>(
+        &self,
+        body: &B,
+        session: &mut EncodeSession,
+    ) -> Result<Vec<u8>, FracJsonError> {
+        let keys_table = EncodeKeysTables::make_session(session, self.global_keys_table.as_ref());
+        self.encode_body_with(body, keys_table)
+    }
+
+    fn encode_body<B: EncodeBody>(&self, body: &B) -> Result<Vec<u8>, FracJsonError> {
+        self.encode_body_with(body, self.make_keys_tables())
+    }
+
+    fn encode_body_with<'a, B: EncodeBody>(
+        &'a self,
+        body: &'a B,
+        mut keys_table: EncodeKeysTables<'a>,
+    ) -> Result<Vec<u8>, FracJsonError> {
+        let mut file_bytes = Vec::with_capacity(1024);
+        self.config().write_header(&mut file_bytes)?;
+        match self.compression_level {
+            None => {
+                body.write_body(&mut file_bytes, &mut keys_table)?;
+            }
+            Some(level) => {
+                let mut json_value_bytes = Vec::with_capacity(1024);
+                body.write_body(&mut json_value_bytes, &mut keys_table)?;
+                let compressed_bytes = match &self.zstd_dict {
+                    Some(dict) => Compressor::with_prepared_dictionary(dict)
+                        .map_err(FracJsonError::Compression)?
+                        .compress(&json_value_bytes)
+                        .map_err(FracJsonError::Compression)?,
+                    None => {
+                        compress(&json_value_bytes, level).map_err(FracJsonError::Compression)?
+                    }
+                };
+                file_bytes.extend(compressed_bytes);
+            }
+        }
+        return Ok(file_bytes);
+    }
+
+    fn encode_body_to_writer<B: EncodeBody, W: Write>(
+        &self,
+        body: &B,
+        writer: W,
+    ) -> Result<(), FracJsonError> {
+        let mut keys_table = self.make_keys_tables();
+        let mut bytes = IoByteWriter::make(writer);
+        self.config().write_header(&mut bytes)?;
+        match self.compression_level {
+            None => {
+                body.write_body(&mut bytes, &mut keys_table)?;
+                bytes.finish()?;
+            }
+            Some(level) => {
+                let header_size = bytes.position();
+                let writer = bytes.finish()?;
+                let encoder = match &self.zstd_dict {
+                    Some(dict) => {
+                        zstd::stream::write::Encoder::with_prepared_dictionary(writer, dict)
+                    }
+                    None => zstd::stream::write::Encoder::new(writer, level),
+                }
+                .map_err(FracJsonError::Compression)?;
+                let mut bytes = IoByteWriter::make_at(encoder, header_size);
+                body.write_body(&mut bytes, &mut keys_table)?;
+                bytes.finish()?.finish().map_err(FracJsonError::Io)?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn config(&self) -> Config {
+        let fingerprint = if self.keys_table_fingerprint && self.keys_table_layers.is_empty() {
+            self.global_keys_table
+                .as_ref()
+                .map(GlobalKeysTable::fingerprint)
+        } else {
+            None
+        };
+        Config::make(
+            self.compression_level.is_some(),
+            self.zstd_dict.is_some(),
+            fingerprint,
+            self.keys_table_layers.clone(),
+        )
+    }
+
+    fn make_keys_tables(&self) -> EncodeKeysTables<'_> {
+        EncodeKeysTables::make(Vec::new(), self.global_keys_table.as_ref())
+    }
+}
+
+/// Reusable decoder, for decoding many files with the same keys table and settings.
+///
+/// The keys table is parsed and the zstd dictionary is digested only once, when the
+/// decoder is created. Decoders are `Send + Sync` and can be shared between threads.
+pub struct Decoder {
+    global_keys_table: Option<GlobalKeysTable>,
+    zstd_dict: Option<DecoderDictionary<'static>>,
+    utf8_validation: Utf8Validation,
+    limits: DecodeLimits,
+    /// layers of the global keys table, if it is a `GlobalKeysTableSet`
+    keys_table_set: Option<GlobalKeysTableSet>,
+}
+
+impl Decoder {
+    pub fn new(global_keys_table: Option<GlobalKeysTable>, zstd_dict: Option<&[u8]>) -> Decoder {
+        Decoder {
+            global_keys_table,
+            zstd_dict: zstd_dict.map(DecoderDictionary::copy),
+            utf8_validation: Utf8Validation::default(),
+            limits: DecodeLimits::default(),
+            keys_table_set: None,
+        }
+    }
+
+    pub fn from_options(options: &DecodeOptions) -> Result<Decoder, FracJsonError> {
+        let global_keys_table = match options.global_keys_table_bytes {
+            Some(bytes) => Some(GlobalKeysTable::from_bytes(bytes)?),
+            None => None,
+        };
+        let decoder = Decoder::new(global_keys_table, options.zstd_dict)
+            .with_utf8_validation(options.utf8_validation)
+            .with_limits(options.limits);
+        match keys_table_set_from_options(
+            options.global_keys_table_bytes,
+            options.global_keys_table_layers,
+        )? {
+            Some(keys_table_set) => return Ok(decoder.with_keys_table_set(keys_table_set)),
+            None => return Ok(decoder),
+        }
+    }
+
+    /// Uses the layers of `keys_table_set` as the global keys table, replacing any other
+    /// table. Files encoded with a set are checked to have been encoded with the same layers.
+    pub fn with_keys_table_set(mut self, keys_table_set: GlobalKeysTableSet) -> Decoder {
+        self.global_keys_table = Some(keys_table_set.to_table());
+        self.keys_table_set = Some(keys_table_set);
+        self
+    }
+
+    pub fn with_utf8_validation(mut self, utf8_validation: Utf8Validation) -> Decoder {
+        self.utf8_validation = utf8_validation;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Decoder {
+        self.limits = limits;
+        self
+    }
+
+    pub fn decode(&self, frac_json_bytes: &[u8]) -> Result<Value, FracJsonError> {
+        self.decode_body(frac_json_bytes, ValueBody)
+    }
+
+    /// Decodes a file directly from `reader`, without reading it into memory first.
+    ///
+    /// zstd compressed files are decompressed while decoding. Bytes after the encoded value
+    /// may or may not have been consumed from `reader`.
+    pub fn decode_from_reader<R: Read>(&self, reader: R) -> Result<Value, FracJsonError> {
+        self.decode_body_from_reader(reader, ValueBody)
+    }
+
+    /// Decodes into a `FracValue` whose strings and keys borrow from `frac_json_bytes` and
+    /// the global keys table of this decoder, instead of being copied.
+    pub fn decode_borrowed<'a>(
+        &'a self,
+        frac_json_bytes: &'a [u8],
+    ) -> Result<FracValue<'a>, FracJsonError> {
+        let body = FracValueBody {
+            global_table: self.global_keys_table.as_ref(),
+        };
+        self.decode_body(frac_json_bytes, body)
+    }
+
+    /// Document that is read lazily through `FracCursor`s, using the global keys table of
+    /// this decoder.
+    pub fn document<'a>(
+        &'a self,
+        frac_json_bytes: &'a [u8],
+    ) -> Result<FracDocument<'a>, FracJsonError> {
+        FracDocument::make(self, frac_json_bytes, self.global_keys_table.as_ref())
+    }
+
+    /// Decodes only the value at the JSON Pointer `pointer`, see `extract`.
+    pub fn extract(
+        &self,
+        frac_json_bytes: &[u8],
+        pointer: &str,
+    ) -> Result<Option<Value>, FracJsonError> {
+        let document = self.document(frac_json_bytes)?;
+        match document.root().pointer(pointer)? {
+            Some(cursor) => return Ok(Some(cursor.to_value()?)),
+            None => return Ok(None),
+        }
+    }
+
+    /// Decodes the values at several JSON Pointers in one pass, see `extract_many`.
+    pub fn extract_many(
+        &self,
+        frac_json_bytes: &[u8],
+        pointers: &[&str],
+    ) -> Result<Vec<Option<Value>>, FracJsonError> {
+        let document = self.document(frac_json_bytes)?;
+        return extract_many_from(document.root(), pointers);
+    }
+
+    /// Deserializes a `T` without building an intermediate `Value`.
+    ///
+    /// Strings of uncompressed files are borrowed from `frac_json_bytes` if `T` supports
+    /// it. Compressed files are decompressed first, so `T` can't borrow from them.
+    pub fn from_slice<'de, T: Deserialize<'de>>(
+        &self,
+        frac_json_bytes: &'de [u8],
+    ) -> Result<T, FracJsonError> {
+        self.decode_body(frac_json_bytes, DeserializeBody::new())
+    }
+
+    /// Like `from_slice`, but reads the file directly from `reader`.
+    pub fn from_reader<R: Read, T: DeserializeOwned>(&self, reader: R) -> Result<T, FracJsonError> {
+        self.decode_body_from_reader(reader, DeserializeBody::new())
+    }
+
+    /// Writes the file as JSON text into `writer`, see `transcode_to_json`.
+    ///
+    /// Strings of uncompressed files are written without being copied first.
+    pub fn transcode_to_json<W: Write>(
+        &self,
+        frac_json_bytes: &[u8],
+        writer: W,
+        pretty: bool,
+    ) -> Result<(), FracJsonError> {
+        self.decode_body(frac_json_bytes, JsonTextBody::new(writer, pretty))
+    }
+
+    /// Like `transcode_to_json`, but reads the file directly from `reader`.
+    pub fn transcode_to_json_from_reader<R: Read, W: Write>(
+        &self,
+        reader: R,
+        writer: W,
+        pretty: bool,
+    ) -> Result<(), FracJsonError> {
+        self.decode_body_from_reader(reader, JsonTextBody::new(writer, pretty))
+    }
+
+    pub(crate) fn decode_body<'de, B: DecodeBody<'de>>(
+        &self,
+        frac_json_bytes: &'de [u8],
+        body: B,
+    ) -> Result<B::Output, FracJsonError> {
+        self.decode_body_with(frac_json_bytes, body, self.make_keys_tables())
+    }
+
+    /// Decodes `body` with the local keys table of `session` instead of an empty one.
+    pub(crate) fn decode_in_session<'de, B: DecodeBody<'de>>(
+        &self,
+        frac_json_bytes: &'de [u8],
+        body: B,
+        session: &mut DecodeSession,
+    ) -> Result<B::Output, FracJsonError> {
+        let keys_table = DecodeKeysTables::make_session(session, self.global_keys_table.as_ref());
+        self.decode_body_with(frac_json_bytes, body, keys_table)
+    }
+
+    fn decode_body_with<'de, B: DecodeBody<'de>>(
+        &self,
+        frac_json_bytes: &'de [u8],
+        body: B,
+        keys_table: DecodeKeysTables,
+    ) -> Result<B::Output, FracJsonError> {
+        match self.read_file_body(frac_json_bytes)? {
+            (Cow::Borrowed(body_bytes), header_size) => {
+                let bytes = SliceByteReader::make_at(body_bytes, header_size);
+                body.read_body(self.configure(bytes), keys_table)
+            }
+            (Cow::Owned(body_bytes), header_size) => {
+                let bytes = OwnedStrings(SliceByteReader::make_at(&body_bytes, header_size));
+                body.read_body(self.configure(bytes), keys_table)
+            }
+        }
+    }
+
+    /// Reads the header and returns the rest of the file, decompressed if necessary, along
+    /// with the size of the header.
+    pub(crate) fn read_file_body<'b>(
+        &self,
+        frac_json_bytes: &'b [u8],
+    ) -> Result<(Cow<'b, [u8]>, usize), FracJsonError> {
+        let mut bytes = SliceByteReader::make(frac_json_bytes);
+        let config = Config::read_header(&mut bytes)?;
+        self.check_keys_table(&config)?;
+        let dict = self.dict_for(&config)?;
+        let header_size = bytes.offset();
+        let body_bytes = bytes.read_remaining()?;
+        if !config.is_zstd_compressed {
+            return Ok((Cow::Borrowed(body_bytes), header_size));
+        }
+        let decompressed_bytes = try_decompress(
+            body_bytes,
+            dict,
+            self.limits.max_decompressed_size,
+            header_size,
+        )?;
+        return Ok((Cow::Owned(decompressed_bytes), header_size));
+    }
+
+    fn decode_body_from_reader<'de, R: Read, B: DecodeBody<'de>>(
+        &self,
+        reader: R,
+        body: B,
+    ) -> Result<B::Output, FracJsonError> {
+        let mut bytes = IoByteReader::make(reader);
+        let config = Config::read_header(&mut bytes)?;
+        self.check_keys_table(&config)?;
+        let dict = self.dict_for(&config)?;
+        let keys_table = self.make_keys_tables();
+        if !config.is_zstd_compressed {
+            return body.read_body(self.configure(OwnedStrings(bytes)), keys_table);
+        }
+        let header_size = bytes.offset();
+        let reader = bytes.into_inner();
+        let decoder = match dict {
+            Some(dict) => zstd::stream::read::Decoder::with_prepared_dictionary(reader, dict),
+            None => zstd::stream::read::Decoder::with_buffer(reader),
+        }
+        .map_err(FracJsonError::Compression)?;
+        let bytes = IoByteReader::make_at(decoder, header_size)
+            .with_max_size(self.limits.max_decompressed_size);
+        return body.read_body(self.configure(OwnedStrings(bytes)), keys_table);
+    }
+
+    pub(crate) fn utf8_validation(&self) -> Utf8Validation {
+        self.utf8_validation
+    }
+
+    pub(crate) fn limits(&self) -> DecodeLimits {
+        self.limits
+    }
+
+    fn configure<R: ByteReader>(&self, bytes: R) -> R {
+        bytes
+            .with_utf8_validation(self.utf8_validation)
+            .with_limits(self.limits)
+    }
+
+    /// Fails if the file was encoded with a different global keys table. Tables that only
+    /// appended keys to the one the file was encoded with are accepted.
+    fn check_keys_table(&self, config: &Config) -> Result<(), FracJsonError> {
+        if let Some(expected) = config.keys_table_fingerprint {
+            let table = self.global_keys_table.as_ref();
+            if !table.is_some_and(|table| table.extends_fingerprint(expected)) {
+                return Err(FracJsonError::KeysTableMismatch {
+                    offset: FINGERPRINT_OFFSET,
+                    expected,
+                    actual: table.map(GlobalKeysTable::fingerprint),
+                });
+            }
+        }
+        if config.keys_table_layers.is_empty() {
+            return Ok(());
+        }
+        // a single table counts as a set of one layer
+        let actual_layers: &[GlobalKeysTable] = match &self.keys_table_set {
+            Some(keys_table_set) => keys_table_set.layers(),
+            None => self.global_keys_table.as_slice(),
+        };
+        let mut layers_offset = FINGERPRINT_OFFSET;
+        if config.keys_table_fingerprint.is_some() {
+            layers_offset += 4;
+        }
+        let layer_count = config.keys_table_layers.len().max(actual_layers.len());
+        for layer in 0..layer_count {
+            let expected = config.keys_table_layers.get(layer).copied();
+            let actual = actual_layers.get(layer);
+            // only the top layer can be extended, without moving the indices of other layers
+            let is_top_layer =
+                layer + 1 == config.keys_table_layers.len() && layer + 1 == actual_layers.len();
+            let matches = match (expected, actual) {
+                (Some(expected), Some(actual)) => {
+                    actual.fingerprint() == expected
+                        || (is_top_layer && actual.extends_fingerprint(expected))
+                }
+                _ => false,
+            };
+            if !matches {
+                // offset of the layer's fingerprint, or of the layer count if it has none
+                let offset = if expected.is_some() {
+                    layers_offset + 1 + layer * 4
+                } else {
+                    layers_offset
+                };
+                return Err(FracJsonError::KeysTableLayerMismatch {
+                    offset,
+                    layer,
+                    expected,
+                    actual: actual.map(GlobalKeysTable::fingerprint),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// zstd dictionary to use for a file with the given config
+    fn dict_for(
+        &self,
+        config: &Config,
+    ) -> Result<Option<&DecoderDictionary<'static>>, FracJsonError> {
+        if !config.uses_external_dict {
+            return Ok(None);
+        }
+        match &self.zstd_dict {
+            Some(dict) => return Ok(Some(dict)),
+            None => return Err(FracJsonError::MissingZstdDict),
+        }
+    }
+
+    fn make_keys_tables(&self) -> DecodeKeysTables<'_> {
+        DecodeKeysTables::make(self.global_keys_table.as_ref())
+    }
+}
+
+/// Keys table set described by `global_keys_table_layers`, which can't be combined with
+/// `global_keys_table_bytes`
+fn keys_table_set_from_options(
+    global_keys_table_bytes: Option<&[u8]>,
+    global_keys_table_layers: Option<&[&[u8]]>,
+) -> Result<Option<GlobalKeysTableSet>, FracJsonError> {
+    let Some(layers) = global_keys_table_layers else {
+        return Ok(None);
+    };
+    if global_keys_table_bytes.is_some() {
+        return Err(FracJsonError::InvalidOptions(
+            "global_keys_table_bytes and global_keys_table_layers can't both be set".to_string(),
+        ));
+    }
+    return Ok(Some(GlobalKeysTableSet::from_bytes(layers)?));
+}
+
+fn try_decompress(
+    bytes: &[u8],
+    dict: Option<&DecoderDictionary>,
+    max_size: usize,
+    offset: usize,
+) -> Result<Vec<u8>, FracJsonError> {
+    match zstd::zstd_safe::get_frame_content_size(bytes) {
+        Ok(Some(content_size)) => {
+            if content_size > max_size as u64 {
+                return Err(FracJsonError::LimitExceeded {
+                    offset,
+                    limit: Limit::DecompressedSize,
+                    value: usize::try_from(content_size).unwrap_or(usize::MAX),
+                    max: max_size,
+                });
+            }
+            // the content size is not verified before decompressing, so a forged size could
+            // allocate up to max_size for a tiny input. Larger outputs grow while streaming.
+            if content_size > (bytes.len() as u64).saturating_mul(MAX_PREALLOCATION_RATIO) {
+                return stream_decompress(bytes, dict, max_size, offset);
+            }
+            let mut decompressor = match dict {
+                Some(d) => Decompressor::with_prepared_dictionary(d),
+                None => Decompressor::new(),
+            }
+            .map_err(FracJsonError::Compression)?;
+            return decompressor
+                .decompress(bytes, content_size as usize)
+                .map_err(FracJsonError::Compression);
+        }
+        // size is not stored in the frame header or the header is invalid, let the
+        // streaming decoder figure it out
+        _ => return stream_decompress(bytes, dict, max_size, offset),
+    }
+}
+
+fn stream_decompress(
+    bytes: &[u8],
+    dict: Option<&DecoderDictionary>,
+    max_size: usize,
+    offset: usize,
+) -> Result<Vec<u8>, FracJsonError> {
+    let decoder = match dict {
+        Some(d) => zstd::stream::read::Decoder::with_prepared_dictionary(bytes, d),
+        None => zstd::stream::read::Decoder::with_buffer(bytes),
+    }
+    .map_err(FracJsonError::Compression)?;
+    // read one byte more than allowed, to detect when the limit is exceeded
+    let mut decompressed_bytes = Vec::new();
+    decoder
+        .take((max_size as u64).saturating_add(1))
+        .read_to_end(&mut decompressed_bytes)
+        .map_err(FracJsonError::Compression)?;
+    if decompressed_bytes.len() > max_size {
+        return Err(FracJsonError::LimitExceeded {
+            offset,
+            limit: Limit::DecompressedSize,
+            value: decompressed_bytes.len(),
+            max: max_size,
+        });
+    }
+    return Ok(decompressed_bytes);
+}
@@ -1,44 +1,94 @@
-use crate::byte_stream::ByteWriter;
+use crate::{byte_stream::ByteWriter, error::FracJsonError};
 
 use super::byte_stream::ByteReader;
 
-const CURRENT_VERSION: u8 = 0;
+/// Version of files without keys table fingerprints, which older decoders can still read
+const BASE_VERSION: u8 = 0;
+/// Version of files with a keys table fingerprint or layers in the header. Decoders that
+/// predate them reject these files instead of reading the fingerprints as values.
+const CURRENT_VERSION: u8 = 1;
 
 pub struct Config {
     pub version: u8,
     pub is_zstd_compressed: bool,
     pub uses_external_dict: bool,
+    /// Fingerprint of the global keys table the file was encoded with, stored after the
+    /// config byte
+    pub keys_table_fingerprint: Option<u32>,
+    /// Fingerprints of each layer of a `GlobalKeysTableSet`, base layer first. Empty if the
+    /// file wasn't encoded with a set.
+    pub keys_table_layers: Vec<u32>,
 }
 
 const FJ_MAGIC: &[u8; 2] = b"FJ";
+/// Offset of the keys table fingerprint, if the file has one
+pub const FINGERPRINT_OFFSET: usize = 3;
+/// Most layers of a `GlobalKeysTableSet` the header can describe
+pub const MAX_KEYS_TABLE_LAYERS: usize = 0xFF;
+
 impl Config {
-    pub fn make(is_zstd_compressed: bool, uses_external_dict: bool) -> Config {
+    pub fn make(
+        is_zstd_compressed: bool,
+        uses_external_dict: bool,
+        keys_table_fingerprint: Option<u32>,
+        keys_table_layers: Vec<u32>,
+    ) -> Config {
+        let version = if keys_table_fingerprint.is_some() || !keys_table_layers.is_empty() {
+            CURRENT_VERSION
+        } else {
+            BASE_VERSION
+        };
         Config {
-            version: CURRENT_VERSION,
-            is_zstd_compressed: is_zstd_compressed,
-            uses_external_dict: uses_external_dict,
+            version,
+            is_zstd_compressed,
+            uses_external_dict,
+            keys_table_fingerprint,
+            keys_table_layers,
         }
     }
 
-    pub fn read_header(bytes: &mut ByteReader) -> Result<Config, String> {
+    pub fn read_header<R: ByteReader>(bytes: &mut R) -> Result<Config, FracJsonError> {
+        let magic_offset = bytes.offset();
         let magic = bytes.read2()?;
         if magic != *FJ_MAGIC {
-            return Err(format!("Invalid magic {:?}", magic));
+            return Err(FracJsonError::InvalidMagic {
+                offset: magic_offset,
+                magic,
+            });
         }
+        let config_offset = bytes.offset();
         let config = bytes.read_u8()?;
-        let config = Config {
-            version: config & 0b00001111,
-            is_zstd_compressed: (config & 0b00010000) != 0,
-            uses_external_dict: (config & 0b00100000) != 0,
+        let version = config & 0b00001111;
+        if version > CURRENT_VERSION {
+            return Err(FracJsonError::UnsupportedVersion {
+                offset: config_offset,
+                version,
+            });
+        }
+        let keys_table_fingerprint = if (config & 0b01000000) != 0 {
+            Some(bytes.read_u32()?)
+        } else {
+            None
         };
-        if config.version > CURRENT_VERSION {
-            return Err(format!("Unsupported version {}", config.version));
+        let mut keys_table_layers = Vec::new();
+        if (config & 0b10000000) != 0 {
+            let count = bytes.read_u8()?;
+            keys_table_layers.reserve_exact(count.into());
+            for _ in 0..count {
+                keys_table_layers.push(bytes.read_u32()?);
+            }
         }
-        return Ok(config);
+        return Ok(Config {
+            version,
+            is_zstd_compressed: (config & 0b00010000) != 0,
+            uses_external_dict: (config & 0b00100000) != 0,
+            keys_table_fingerprint,
+            keys_table_layers,
+        });
     }
 
-    pub fn write_header<W: ByteWriter>(&self, bytes: &mut W) {
-        bytes.write(FJ_MAGIC);
+    pub fn write_header<W: ByteWriter>(&self, bytes: &mut W) -> Result<(), FracJsonError> {
+        bytes.write(FJ_MAGIC)?;
         let mut config = self.version;
         if self.is_zstd_compressed {
             config |= 0b00010000;
@@ -46,6 +96,22 @@ impl Config {
         if self.uses_external_dict {
             config |= 0b00100000;
         }
-        bytes.write_u8(config);
+        if self.keys_table_fingerprint.is_some() {
+            config |= 0b01000000;
+        }
+        if !self.keys_table_layers.is_empty() {
+            config |= 0b10000000;
+        }
+        bytes.write_u8(config)?;
+        if let Some(fingerprint) = self.keys_table_fingerprint {
+            bytes.write_u32(fingerprint)?;
+        }
+        if !self.keys_table_layers.is_empty() {
+            bytes.write_u8(self.keys_table_layers.len() as u8)?;
+            for fingerprint in &self.keys_table_layers {
+                bytes.write_u32(*fingerprint)?;
+            }
+        }
+        return Ok(());
     }
 }
@@ -0,0 +1,399 @@
+use std::{borrow::Cow, cell::RefCell};
+
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    byte_stream::{ByteReader, SliceByteReader},
+    codec::Decoder,
+    error::FracJsonError,
+    extract::{parse_array_index, parse_pointer},
+    json_types::{
+        object::{read_key_ref, KeyRef},
+        value::{read_value_header, skip_value, ValueHeader},
+    },
+    keys_table::{lookup_global_index, CursorKeysTables, GlobalKeysTable, KeyPosition},
+    options::{DecodeLimits, Utf8Validation},
+};
+
+/// Encoded file that is read lazily through `FracCursor`s.
+///
+/// Compressed files are decompressed when the document is created, everything else is only
+/// read when a cursor needs it.
+#[derive(Debug)]
+pub struct FracDocument<'a> {
+    /// Everything after the header, decompressed
+    body: Cow<'a, [u8]>,
+    header_size: usize,
+    global_table: Option<&'a GlobalKeysTable>,
+    /// Immediate keys read so far, shared by all cursors
+    local_table: RefCell<Vec<KeyPosition>>,
+    utf8_validation: Utf8Validation,
+    limits: DecodeLimits,
+}
+
+impl<'a> FracDocument<'a> {
+    /// Document without a global keys table or zstd dictionary.
+    pub fn new(frac_json_bytes: &'a [u8]) -> Result<FracDocument<'a>, FracJsonError> {
+        return FracDocument::make(&Decoder::new(None, None), frac_json_bytes, None);
+    }
+
+    pub(crate) fn make(
+        decoder: &Decoder,
+        frac_json_bytes: &'a [u8],
+        global_table: Option<&'a GlobalKeysTable>,
+    ) -> Result<FracDocument<'a>, FracJsonError> {
+        let (body, header_size) = decoder.read_file_body(frac_json_bytes)?;
+        return Ok(FracDocument {
+            body,
+            header_size,
+            global_table,
+            local_table: RefCell::new(Vec::new()),
+            utf8_validation: decoder.utf8_validation(),
+            limits: decoder.limits(),
+        });
+    }
+
+    /// Cursor at the top level value
+    pub fn root(&self) -> FracCursor<'_> {
+        FracCursor {
+            document: self,
+            offset: self.header_size,
+        }
+    }
+
+    /// Reader starting at `offset` of the file. Limits that are tracked while reading, like
+    /// the nesting depth, apply to each cursor operation on its own.
+    fn reader_at(&self, offset: usize) -> SliceByteReader<'_> {
+        SliceByteReader::make_at(&self.body[offset - self.header_size..], offset)
+            .with_utf8_validation(self.utf8_validation)
+            .with_limits(self.limits)
+    }
+
+    fn with_keys_tables<T>(&self, f: impl FnOnce(&mut CursorKeysTables) -> T) -> T {
+        let mut local_table = self.local_table.borrow_mut();
+        let mut keys_table = CursorKeysTables::make(&mut local_table, self.global_table);
+        return f(&mut keys_table);
+    }
+
+    fn read_key<'d>(
+        &'d self,
+        bytes: &mut SliceByteReader<'d>,
+    ) -> Result<Cow<'d, str>, FracJsonError> {
+        let key_offset = bytes.offset();
+        match read_key_ref(bytes)? {
+            KeyRef::Immediate(length) => {
+                let offset = bytes.offset();
+                let key = bytes.read_str(length)?;
+                self.with_keys_tables(|keys_table| {
+                    keys_table.on_immediate_key(KeyPosition { offset, length })
+                });
+                return Ok(key);
+            }
+            KeyRef::Local(index) => {
+                let position = self.with_keys_tables(|keys_table| {
+                    keys_table.lookup_local_index(index, key_offset)
+                })?;
+                return self.reader_at(position.offset).read_str(position.length);
+            }
+            KeyRef::Global(index) => {
+                let key = lookup_global_index(self.global_table, index, key_offset)?;
+                return Ok(Cow::Borrowed(key));
+            }
+        }
+    }
+
+    fn skip_value(&self, bytes: &mut SliceByteReader<'_>) -> Result<(), FracJsonError> {
+        self.with_keys_tables(|keys_table| skip_value(bytes, keys_table))
+    }
+
+    /// Decodes the value at the position of `bytes` in one pass, each child starts where the
+    /// previous one ended.
+    fn read_value<'d>(&'d self, bytes: &mut SliceByteReader<'d>) -> Result<Value, FracJsonError> {
+        let value = match read_value_header(bytes)? {
+            ValueHeader::Null => Value::Null,
+            ValueHeader::Bool(b) => Value::Bool(b),
+            ValueHeader::Int(n) => Value::from(n),
+            ValueHeader::UInt(n) => Value::from(n),
+            ValueHeader::Float(f) => Number::from_f64(f as f64).map_or(Value::Null, Value::Number),
+            ValueHeader::Double(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+            ValueHeader::String(0) => Value::String(String::new()),
+            ValueHeader::String(length) => {
+                bytes.check_string_length(length)?;
+                Value::String(bytes.read_str(length)?.into_owned())
+            }
+            ValueHeader::Object(0) => Value::Object(Map::new()),
+            ValueHeader::Object(length) => {
+                bytes.enter_container(length)?;
+                let mut map = Map::with_capacity(bytes.capacity_hint(length, 2));
+                for _ in 0..length {
+                    let key = self.read_key(bytes)?.into_owned();
+                    let value = self.read_value(bytes)?;
+                    map.insert(key, value);
+                }
+                bytes.exit_container();
+                Value::Object(map)
+            }
+            ValueHeader::Array(0) => Value::Array(Vec::new()),
+            ValueHeader::Array(length) => {
+                bytes.enter_container(length)?;
+                let mut array = Vec::with_capacity(bytes.capacity_hint(length, 1));
+                for _ in 0..length {
+                    array.push(self.read_value(bytes)?);
+                }
+                bytes.exit_container();
+                Value::Array(array)
+            }
+        };
+        return Ok(value);
+    }
+}
+
+/// Position of a value in a `FracDocument`.
+///
+/// Moving a cursor only reads the keys and headers along the way. Subtrees that are passed
+/// are skipped, but their immediate keys are still recorded, since keys later in the
+/// document may reference them.
+#[derive(Debug, Clone, Copy)]
+pub struct FracCursor<'d> {
+    document: &'d FracDocument<'d>,
+    offset: usize,
+}
+
+impl<'d> FracCursor<'d> {
+    fn read_header(&self) -> Result<(ValueHeader, SliceByteReader<'d>), FracJsonError> {
+        let mut bytes = self.document.reader_at(self.offset);
+        let header = read_value_header(&mut bytes)?;
+        return Ok((header, bytes));
+    }
+
+    fn cursor_at(&self, offset: usize) -> FracCursor<'d> {
+        FracCursor {
+            document: self.document,
+            offset,
+        }
+    }
+
+    pub fn is_null(&self) -> Result<bool, FracJsonError> {
+        return Ok(matches!(self.read_header()?.0, ValueHeader::Null));
+    }
+
+    pub fn as_bool(&self) -> Result<Option<bool>, FracJsonError> {
+        match self.read_header()?.0 {
+            ValueHeader::Bool(b) => return Ok(Some(b)),
+            _ => return Ok(None),
+        }
+    }
+
+    /// The number, if this is a number. NaN and infinity are decoded as null instead.
+    pub fn as_number(&self) -> Result<Option<Number>, FracJsonError> {
+        let number = match self.read_header()?.0 {
+            ValueHeader::Int(n) => Some(n.into()),
+            ValueHeader::UInt(n) => Some(n.into()),
+            ValueHeader::Float(f) => Number::from_f64(f as f64),
+            ValueHeader::Double(f) => Number::from_f64(f),
+            _ => None,
+        };
+        return Ok(number);
+    }
+
+    pub fn as_i64(&self) -> Result<Option<i64>, FracJsonError> {
+        return Ok(self.as_number()?.and_then(|number| number.as_i64()));
+    }
+
+    pub fn as_u64(&self) -> Result<Option<u64>, FracJsonError> {
+        return Ok(self.as_number()?.and_then(|number| number.as_u64()));
+    }
+
+    pub fn as_f64(&self) -> Result<Option<f64>, FracJsonError> {
+        return Ok(self.as_number()?.and_then(|number| number.as_f64()));
+    }
+
+    /// The string, borrowed from the document if possible.
+    pub fn as_str(&self) -> Result<Option<Cow<'d, str>>, FracJsonError> {
+        match self.read_header()? {
+            (ValueHeader::String(0), _) => return Ok(Some(Cow::Borrowed(""))),
+            (ValueHeader::String(length), mut bytes) => {
+                bytes.check_string_length(length)?;
+                return Ok(Some(bytes.read_str(length)?));
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    /// Number of entries of an object or array.
+    pub fn len(&self) -> Result<Option<usize>, FracJsonError> {
+        match self.read_header()?.0 {
+            ValueHeader::Object(length) | ValueHeader::Array(length) => return Ok(Some(length)),
+            _ => return Ok(None),
+        }
+    }
+
+    /// Value of the last entry with `key`, if this is an object. Like `decode`, later
+    /// entries with a duplicate key replace earlier ones, so the whole object is read.
+    pub fn get(&self, key: &str) -> Result<Option<FracCursor<'d>>, FracJsonError> {
+        let Some(mut entries) = self.iter_entries()? else {
+            return Ok(None);
+        };
+        let mut found = None;
+        while entries.remaining > 0 {
+            let mut bytes = self.document.reader_at(entries.offset);
+            entries.remaining -= 1;
+            if self.document.read_key(&mut bytes)? == key {
+                found = Some(self.cursor_at(bytes.offset()));
+            }
+            self.document.skip_value(&mut bytes)?;
+            entries.offset = bytes.offset();
+        }
+        return Ok(found);
+    }
+
+    /// Element at `index`, if this is an array.
+    pub fn index(&self, index: usize) -> Result<Option<FracCursor<'d>>, FracJsonError> {
+        let Some(elements) = self.iter_elements()? else {
+            return Ok(None);
+        };
+        if index >= elements.remaining {
+            return Ok(None);
+        }
+        let mut bytes = self.document.reader_at(elements.offset);
+        for _ in 0..index {
+            self.document.skip_value(&mut bytes)?;
+        }
+        return Ok(Some(self.cursor_at(bytes.offset())));
+    }
+
+    /// Cursor at the JSON Pointer (RFC 6901) `pointer`, relative to this cursor.
+    pub fn pointer(&self, pointer: &str) -> Result<Option<FracCursor<'d>>, FracJsonError> {
+        let mut cursor = *self;
+        for token in parse_pointer(pointer)? {
+            let next = if cursor.iter_elements()?.is_some() {
+                match parse_array_index(&token) {
+                    Some(index) => cursor.index(index)?,
+                    None => None,
+                }
+            } else {
+                cursor.get(&token)?
+            };
+            match next {
+                Some(next) => cursor = next,
+                None => return Ok(None),
+            }
+        }
+        return Ok(Some(cursor));
+    }
+
+    /// Keys and values of an object, in the order they were encoded.
+    pub fn iter_entries(&self) -> Result<Option<FracEntries<'d>>, FracJsonError> {
+        match self.read_header()? {
+            (ValueHeader::Object(length), mut bytes) => {
+                bytes.enter_container(length)?;
+                return Ok(Some(FracEntries {
+                    document: self.document,
+                    offset: bytes.offset(),
+                    remaining: length,
+                }));
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    /// Elements of an array.
+    pub fn iter_elements(&self) -> Result<Option<FracElements<'d>>, FracJsonError> {
+        match self.read_header()? {
+            (ValueHeader::Array(length), mut bytes) => {
+                bytes.enter_container(length)?;
+                return Ok(Some(FracElements {
+                    document: self.document,
+                    offset: bytes.offset(),
+                    remaining: length,
+                }));
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    /// Decodes the value at this cursor and everything below it.
+    pub fn to_value(self) -> Result<Value, FracJsonError> {
+        let mut bytes = self.document.reader_at(self.offset);
+        return self.document.read_value(&mut bytes);
+    }
+}
+
+/// Iterator over the entries of an object, see `FracCursor::iter_entries`.
+///
+/// Each step skips over the previous value. Iteration stops after the first error.
+pub struct FracEntries<'d> {
+    document: &'d FracDocument<'d>,
+    /// Offset of the next key
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'d> Iterator for FracEntries<'d> {
+    type Item = Result<(Cow<'d, str>, FracCursor<'d>), FracJsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let document = self.document;
+        let mut bytes = document.reader_at(self.offset);
+        let entry = document.read_key(&mut bytes).and_then(|key| {
+            let value = FracCursor {
+                document,
+                offset: bytes.offset(),
+            };
+            document.skip_value(&mut bytes)?;
+            Ok((key, value))
+        });
+        match entry {
+            Ok(_) => self.offset = bytes.offset(),
+            Err(_) => self.remaining = 0,
+        }
+        return Some(entry);
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+/// Iterator over the elements of an array, see `FracCursor::iter_elements`.
+///
+/// Each step skips over the previous element. Iteration stops after the first error.
+pub struct FracElements<'d> {
+    document: &'d FracDocument<'d>,
+    /// Offset of the next element
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'d> Iterator for FracElements<'d> {
+    type Item = Result<FracCursor<'d>, FracJsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let document = self.document;
+        let element = FracCursor {
+            document,
+            offset: self.offset,
+        };
+        let mut bytes = document.reader_at(self.offset);
+        match document.skip_value(&mut bytes) {
+            Ok(()) => self.offset = bytes.offset(),
+            Err(err) => {
+                self.remaining = 0;
+                return Some(Err(err));
+            }
+        }
+        return Some(Ok(element));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
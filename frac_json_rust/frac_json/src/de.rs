@@ -0,0 +1,431 @@
+use std::{borrow::Cow, io::Read, marker::PhantomData};
+
+use serde::{
+    de::{
+        self,
+        value::{BorrowedStrDeserializer, StrDeserializer, StringDeserializer},
+        DeserializeOwned, DeserializeSeed, IntoDeserializer, Unexpected, Visitor,
+    },
+    forward_to_deserialize_any, Deserialize,
+};
+
+use crate::{
+    byte_stream::StrRead,
+    codec::{DecodeBody, Decoder},
+    error::FracJsonError,
+    json_types::{
+        object::{read_key_ref, KeyRef},
+        value::{read_value_header, skip_value_contents, ValueHeader},
+    },
+    keys_table::DecodeKeysTables,
+};
+
+/// Deserializes a `T` from a file without a global keys table or zstd dictionary.
+///
+/// The result is the same as `serde_json::from_value(decode(frac_json_bytes, None, None)?)`,
+/// without building the intermediate `Value`. Strings of uncompressed files can be borrowed
+/// from `frac_json_bytes`.
+pub fn from_slice<'de, T: Deserialize<'de>>(
+    frac_json_bytes: &'de [u8],
+) -> Result<T, FracJsonError> {
+    return Decoder::new(None, None).from_slice(frac_json_bytes);
+}
+
+/// Like `from_slice`, but reads the file directly from `reader`.
+pub fn from_reader<R: Read, T: DeserializeOwned>(reader: R) -> Result<T, FracJsonError> {
+    return Decoder::new(None, None).from_reader(reader);
+}
+
+pub(crate) struct DeserializeBody<T>(PhantomData<T>);
+
+impl<T> DeserializeBody<T> {
+    pub fn new() -> DeserializeBody<T> {
+        DeserializeBody(PhantomData)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> DecodeBody<'de> for DeserializeBody<T> {
+    type Output = T;
+
+    fn read_body<R: StrRead<'de>>(
+        self,
+        bytes: R,
+        keys_table: DecodeKeysTables,
+    ) -> Result<T, FracJsonError> {
+        let mut deserializer = Deserializer {
+            bytes,
+            keys_table,
+            peeked: None,
+        };
+        T::deserialize(&mut deserializer)
+    }
+}
+
+/// Reads values in the same way as `read_value`, but passes them to a serde `Visitor`.
+struct Deserializer<'t, R> {
+    bytes: R,
+    keys_table: DecodeKeysTables<'t>,
+    /// Header that was read by `deserialize_option` but not consumed yet
+    peeked: Option<ValueHeader>,
+}
+
+impl<'de, 't, R: StrRead<'de>> Deserializer<'t, R> {
+    fn next_header(&mut self) -> Result<ValueHeader, FracJsonError> {
+        match self.peeked.take() {
+            Some(header) => Ok(header),
+            None => read_value_header(&mut self.bytes),
+        }
+    }
+
+    fn read_str(&mut self, length: usize) -> Result<Cow<'de, str>, FracJsonError> {
+        if length == 0 {
+            return Ok(Cow::Borrowed(""));
+        }
+        self.bytes.check_string_length(length)?;
+        return self.bytes.read_str(length);
+    }
+
+    fn read_key(&mut self) -> Result<KeyDeserializer<'de, '_>, FracJsonError> {
+        let key_offset = self.bytes.offset();
+        let key = match read_key_ref(&mut self.bytes)? {
+            KeyRef::Immediate(length) => {
+                let key = self.bytes.read_str(length)?;
+                self.keys_table.on_immediate_key(&key);
+                Key::Input(key)
+            }
+            KeyRef::Local(index) => {
+                Key::Table(self.keys_table.lookup_local_index(index, key_offset)?)
+            }
+            KeyRef::Global(index) => {
+                Key::Table(self.keys_table.lookup_global_index(index, key_offset)?)
+            }
+        };
+        return Ok(KeyDeserializer { key });
+    }
+
+    /// Runs `visit` on an object or array with `length` entries, which has to consume all
+    /// of them.
+    fn visit_container<T>(
+        &mut self,
+        length: usize,
+        visit: impl FnOnce(&mut Self, &mut usize) -> Result<T, FracJsonError>,
+    ) -> Result<T, FracJsonError> {
+        if length > 0 {
+            self.bytes.enter_container(length)?;
+        }
+        let mut remaining = length;
+        let value = visit(self, &mut remaining)?;
+        if remaining > 0 {
+            return Err(de::Error::invalid_length(length, &"fewer entries"));
+        }
+        if length > 0 {
+            self.bytes.exit_container();
+        }
+        return Ok(value);
+    }
+}
+
+fn unexpected(header: &ValueHeader) -> Unexpected<'_> {
+    match *header {
+        ValueHeader::Null => Unexpected::Unit,
+        ValueHeader::Bool(b) => Unexpected::Bool(b),
+        ValueHeader::Int(n) => Unexpected::Signed(n),
+        ValueHeader::UInt(n) => Unexpected::Unsigned(n),
+        ValueHeader::Float(f) => Unexpected::Float(f as f64),
+        ValueHeader::Double(f) => Unexpected::Float(f),
+        ValueHeader::String(_) => Unexpected::Other("string"),
+        ValueHeader::Object(_) => Unexpected::Map,
+        ValueHeader::Array(_) => Unexpected::Seq,
+    }
+}
+
+impl<'de, R: StrRead<'de>> de::Deserializer<'de> for &mut Deserializer<'_, R> {
+    type Error = FracJsonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FracJsonError> {
+        match self.next_header()? {
+            ValueHeader::Null => visitor.visit_unit(),
+            ValueHeader::Bool(b) => visitor.visit_bool(b),
+            ValueHeader::Int(n) => visitor.visit_i64(n),
+            ValueHeader::UInt(n) => visitor.visit_u64(n),
+            ValueHeader::Float(f) => visitor.visit_f32(f),
+            ValueHeader::Double(f) => visitor.visit_f64(f),
+            ValueHeader::String(length) => match self.read_str(length)? {
+                Cow::Borrowed(string) => visitor.visit_borrowed_str(string),
+                Cow::Owned(string) => visitor.visit_string(string),
+            },
+            ValueHeader::Object(length) => self.visit_container(length, |de, remaining| {
+                visitor.visit_map(MapAccess { de, remaining })
+            }),
+            ValueHeader::Array(length) => self.visit_container(length, |de, remaining| {
+                visitor.visit_seq(SeqAccess { de, remaining })
+            }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FracJsonError> {
+        match self.next_header()? {
+            ValueHeader::Null => visitor.visit_none(),
+            header => {
+                self.peeked = Some(header);
+                visitor.visit_some(self)
+            }
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Enums are externally tagged, either as the name of a unit variant or as an object
+    /// with a single entry.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        match self.next_header()? {
+            ValueHeader::String(length) => {
+                let key = KeyDeserializer {
+                    key: Key::Input(self.read_str(length)?),
+                };
+                de::Deserializer::deserialize_enum(key, name, variants, visitor)
+            }
+            ValueHeader::Object(1) => self.visit_container(1, |de, remaining| {
+                *remaining = 0;
+                visitor.visit_enum(EnumAccess { de })
+            }),
+            ValueHeader::Object(length) => Err(de::Error::invalid_length(
+                length,
+                &"object with a single entry",
+            )),
+            header => Err(de::Error::invalid_type(
+                unexpected(&header),
+                &"string or object with a single entry",
+            )),
+        }
+    }
+
+    /// Skips the value without decoding strings or building containers.
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        let header = self.next_header()?;
+        skip_value_contents(header, &mut self.bytes, &mut self.keys_table)?;
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct MapAccess<'d, 't, R> {
+    de: &'d mut Deserializer<'t, R>,
+    remaining: &'d mut usize,
+}
+
+impl<'de, R: StrRead<'de>> de::MapAccess<'de> for MapAccess<'_, '_, R> {
+    type Error = FracJsonError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, FracJsonError> {
+        if *self.remaining == 0 {
+            return Ok(None);
+        }
+        *self.remaining -= 1;
+        return seed.deserialize(self.de.read_key()?).map(Some);
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, FracJsonError> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(*self.remaining)
+    }
+}
+
+struct SeqAccess<'d, 't, R> {
+    de: &'d mut Deserializer<'t, R>,
+    remaining: &'d mut usize,
+}
+
+impl<'de, R: StrRead<'de>> de::SeqAccess<'de> for SeqAccess<'_, '_, R> {
+    type Error = FracJsonError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, FracJsonError> {
+        if *self.remaining == 0 {
+            return Ok(None);
+        }
+        *self.remaining -= 1;
+        return seed.deserialize(&mut *self.de).map(Some);
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(*self.remaining)
+    }
+}
+
+/// Object with a single entry, whose key is the variant name.
+struct EnumAccess<'d, 't, R> {
+    de: &'d mut Deserializer<'t, R>,
+}
+
+impl<'de, 'd, 't, R: StrRead<'de>> de::EnumAccess<'de> for EnumAccess<'d, 't, R> {
+    type Error = FracJsonError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), FracJsonError> {
+        let variant = seed.deserialize(self.de.read_key()?)?;
+        return Ok((variant, self));
+    }
+}
+
+impl<'de, R: StrRead<'de>> de::VariantAccess<'de> for EnumAccess<'_, '_, R> {
+    type Error = FracJsonError;
+
+    fn unit_variant(self) -> Result<(), FracJsonError> {
+        <()>::deserialize(self.de)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, FracJsonError> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
+enum Key<'de, 'k> {
+    /// Immediate key, borrowed from the input if possible
+    Input(Cow<'de, str>),
+    /// Key from the local or global keys table
+    Table(&'k str),
+}
+
+impl Key<'_, '_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Key::Input(key) => key,
+            Key::Table(key) => key,
+        }
+    }
+}
+
+/// Object keys are always strings. Like serde_json, numbers and bools are parsed from them
+/// when requested, to support maps with non-string keys.
+struct KeyDeserializer<'de, 'k> {
+    key: Key<'de, 'k>,
+}
+
+macro_rules! deserialize_parsed_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FracJsonError> {
+                match self.key.as_str().parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer<'de, '_> {
+    type Error = FracJsonError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FracJsonError> {
+        match self.key {
+            Key::Input(Cow::Borrowed(key)) => visitor.visit_borrowed_str(key),
+            Key::Input(Cow::Owned(key)) => visitor.visit_string(key),
+            Key::Table(key) => visitor.visit_str(key),
+        }
+    }
+
+    deserialize_parsed_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FracJsonError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Only unit variants can be keys
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FracJsonError> {
+        match self.key {
+            Key::Input(Cow::Borrowed(key)) => {
+                visitor.visit_enum(BorrowedStrDeserializer::<FracJsonError>::new(key))
+            }
+            Key::Input(Cow::Owned(key)) => {
+                let key: StringDeserializer<FracJsonError> = key.into_deserializer();
+                visitor.visit_enum(key)
+            }
+            Key::Table(key) => visitor.visit_enum(StrDeserializer::<FracJsonError>::new(key)),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
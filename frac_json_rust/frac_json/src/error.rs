@@ -0,0 +1,316 @@
+use std::fmt;
+
+/// Format or configuration limit that was exceeded while encoding or decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// Length of an object key in bytes
+    KeyLength,
+    /// Number of keys in a keys table
+    KeysTableSize,
+    /// Length of a string, object or array
+    ValueLength,
+    /// Nesting depth of objects and arrays
+    Depth,
+    /// Size of the decompressed data
+    DecompressedSize,
+    /// Length of a string value
+    StringLength,
+    /// Number of entries of an object or array
+    ContainerLength,
+    /// Number of object and array entries in the whole document
+    TotalElements,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Limit::KeyLength => "key length",
+            Limit::KeysTableSize => "keys table size",
+            Limit::ValueLength => "value length",
+            Limit::Depth => "nesting depth",
+            Limit::DecompressedSize => "decompressed size",
+            Limit::StringLength => "string length",
+            Limit::ContainerLength => "container length",
+            Limit::TotalElements => "total elements",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Error returned by all fallible frac_json operations.
+///
+/// Decoding errors carry the byte `offset` at which they occurred. Offsets are relative to
+/// the start of the file, with compressed files counting the header followed by the
+/// decompressed bytes. Encoding errors carry the offset in the (uncompressed) output.
+#[derive(Debug)]
+pub enum FracJsonError {
+    InvalidMagic {
+        offset: usize,
+        magic: [u8; 2],
+    },
+    UnsupportedVersion {
+        offset: usize,
+        version: u8,
+    },
+    UnsupportedKeysTableConfig {
+        offset: usize,
+        config: u8,
+    },
+    UnexpectedEof {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    UnknownGlobalKeyIndex {
+        offset: usize,
+        index: usize,
+        table_size: usize,
+    },
+    UnknownLocalKeyIndex {
+        offset: usize,
+        index: usize,
+        table_size: usize,
+    },
+    /// A reserved data type or key type byte was encountered
+    ReservedType {
+        offset: usize,
+        type_byte: u8,
+    },
+    InvalidKeyIndex {
+        offset: usize,
+        bytes: [u8; 3],
+    },
+    InvalidNumber {
+        offset: usize,
+    },
+    InvalidUtf8 {
+        offset: usize,
+    },
+    /// An entry of the index of an archive points outside of the archive or is out of order
+    InvalidArchiveIndex {
+        offset: usize,
+    },
+    /// Reading a session stream was started at a record that isn't a reset point
+    NotAResetPoint {
+        offset: usize,
+    },
+    LimitExceeded {
+        offset: usize,
+        limit: Limit,
+        value: usize,
+        max: usize,
+    },
+    /// The file was encoded with a global keys table whose fingerprint is `expected`, but
+    /// is decoded with a table whose fingerprint is `actual`, or without one
+    KeysTableMismatch {
+        offset: usize,
+        expected: u32,
+        actual: Option<u32>,
+    },
+    /// The file was encoded with a `GlobalKeysTableSet` whose `layer` had the fingerprint
+    /// `expected`, but is decoded with a layer with fingerprint `actual` there. `None` means
+    /// the layer is missing on one side.
+    KeysTableLayerMismatch {
+        offset: usize,
+        layer: usize,
+        expected: Option<u32>,
+        actual: Option<u32>,
+    },
+    /// The checksum stored in a keys table file doesn't match its contents
+    ChecksumMismatch {
+        offset: usize,
+        expected: u32,
+        actual: u32,
+    },
+    MissingZstdDict,
+    InvalidOptions(String),
+    Compression(std::io::Error),
+    /// Reading from or writing to the underlying stream failed
+    Io(std::io::Error),
+    /// Error reported by a serde `Serialize` or `Deserialize` implementation
+    Serde(String),
+}
+
+impl FracJsonError {
+    /// Byte offset at which the error occurred, if it is tied to a position.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            FracJsonError::InvalidMagic { offset, .. }
+            | FracJsonError::UnsupportedVersion { offset, .. }
+            | FracJsonError::UnsupportedKeysTableConfig { offset, .. }
+            | FracJsonError::UnexpectedEof { offset, .. }
+            | FracJsonError::UnknownGlobalKeyIndex { offset, .. }
+            | FracJsonError::UnknownLocalKeyIndex { offset, .. }
+            | FracJsonError::ReservedType { offset, .. }
+            | FracJsonError::InvalidKeyIndex { offset, .. }
+            | FracJsonError::InvalidNumber { offset }
+            | FracJsonError::InvalidUtf8 { offset }
+            | FracJsonError::InvalidArchiveIndex { offset }
+            | FracJsonError::NotAResetPoint { offset }
+            | FracJsonError::KeysTableMismatch { offset, .. }
+            | FracJsonError::KeysTableLayerMismatch { offset, .. }
+            | FracJsonError::ChecksumMismatch { offset, .. }
+            | FracJsonError::LimitExceeded { offset, .. } => Some(*offset),
+            FracJsonError::MissingZstdDict
+            | FracJsonError::InvalidOptions(_)
+            | FracJsonError::Compression(_)
+            | FracJsonError::Io(_)
+            | FracJsonError::Serde(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for FracJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FracJsonError::InvalidMagic { offset, magic } => {
+                write!(f, "Invalid magic {:?} at offset {}", magic, offset)
+            }
+            FracJsonError::UnsupportedVersion { offset, version } => {
+                write!(f, "Unsupported version {} at offset {}", version, offset)
+            }
+            FracJsonError::UnsupportedKeysTableConfig { offset, config } => write!(
+                f,
+                "Unsupported keys table config {} at offset {}",
+                config, offset
+            ),
+            FracJsonError::UnexpectedEof {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Cannot read {} bytes at offset {}! Only {} bytes left",
+                needed, offset, available
+            ),
+            FracJsonError::UnknownGlobalKeyIndex {
+                offset,
+                index,
+                table_size,
+            } => write!(
+                f,
+                "Index {} is not in GlobalKeysTable of size {} (offset {})",
+                index, table_size, offset
+            ),
+            FracJsonError::UnknownLocalKeyIndex {
+                offset,
+                index,
+                table_size,
+            } => write!(
+                f,
+                "Index {} is not in LocalKeysTable of size {} (offset {})",
+                index, table_size, offset
+            ),
+            FracJsonError::ReservedType { offset, type_byte } => write!(
+                f,
+                "Reserved type byte {:02X} at offset {}",
+                type_byte, offset
+            ),
+            FracJsonError::InvalidKeyIndex { offset, bytes } => write!(
+                f,
+                "Invalid key index bytes {:02X} {:02X} {:02X} at offset {}",
+                bytes[0], bytes[1], bytes[2], offset
+            ),
+            FracJsonError::InvalidNumber { offset } => {
+                write!(f, "Number is not an integer or float (offset {})", offset)
+            }
+            FracJsonError::InvalidUtf8 { offset } => {
+                write!(f, "Invalid UTF-8 in string at offset {}", offset)
+            }
+            FracJsonError::InvalidArchiveIndex { offset } => {
+                write!(f, "Invalid archive index entry at offset {}", offset)
+            }
+            FracJsonError::NotAResetPoint { offset } => {
+                write!(f, "Record at offset {} is not a reset point", offset)
+            }
+            FracJsonError::LimitExceeded {
+                offset,
+                limit,
+                value,
+                max,
+            } => write!(
+                f,
+                "{} {} exceeds maximum of {} at offset {}",
+                limit, value, max, offset
+            ),
+            FracJsonError::KeysTableMismatch {
+                offset,
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "File was encoded with keys table {:08X}, but decoded with keys table {:08X} (offset {})",
+                expected, actual, offset
+            ),
+            FracJsonError::KeysTableMismatch {
+                offset,
+                expected,
+                actual: None,
+            } => write!(
+                f,
+                "File was encoded with keys table {:08X}, but decoded without a keys table (offset {})",
+                expected, offset
+            ),
+            FracJsonError::KeysTableLayerMismatch {
+                offset,
+                layer,
+                expected,
+                actual,
+            } => match (expected, actual) {
+                (Some(expected), Some(actual)) => write!(
+                    f,
+                    "File was encoded with keys table {:08X} as layer {}, but decoded with keys table {:08X} (offset {})",
+                    expected, layer, actual, offset
+                ),
+                (Some(expected), None) => write!(
+                    f,
+                    "File was encoded with keys table {:08X} as layer {}, but decoded without it (offset {})",
+                    expected, layer, offset
+                ),
+                (None, Some(actual)) => write!(
+                    f,
+                    "File was decoded with keys table {:08X} as layer {}, but encoded without it (offset {})",
+                    actual, layer, offset
+                ),
+                (None, None) => write!(f, "Keys table layer {} mismatch (offset {})", layer, offset),
+            },
+            FracJsonError::ChecksumMismatch {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Checksum mismatch at offset {}: expected {:08X}, got {:08X}",
+                offset, expected, actual
+            ),
+            FracJsonError::MissingZstdDict => {
+                write!(f, "zstd_dict is required but not provided")
+            }
+            FracJsonError::InvalidOptions(message) => write!(f, "{}", message),
+            FracJsonError::Compression(error) => write!(f, "zstd error: {}", error),
+            FracJsonError::Io(error) => write!(f, "I/O error: {}", error),
+            FracJsonError::Serde(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for FracJsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FracJsonError::Compression(error) | FracJsonError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl serde::ser::Error for FracJsonError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        FracJsonError::Serde(msg.to_string())
+    }
+}
+
+impl serde::de::Error for FracJsonError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        FracJsonError::Serde(msg.to_string())
+    }
+}
@@ -0,0 +1,146 @@
+use serde_json::Value;
+
+use crate::{codec::Decoder, cursor::FracCursor, error::FracJsonError, options::DecodeOptions};
+
+/// Decodes only the value at the JSON Pointer (RFC 6901) `pointer`, for example
+/// `"/data/children/0/title"`. Returns `None` if there is no value at that path.
+pub fn extract(
+    frac_json_bytes: &[u8],
+    pointer: &str,
+    options: &DecodeOptions,
+) -> Result<Option<Value>, FracJsonError> {
+    return Decoder::from_options(options)?.extract(frac_json_bytes, pointer);
+}
+
+/// Like `extract` for several pointers at once, reading the file only once. Results are in
+/// the same order as `pointers`.
+pub fn extract_many(
+    frac_json_bytes: &[u8],
+    pointers: &[&str],
+    options: &DecodeOptions,
+) -> Result<Vec<Option<Value>>, FracJsonError> {
+    return Decoder::from_options(options)?.extract_many(frac_json_bytes, pointers);
+}
+
+/// Splits a JSON Pointer into its unescaped reference tokens.
+pub(crate) fn parse_pointer(pointer: &str) -> Result<Vec<String>, FracJsonError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(tokens) = pointer.strip_prefix('/') else {
+        return Err(FracJsonError::InvalidOptions(format!(
+            "JSON pointer {:?} has to start with '/'",
+            pointer
+        )));
+    };
+    return Ok(tokens
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect());
+}
+
+/// Array index of a reference token. Leading zeros and "-" (the element after the last)
+/// never match.
+pub(crate) fn parse_array_index(token: &str) -> Option<usize> {
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    if !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    return token.parse().ok();
+}
+
+/// Pointers that share a prefix share a node.
+#[derive(Default)]
+struct PathNode {
+    /// Indices of the pointers that end at this node
+    ends: Vec<usize>,
+    children: Vec<(String, PathNode)>,
+}
+
+impl PathNode {
+    fn insert(&mut self, tokens: Vec<String>, pointer_index: usize) {
+        let mut node = self;
+        for token in tokens {
+            let child_index = match node.children.iter().position(|(t, _)| *t == token) {
+                Some(child_index) => child_index,
+                None => {
+                    node.children.push((token, PathNode::default()));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[child_index].1;
+        }
+        node.ends.push(pointer_index);
+    }
+
+    fn child(&self, token: &str) -> Option<&PathNode> {
+        self.children
+            .iter()
+            .find(|(t, _)| t == token)
+            .map(|(_, child)| child)
+    }
+}
+
+pub(crate) fn extract_many_from(
+    root: FracCursor,
+    pointers: &[&str],
+) -> Result<Vec<Option<Value>>, FracJsonError> {
+    let mut paths = PathNode::default();
+    for (i, pointer) in pointers.iter().enumerate() {
+        paths.insert(parse_pointer(pointer)?, i);
+    }
+    let mut results = vec![None; pointers.len()];
+    extract_node(root, &paths, &mut results)?;
+    return Ok(results);
+}
+
+/// Decodes the values of all pointers ending at `node` and descends into the entries of
+/// `cursor` that continue a path. Everything else is skipped.
+fn extract_node(
+    cursor: FracCursor,
+    node: &PathNode,
+    results: &mut [Option<Value>],
+) -> Result<(), FracJsonError> {
+    if !node.ends.is_empty() {
+        let value = cursor.to_value()?;
+        for &pointer_index in &node.ends {
+            results[pointer_index] = Some(value.clone());
+        }
+    }
+    if node.children.is_empty() {
+        return Ok(());
+    }
+    if let Some(entries) = cursor.iter_entries()? {
+        // duplicate keys: only the last entry counts, same as `FracCursor::get`
+        let mut children = vec![None; node.children.len()];
+        for entry in entries {
+            let (key, value) = entry?;
+            if let Some(child_index) = node.children.iter().position(|(token, _)| *token == key) {
+                children[child_index] = Some(value);
+            }
+        }
+        for (value, (_, child)) in children.into_iter().zip(&node.children) {
+            if let Some(value) = value {
+                extract_node(value, child, results)?;
+            }
+        }
+    } else if let Some(elements) = cursor.iter_elements()? {
+        let max_index = node
+            .children
+            .iter()
+            .filter_map(|(token, _)| parse_array_index(token))
+            .max();
+        let Some(max_index) = max_index else {
+            return Ok(());
+        };
+        for (i, element) in elements.enumerate().take(max_index + 1) {
+            let element = element?;
+            if let Some(child) = node.child(&i.to_string()) {
+                extract_node(element, child, results)?;
+            }
+        }
+    }
+    return Ok(());
+}
@@ -1,11 +1,11 @@
+use std::io::{Read, Write};
+
 use serde_json::Value;
-use zstd::bulk::{compress, Compressor, Decompressor};
 
 use crate::{
-    byte_stream::ByteReader,
-    config::Config,
-    json_types::value::{read_value, write_value},
-    keys_table::{DecodeKeysTables, EncodeKeysTables, GlobalKeysTable},
+    codec::{Decoder, Encoder},
+    error::FracJsonError,
+    options::{DecodeOptions, EncodeOptions},
 };
 
 pub fn encode(
@@ -13,91 +13,55 @@ pub fn encode(
     global_keys_table_bytes: Option<&Vec<u8>>,
     compression_level: Option<i32>,
     zstd_dict: Option<&Vec<u8>>,
-) -> Result<Vec<u8>, String> {
-    if zstd_dict.is_some() && compression_level.is_none() {
-        return Err("zstd_dict is set but compression_level is not set".to_string());
-    }
-    let mut header_bytes = Vec::with_capacity(3);
-    let mut json_value_bytes = Vec::with_capacity(1024);
-
-    let global_keys_table = match global_keys_table_bytes {
-        Some(bytes) => match GlobalKeysTable::read_keys_table(&mut ByteReader::make(bytes)) {
-            Ok(v) => Some(v),
-            Err(e) => return Err(e),
-        },
-        None => None,
+) -> Result<Vec<u8>, FracJsonError> {
+    let options = EncodeOptions {
+        global_keys_table_bytes: global_keys_table_bytes.map(|bytes| bytes.as_slice()),
+        compression_level,
+        zstd_dict: zstd_dict.map(|dict| dict.as_slice()),
+        ..Default::default()
     };
-    let mut keys_table = EncodeKeysTables::make(Vec::new(), global_keys_table);
-    write_value(json, &mut json_value_bytes, &mut keys_table)?;
-
-    let config = Config::make(compression_level.is_some(), zstd_dict.is_some());
-    config.write_header(&mut header_bytes);
+    return Encoder::from_options(&options)?.encode(json);
+}
 
-    let mut file_bytes: Vec<u8> = Vec::new();
-    file_bytes.extend(header_bytes);
-    match compression_level {
-        None => {
-            file_bytes.extend(json_value_bytes);
-        }
-        Some(level) => {
-            let compressed_bytes = match zstd_dict {
-                Some(dict) => Compressor::with_dictionary(level, dict)
-                    .map_err(|e| e.to_string())?
-                    .compress(&json_value_bytes)
-                    .map_err(|e| e.to_string())?,
-                None => compress(&json_value_bytes, level).map_err(|e| e.to_string())?
-            };
-            file_bytes.extend(compressed_bytes);
-        }
-    }
-    return Ok(file_bytes);
+/// Encodes `json` directly into `writer`.
+///
+/// Output is buffered internally and compressed as a zstd stream, so memory usage does
+/// not grow with the size of the document.
+pub fn encode_to_writer<W: Write>(
+    json: &Value,
+    writer: W,
+    options: &EncodeOptions,
+) -> Result<(), FracJsonError> {
+    return Encoder::from_options(options)?.encode_to_writer(json, writer);
 }
 
 pub fn decode(
-    frac_json_bytes: &Vec<u8>,
+    frac_json_bytes: &[u8],
     global_keys_table_bytes: Option<&Vec<u8>>,
     zstd_dict: Option<&Vec<u8>>,
-) -> Result<Value, String> {
-    let mut bytes = ByteReader::make(frac_json_bytes);
-    let config = Config::read_header(&mut bytes)?;
-    if config.uses_external_dict && zstd_dict.is_none() {
-        return Err("zstd_dict is required but not provided".to_string());
-    }
-    let decompressed_bytes: Vec<u8>;
-    if config.is_zstd_compressed {
-        let compressed_bytes = bytes.read_remaining()?;
-        let buffer_size = compressed_bytes.len() * 25 + 1024;
-        let dict = if config.uses_external_dict { zstd_dict } else { None };
-        decompressed_bytes = try_decompress(&compressed_bytes, buffer_size, dict)?;
-        bytes = ByteReader::make(&decompressed_bytes);
-    }
-    let global_keys_table = match global_keys_table_bytes {
-        Some(bytes) => match GlobalKeysTable::read_keys_table(&mut ByteReader::make(bytes)) {
-            Ok(v) => Some(v),
-            Err(e) => return Err(e),
-        },
-        None => None,
+) -> Result<Value, FracJsonError> {
+    let options = DecodeOptions {
+        global_keys_table_bytes: global_keys_table_bytes.map(|bytes| bytes.as_slice()),
+        zstd_dict: zstd_dict.map(|dict| dict.as_slice()),
+        ..Default::default()
     };
-    let mut keys_table = DecodeKeysTables::make(global_keys_table);
+    return decode_with_options(frac_json_bytes, &options);
+}
 
-    return read_value(&mut bytes, &mut keys_table);
+pub fn decode_with_options(
+    frac_json_bytes: &[u8],
+    options: &DecodeOptions,
+) -> Result<Value, FracJsonError> {
+    return Decoder::from_options(options)?.decode(frac_json_bytes);
 }
 
-fn try_decompress(bytes: &[u8], buffer_size: usize, dict: Option<&Vec<u8>>) -> Result<Vec<u8>, String> {
-    const max_buffer_size: usize = 10 * 1024 * 1024;
-    let mut decompressor = match dict {
-        Some(d) => Decompressor::with_dictionary(d).map_err(|e| e.to_string())?,
-        None => Decompressor::new().map_err(|e| e.to_string())?,
-    };
-    let result = decompressor.decompress(bytes, buffer_size);
-    match result {
-        Ok(v) => Ok(v),
-        Err(e) => {
-            let next_buffer_size = buffer_size * 10;
-            if next_buffer_size < max_buffer_size {
-                return try_decompress(bytes, next_buffer_size, dict);
-            }
-            return Err(e.to_string());
-        }
-    }
+/// Decodes a file directly from `reader`, without reading it into memory first.
+///
+/// zstd compressed files are decompressed while decoding. Bytes after the encoded value
+/// may or may not have been consumed from `reader`.
+pub fn decode_from_reader<R: Read>(
+    reader: R,
+    options: &DecodeOptions,
+) -> Result<Value, FracJsonError> {
+    return Decoder::from_options(options)?.decode_from_reader(reader);
 }
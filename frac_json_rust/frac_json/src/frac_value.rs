@@ -0,0 +1,274 @@
+use std::borrow::Cow;
+
+use serde::{
+    ser::{SerializeMap, SerializeSeq},
+    Serialize, Serializer,
+};
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    byte_stream::StrRead,
+    codec::{DecodeBody, Decoder},
+    error::FracJsonError,
+    json_types::{
+        object::{read_key_ref, KeyRef},
+        value::{read_value_header, ValueHeader},
+    },
+    keys_table::{BorrowedDecodeKeysTables, DecodeKeysTables, GlobalKeysTable},
+};
+
+/// Decoded JSON value whose strings and keys borrow from the decoded bytes and the global
+/// keys table where possible.
+///
+/// Strings are only owned when they can't be borrowed, for example because the file is
+/// zstd compressed or invalid UTF-8 was replaced with `Utf8Validation::Lossy`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FracValue<'a> {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(Cow<'a, str>),
+    Array(Vec<FracValue<'a>>),
+    /// Entries in the order they were encoded
+    Object(Vec<(Cow<'a, str>, FracValue<'a>)>),
+}
+
+impl<'a> FracValue<'a> {
+    pub fn is_null(&self) -> bool {
+        matches!(self, FracValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            FracValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<&Number> {
+        match self {
+            FracValue::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_number()?.as_i64()
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_number()?.as_u64()
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_number()?.as_f64()
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FracValue::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[FracValue<'a>]> {
+        match self {
+            FracValue::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(Cow<'a, str>, FracValue<'a>)]> {
+        match self {
+            FracValue::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+
+    /// Value of `key` if this is an object. Like `serde_json::Value`, the last entry wins
+    /// if a key occurs more than once.
+    pub fn get(&self, key: &str) -> Option<&FracValue<'a>> {
+        self.as_object()?
+            .iter()
+            .rev()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Copies all borrowed strings and keys, so the value no longer borrows the input.
+    pub fn into_owned(self) -> FracValue<'static> {
+        match self {
+            FracValue::Null => FracValue::Null,
+            FracValue::Bool(b) => FracValue::Bool(b),
+            FracValue::Number(number) => FracValue::Number(number),
+            FracValue::String(string) => FracValue::String(Cow::Owned(string.into_owned())),
+            FracValue::Array(array) => {
+                FracValue::Array(array.into_iter().map(FracValue::into_owned).collect())
+            }
+            FracValue::Object(object) => FracValue::Object(
+                object
+                    .into_iter()
+                    .map(|(key, value)| (Cow::Owned(key.into_owned()), value.into_owned()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<FracValue<'_>> for Value {
+    fn from(value: FracValue<'_>) -> Value {
+        match value {
+            FracValue::Null => Value::Null,
+            FracValue::Bool(b) => Value::Bool(b),
+            FracValue::Number(number) => Value::Number(number),
+            FracValue::String(string) => Value::String(string.into_owned()),
+            FracValue::Array(array) => Value::Array(array.into_iter().map(Value::from).collect()),
+            FracValue::Object(object) => {
+                let mut map = Map::with_capacity(object.len());
+                for (key, value) in object {
+                    map.insert(key.into_owned(), Value::from(value));
+                }
+                Value::Object(map)
+            }
+        }
+    }
+}
+
+impl Serialize for FracValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FracValue::Null => serializer.serialize_unit(),
+            FracValue::Bool(b) => serializer.serialize_bool(*b),
+            FracValue::Number(number) => number.serialize(serializer),
+            FracValue::String(string) => serializer.serialize_str(string),
+            FracValue::Array(array) => {
+                let mut seq = serializer.serialize_seq(Some(array.len()))?;
+                for value in array {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            FracValue::Object(object) => {
+                let mut map = serializer.serialize_map(Some(object.len()))?;
+                for (key, value) in object {
+                    map.serialize_entry(key.as_ref(), value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// Decodes a file without a global keys table or zstd dictionary into a `FracValue`
+/// borrowing from `frac_json_bytes`.
+pub fn decode_borrowed(frac_json_bytes: &[u8]) -> Result<FracValue<'_>, FracJsonError> {
+    let body = FracValueBody { global_table: None };
+    return Decoder::new(None, None).decode_body(frac_json_bytes, body);
+}
+
+pub(crate) struct FracValueBody<'a> {
+    pub global_table: Option<&'a GlobalKeysTable>,
+}
+
+impl<'a> DecodeBody<'a> for FracValueBody<'a> {
+    type Output = FracValue<'a>;
+
+    fn read_body<R: StrRead<'a>>(
+        self,
+        mut bytes: R,
+        _keys_table: DecodeKeysTables,
+    ) -> Result<FracValue<'a>, FracJsonError> {
+        let mut keys_table = BorrowedDecodeKeysTables::make(self.global_table);
+        read_frac_value(&mut bytes, &mut keys_table)
+    }
+}
+
+fn read_frac_value<'a, R: StrRead<'a>>(
+    bytes: &mut R,
+    keys_table: &mut BorrowedDecodeKeysTables<'a>,
+) -> Result<FracValue<'a>, FracJsonError> {
+    let value = match read_value_header(bytes)? {
+        ValueHeader::Null => FracValue::Null,
+        ValueHeader::Bool(b) => FracValue::Bool(b),
+        ValueHeader::Int(n) => FracValue::Number(n.into()),
+        ValueHeader::UInt(n) => FracValue::Number(n.into()),
+        ValueHeader::Float(f) => float_value(f as f64),
+        ValueHeader::Double(f) => float_value(f),
+        ValueHeader::String(0) => FracValue::String(Cow::Borrowed("")),
+        ValueHeader::String(length) => {
+            bytes.check_string_length(length)?;
+            FracValue::String(bytes.read_str(length)?)
+        }
+        ValueHeader::Object(length) => read_frac_object(bytes, length, keys_table)?,
+        ValueHeader::Array(length) => read_frac_array(bytes, length, keys_table)?,
+    };
+    return Ok(value);
+}
+
+/// NaN and infinity become null, same as `Value::from`.
+fn float_value<'a>(f: f64) -> FracValue<'a> {
+    match Number::from_f64(f) {
+        Some(number) => FracValue::Number(number),
+        None => FracValue::Null,
+    }
+}
+
+fn read_frac_object<'a, R: StrRead<'a>>(
+    bytes: &mut R,
+    length: usize,
+    keys_table: &mut BorrowedDecodeKeysTables<'a>,
+) -> Result<FracValue<'a>, FracJsonError> {
+    if length == 0 {
+        return Ok(FracValue::Object(Vec::new()));
+    }
+    bytes.enter_container(length)?;
+    // every entry takes at least two bytes, don't trust larger lengths for preallocation
+    let mut object = Vec::with_capacity(bytes.capacity_hint(length, 2));
+    for _ in 0..length {
+        let key = read_frac_key(bytes, keys_table)?;
+        let value = read_frac_value(bytes, keys_table)?;
+        object.push((key, value));
+    }
+    bytes.exit_container();
+    return Ok(FracValue::Object(object));
+}
+
+fn read_frac_array<'a, R: StrRead<'a>>(
+    bytes: &mut R,
+    length: usize,
+    keys_table: &mut BorrowedDecodeKeysTables<'a>,
+) -> Result<FracValue<'a>, FracJsonError> {
+    if length == 0 {
+        return Ok(FracValue::Array(Vec::new()));
+    }
+    bytes.enter_container(length)?;
+    // every entry takes at least one byte, don't trust larger lengths for preallocation
+    let mut array = Vec::with_capacity(bytes.capacity_hint(length, 1));
+    for _ in 0..length {
+        array.push(read_frac_value(bytes, keys_table)?);
+    }
+    bytes.exit_container();
+    return Ok(FracValue::Array(array));
+}
+
+fn read_frac_key<'a, R: StrRead<'a>>(
+    bytes: &mut R,
+    keys_table: &mut BorrowedDecodeKeysTables<'a>,
+) -> Result<Cow<'a, str>, FracJsonError> {
+    let key_offset = bytes.offset();
+    match read_key_ref(bytes)? {
+        KeyRef::Immediate(length) => {
+            let key = bytes.read_str(length)?;
+            keys_table.on_immediate_key(key.clone());
+            return Ok(key);
+        }
+        KeyRef::Local(index) => {
+            return Ok(keys_table.lookup_local_index(index, key_offset)?.clone());
+        }
+        KeyRef::Global(index) => {
+            return Ok(Cow::Borrowed(
+                keys_table.lookup_global_index(index, key_offset)?,
+            ));
+        }
+    }
+}
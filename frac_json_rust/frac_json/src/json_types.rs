@@ -1,5 +1,5 @@
 mod array;
 mod data_type;
-mod object;
+pub mod object;
 mod string;
 pub mod value;
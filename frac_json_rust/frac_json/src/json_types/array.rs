@@ -2,31 +2,51 @@ use serde_json::Value;
 
 use crate::{
     byte_stream::{ByteReader, ByteWriter},
-    keys_table::{DecodeKeysTables, EncodeKeysTables},
+    error::FracJsonError,
+    keys_table::{DecodeKeysTables, EncodeKeysTables, SkipKeysTables},
 };
 
-use super::value::{read_value, write_value};
+use super::value::{read_value, skip_value, write_value};
 
-pub fn read_array(
-    bytes: &mut ByteReader,
+pub fn read_array<R: ByteReader>(
+    bytes: &mut R,
     length: usize,
     keys_table: &mut DecodeKeysTables,
-) -> Result<Value, String> {
+) -> Result<Value, FracJsonError> {
     if length == 0 {
         return Ok(Value::Array(Vec::new()));
     }
-    let mut array = Vec::with_capacity(length);
+    bytes.enter_container(length)?;
+    // every entry takes at least one byte, don't trust larger lengths for preallocation
+    let mut array = Vec::with_capacity(bytes.capacity_hint(length, 1));
     for _ in 0..length {
         array.push(read_value(bytes, keys_table)?);
     }
+    bytes.exit_container();
     return Ok(Value::Array(array));
 }
 
+pub fn skip_array<R: ByteReader, K: SkipKeysTables>(
+    bytes: &mut R,
+    length: usize,
+    keys_table: &mut K,
+) -> Result<(), FracJsonError> {
+    if length == 0 {
+        return Ok(());
+    }
+    bytes.enter_container(length)?;
+    for _ in 0..length {
+        skip_value(bytes, keys_table)?;
+    }
+    bytes.exit_container();
+    return Ok(());
+}
+
 pub fn write_array<'a, 'b: 'a, W: ByteWriter>(
     array: &'b Vec<Value>,
     bytes: &mut W,
     keys_table: &mut EncodeKeysTables<'a>,
-) -> Result<(), String> {
+) -> Result<(), FracJsonError> {
     for value in array {
         write_value(value, bytes, keys_table)?;
     }
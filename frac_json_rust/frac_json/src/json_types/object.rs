@@ -1,11 +1,14 @@
+use std::borrow::Cow;
+
 use serde_json::{Map, Value};
 
 use crate::{
     byte_stream::{ByteReader, ByteWriter},
-    keys_table::{DecodeKeysTables, EncodeKeysTables, MAX_KEY_LENGTH},
+    error::{FracJsonError, Limit},
+    keys_table::{DecodeKeysTables, EncodeKeysTables, SkipKeysTables, MAX_KEY_LENGTH},
 };
 
-use super::value::{read_value, write_value};
+use super::value::{read_value, skip_value, write_value};
 
 const IMMEDIATE_TINY_START: u8 = 0x03;
 const BACK_REFERENCE_TINY_START: u8 = 0x57;
@@ -15,28 +18,48 @@ const IMMEDIATE_MAX: u8 = BACK_REFERENCE_TINY_START - IMMEDIATE_TINY_START;
 const BACK_REFERENCE_MAX: u8 = GLOBAL_INDEX_TINY_START - BACK_REFERENCE_TINY_START;
 const GLOBAL_INDEX_MAX: u8 = RESERVED - GLOBAL_INDEX_TINY_START;
 
-pub fn read_object(
-    bytes: &mut ByteReader,
+pub fn read_object<R: ByteReader>(
+    bytes: &mut R,
     length: usize,
     keys_table: &mut DecodeKeysTables,
-) -> Result<Value, String> {
+) -> Result<Value, FracJsonError> {
     if length == 0 {
         return Ok(Value::Object(Map::new()));
     }
-    let mut map = Map::with_capacity(length);
+    bytes.enter_container(length)?;
+    // every entry takes at least two bytes, don't trust larger lengths for preallocation
+    let mut map = Map::with_capacity(bytes.capacity_hint(length, 2));
     for _ in 0..length {
         let key = read_key(bytes, keys_table)?;
         let value = read_value(bytes, keys_table)?;
         map.insert(key, value);
     }
+    bytes.exit_container();
     return Ok(Value::Object(map));
 }
 
+pub fn skip_object<R: ByteReader, K: SkipKeysTables>(
+    bytes: &mut R,
+    length: usize,
+    keys_table: &mut K,
+) -> Result<(), FracJsonError> {
+    if length == 0 {
+        return Ok(());
+    }
+    bytes.enter_container(length)?;
+    for _ in 0..length {
+        skip_key(bytes, keys_table)?;
+        skip_value(bytes, keys_table)?;
+    }
+    bytes.exit_container();
+    return Ok(());
+}
+
 pub fn write_object<'a, 'b: 'a, W: ByteWriter>(
     object: &'b Map<String, Value>,
     bytes: &mut W,
     keys_table: &mut EncodeKeysTables<'a>,
-) -> Result<(), String> {
+) -> Result<(), FracJsonError> {
     for (key, value) in object {
         write_key(key, bytes, keys_table)?;
         write_value(value, bytes, keys_table)?;
@@ -44,79 +67,136 @@ pub fn write_object<'a, 'b: 'a, W: ByteWriter>(
     Ok(())
 }
 
-fn read_key(bytes: &mut ByteReader, keys_table: &mut DecodeKeysTables) -> Result<String, String> {
+/// Encoded form of an object key, before it is resolved to a string.
+pub enum KeyRef {
+    /// Length of a key string that follows
+    Immediate(usize),
+    /// Index into the local keys table
+    Local(usize),
+    /// Index into the global keys table
+    Global(usize),
+}
+
+fn read_key<R: ByteReader>(
+    bytes: &mut R,
+    keys_table: &mut DecodeKeysTables,
+) -> Result<String, FracJsonError> {
+    let key_offset = bytes.offset();
+    match read_key_ref(bytes)? {
+        KeyRef::Immediate(length) => read_immediate_key(bytes, length, keys_table),
+        KeyRef::Local(index) => Ok(keys_table.lookup_local_index(index, key_offset)?.clone()),
+        KeyRef::Global(index) => Ok(keys_table.lookup_global_index(index, key_offset)?.clone()),
+    }
+}
+
+/// Reads past a key. Immediate keys are added to the local keys table and indices are
+/// checked, same as when decoding.
+fn skip_key<R: ByteReader, K: SkipKeysTables>(
+    bytes: &mut R,
+    keys_table: &mut K,
+) -> Result<(), FracJsonError> {
+    let key_offset = bytes.offset();
+    match read_key_ref(bytes)? {
+        KeyRef::Immediate(length) => keys_table.skip_immediate_key(bytes, length),
+        KeyRef::Local(index) => keys_table.check_local_index(index, key_offset),
+        KeyRef::Global(index) => keys_table.check_global_index(index, key_offset),
+    }
+}
+
+pub fn read_key_ref<R: ByteReader>(bytes: &mut R) -> Result<KeyRef, FracJsonError> {
+    let key_offset = bytes.offset();
     let first_byte = bytes.read_u8()?;
     if first_byte < IMMEDIATE_TINY_START {
         let value = read_vu16(bytes)? as usize;
         match first_byte {
-            0 => {
-                let key = read_immediate_key(bytes, value, keys_table)?;
-                return Ok(key);
-            }
-            1 => {
-                let key = keys_table.lookup_local_index(value)?;
-                return Ok(key.clone());
+            0 => return Ok(KeyRef::Immediate(value)),
+            1 => return Ok(KeyRef::Local(value)),
+            2 => return Ok(KeyRef::Global(value)),
+            _ => {
+                return Err(FracJsonError::ReservedType {
+                    offset: key_offset,
+                    type_byte: first_byte,
+                })
             }
-            2 => {
-                let key = keys_table.lookup_global_index(value)?;
-                return Ok(key.clone());
-            }
-            _ => return Err(format!("Invalid key index byte: {:02X}", first_byte)),
         }
     }
     if first_byte < BACK_REFERENCE_TINY_START {
-        let size = read_tiny_u8(first_byte, IMMEDIATE_TINY_START)?;
-        let key = read_immediate_key(bytes, size as usize, keys_table)?;
-        return Ok(key);
+        let size = read_tiny_u8(first_byte, IMMEDIATE_TINY_START);
+        return Ok(KeyRef::Immediate(size as usize));
     }
     if first_byte < GLOBAL_INDEX_TINY_START {
-        let key_index = read_tiny_u8(first_byte, BACK_REFERENCE_TINY_START)?;
-        let key = keys_table.lookup_local_index(key_index as usize)?;
-        return Ok(key.clone());
+        let key_index = read_tiny_u8(first_byte, BACK_REFERENCE_TINY_START);
+        return Ok(KeyRef::Local(key_index as usize));
     }
     if first_byte < RESERVED {
-        let key_index = read_tiny_u8(first_byte, GLOBAL_INDEX_TINY_START)?;
-        let key = keys_table.lookup_global_index(key_index as usize)?;
-        return Ok(key.clone());
+        let key_index = read_tiny_u8(first_byte, GLOBAL_INDEX_TINY_START);
+        return Ok(KeyRef::Global(key_index as usize));
     }
-    return Err(format!("Invalid key index byte: {:02X}", first_byte));
+    return Err(FracJsonError::ReservedType {
+        offset: key_offset,
+        type_byte: first_byte,
+    });
 }
 
-fn write_key<'a, 'b: 'a, W: ByteWriter>(
-    key: &'b String,
+pub fn write_key<'a, 'b: 'a, W: ByteWriter>(
+    key: &'b str,
     bytes: &mut W,
     keys_table: &mut EncodeKeysTables<'a>,
-) -> Result<(), String> {
+) -> Result<(), FracJsonError> {
+    if write_key_reference(key, bytes, keys_table)? {
+        return Ok(());
+    }
+    write_immediate_key(Cow::Borrowed(key), bytes, keys_table)
+}
+
+/// Like `write_key`, for keys that don't live as long as the keys table.
+pub fn write_transient_key<W: ByteWriter>(
+    key: &str,
+    bytes: &mut W,
+    keys_table: &mut EncodeKeysTables,
+) -> Result<(), FracJsonError> {
+    if write_key_reference(key, bytes, keys_table)? {
+        return Ok(());
+    }
+    write_immediate_key(Cow::Owned(key.to_string()), bytes, keys_table)
+}
+
+/// Writes the index of `key` in the global or local keys table. Returns false without
+/// writing anything if it is in neither, and has to be written as an immediate key.
+fn write_key_reference<W: ByteWriter>(
+    key: &str,
+    bytes: &mut W,
+    keys_table: &EncodeKeysTables,
+) -> Result<bool, FracJsonError> {
     if key.len() > MAX_KEY_LENGTH {
-        return Err(format!(
-            "Key length {} exceeds MAX_KEY_LENGTH {}",
-            key.len(),
-            MAX_KEY_LENGTH
-        ));
+        return Err(FracJsonError::LimitExceeded {
+            offset: bytes.position(),
+            limit: Limit::KeyLength,
+            value: key.len(),
+            max: MAX_KEY_LENGTH,
+        });
     }
-    if let Some(global_index) = keys_table.find_global_index(&key) {
+    if let Some(global_index) = keys_table.find_global_index(key) {
         write_type_and_value(
             bytes,
             global_index,
             2,
             GLOBAL_INDEX_TINY_START,
             GLOBAL_INDEX_MAX,
-        );
-        return Ok(());
+        )?;
+        return Ok(true);
     }
-    if let Some(local_index) = keys_table.find_local_index(&key) {
+    if let Some(local_index) = keys_table.find_local_index(key) {
         write_type_and_value(
             bytes,
             local_index,
             1,
             BACK_REFERENCE_TINY_START,
             BACK_REFERENCE_MAX,
-        );
-        return Ok(());
+        )?;
+        return Ok(true);
     }
-    write_type_and_value(bytes, key.len(), 0, IMMEDIATE_TINY_START, IMMEDIATE_MAX);
-    write_immediate_key(key, bytes, keys_table)?;
-    Ok(())
+    Ok(false)
 }
 
 fn write_type_and_value<W: ByteWriter>(
@@ -125,44 +205,72 @@ fn write_type_and_value<W: ByteWriter>(
     vu8_offset: u8,
     tiny_start: u8,
     tiny_max: u8,
-) {
+) -> Result<(), FracJsonError> {
+    if value < tiny_max as usize {
+        return write_tiny_u8(value as u8, tiny_start, bytes);
+    }
+    bytes.write_u8(vu8_offset)?;
+    write_vu16(value as u16, bytes)
+}
+
+/// Number of bytes `write_type_and_value` writes for `value`
+fn type_and_value_size(value: usize, tiny_max: u8) -> usize {
     if value < tiny_max as usize {
-        write_tiny_u8(value as u8, tiny_start, bytes);
-        return;
+        return 1;
     }
-    bytes.write_u8(vu8_offset as u8);
-    write_vu16(value as u16, bytes);
+    if value < 0x80 {
+        return 2;
+    }
+    if value < 0x4000 {
+        return 3;
+    }
+    4
+}
+
+/// Encoded size of an immediate key of `length` bytes, including the key itself
+pub(crate) fn immediate_key_size(length: usize) -> usize {
+    type_and_value_size(length, IMMEDIATE_MAX) + length
+}
+
+pub(crate) fn back_reference_size(local_index: usize) -> usize {
+    type_and_value_size(local_index, BACK_REFERENCE_MAX)
 }
 
-fn read_tiny_u8(value: u8, start: u8) -> Result<u8, String> {
-    Ok(value - start)
+pub(crate) fn global_index_size(global_index: usize) -> usize {
+    type_and_value_size(global_index, GLOBAL_INDEX_MAX)
 }
 
-fn write_tiny_u8<W: ByteWriter>(value: u8, start: u8, bytes: &mut W) {
-    bytes.write_u8(value + start);
+fn read_tiny_u8(value: u8, start: u8) -> u8 {
+    value - start
 }
 
-fn read_immediate_key(
-    bytes: &mut ByteReader,
+fn write_tiny_u8<W: ByteWriter>(value: u8, start: u8, bytes: &mut W) -> Result<(), FracJsonError> {
+    bytes.write_u8(value + start)
+}
+
+fn read_immediate_key<R: ByteReader>(
+    bytes: &mut R,
     length: usize,
     keys_table: &mut DecodeKeysTables,
-) -> Result<String, String> {
+) -> Result<String, FracJsonError> {
     let key = bytes.read_string(length)?;
     keys_table.on_immediate_key(&key);
     Ok(key)
 }
 
-fn write_immediate_key<'a, 'b: 'a, W: ByteWriter>(
-    key: &'b String,
+fn write_immediate_key<'a, W: ByteWriter>(
+    key: Cow<'a, str>,
     bytes: &mut W,
     keys_table: &mut EncodeKeysTables<'a>,
-) -> Result<(), String> {
-    bytes.write_string(&key);
+) -> Result<(), FracJsonError> {
+    write_type_and_value(bytes, key.len(), 0, IMMEDIATE_TINY_START, IMMEDIATE_MAX)?;
+    bytes.write_string(&key)?;
     keys_table.on_immediate_key(key);
     Ok(())
 }
 
-fn read_vu16(bytes: &mut ByteReader) -> Result<u16, String> {
+fn read_vu16<R: ByteReader>(bytes: &mut R) -> Result<u16, FracJsonError> {
+    let offset = bytes.offset();
     let b0 = bytes.read_u8()?;
     let has_more = b0 & 0x80 != 0;
     if !has_more {
@@ -175,40 +283,42 @@ fn read_vu16(bytes: &mut ByteReader) -> Result<u16, String> {
     }
     let b2 = bytes.read_u8()? as u16;
     if b2 > 0x03 {
-        return Err(format!(
-            "Invalid key index bytes: {:02X} {:02X} {:02X}",
-            b0, b1, b2
-        ));
+        return Err(FracJsonError::InvalidKeyIndex {
+            offset,
+            bytes: [b0, b1 as u8, b2 as u8],
+        });
     }
-    Ok((b0 as u16 & 0x7F | (b1 & 0x7F) << 7 | (b2 & 0x03) << 14) as u16)
+    Ok(b0 as u16 & 0x7F | (b1 & 0x7F) << 7 | (b2 & 0x03) << 14)
 }
 
-fn write_vu16<W: ByteWriter>(key_index: u16, bytes: &mut W) {
+fn write_vu16<W: ByteWriter>(key_index: u16, bytes: &mut W) -> Result<(), FracJsonError> {
     let mut b0 = (key_index & 0x7F) as u8;
     if key_index < 0x80 {
-        bytes.write_u8(b0);
-        return;
+        return bytes.write_u8(b0);
     }
     b0 |= 0x80;
     let mut b1 = ((key_index >> 7) & 0x7F) as u8;
     if key_index < 0x4000 {
-        bytes.write(&[b0, b1]);
-        return;
+        return bytes.write(&[b0, b1]);
     }
     b1 |= 0x80;
     let b2 = ((key_index >> 14) & 0x03) as u8;
-    bytes.write(&[b0, b1, b2]);
+    bytes.write(&[b0, b1, b2])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::byte_stream::SliceByteReader;
 
-    fn convert_key_index_twice(key_index: u16, expected_bytes_count: usize) -> Result<(), String> {
+    fn convert_key_index_twice(
+        key_index: u16,
+        expected_bytes_count: usize,
+    ) -> Result<(), FracJsonError> {
         let mut bytes = Vec::new();
-        write_vu16(key_index, &mut bytes);
+        write_vu16(key_index, &mut bytes)?;
         assert_eq!(expected_bytes_count, bytes.len());
-        let mut bytes = ByteReader::make(&bytes);
+        let mut bytes = SliceByteReader::make(&bytes);
         let result = read_vu16(&mut bytes).unwrap();
         assert_eq!(key_index, result);
         Ok(())
@@ -223,4 +333,23 @@ mod tests {
         convert_key_index_twice(0x4000, 3).unwrap();
         convert_key_index_twice(0xFFFF, 3).unwrap();
     }
+
+    #[test]
+    fn test_key_sizes() {
+        for index in [0, 83, 84, 0x7F, 0x80, 0x3FFF, 0x4000, 0xFFFF] {
+            let mut bytes = Vec::new();
+            write_type_and_value(
+                &mut bytes,
+                index,
+                2,
+                GLOBAL_INDEX_TINY_START,
+                GLOBAL_INDEX_MAX,
+            )
+            .unwrap();
+            assert_eq!(bytes.len(), global_index_size(index));
+            assert_eq!(bytes.len(), back_reference_size(index));
+        }
+        assert_eq!(1 + 83, immediate_key_size(83));
+        assert_eq!(2 + 84, immediate_key_size(84));
+    }
 }
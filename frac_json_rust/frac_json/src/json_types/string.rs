@@ -1,16 +1,21 @@
 use serde_json::Value;
 
-use crate::byte_stream::{ByteReader, ByteWriter};
+use crate::{
+    byte_stream::{ByteReader, ByteWriter},
+    error::FracJsonError,
+};
 
-pub fn read_string(bytes: &mut ByteReader, length: usize) -> Result<Value, String> {
+pub fn read_string<R: ByteReader>(bytes: &mut R, length: usize) -> Result<Value, FracJsonError> {
     if length == 0 {
         return Ok(Value::String("".to_string()));
     }
+    bytes.check_string_length(length)?;
     return Ok(Value::String(bytes.read_string(length)?));
 }
 
-pub fn write_string<W: ByteWriter>(string: &String, bytes: &mut W) {
+pub fn write_string<W: ByteWriter>(string: &str, bytes: &mut W) -> Result<(), FracJsonError> {
     if !string.is_empty() {
-        bytes.write_string(string);
+        bytes.write_string(string)?;
     }
+    return Ok(());
 }
@@ -1,78 +1,87 @@
+use std::marker::PhantomData;
+
 use serde_json::Value;
 
 use crate::{
     byte_stream::{ByteReader, ByteWriter},
-    keys_table::{DecodeKeysTables, EncodeKeysTables},
+    error::{FracJsonError, Limit},
+    keys_table::{DecodeKeysTables, EncodeKeysTables, SkipKeysTables},
 };
 
 use super::{
-    array::{read_array, write_array},
+    array::{read_array, skip_array, write_array},
     data_type::DataTypes,
-    object::{read_object, write_object},
+    object::{read_object, skip_object, write_object},
     string::{read_string, write_string},
 };
 
-const READ_VALUE_FROM_TYPE: [fn(&mut ByteReader, &mut DecodeKeysTables) -> Result<Value, String>;
-    22] = [
-    |_, _| Ok(Value::Null),
-    |_, _| Ok(Value::Bool(false)),
-    |_, _| Ok(Value::Bool(true)),
-    |bytes, _| Ok(Value::from(bytes.read_i8()?)),
-    |bytes, _| Ok(Value::from(bytes.read_u8()?)),
-    |bytes, _| Ok(Value::from(bytes.read_i16()?)),
-    |bytes, _| Ok(Value::from(bytes.read_u16()?)),
-    |bytes, _| Ok(Value::from(bytes.read_i32()?)),
-    |bytes, _| Ok(Value::from(bytes.read_u32()?)),
-    |bytes, _| Ok(Value::from(bytes.read_i64()?)),
-    |bytes, _| Ok(Value::from(bytes.read_u64()?)),
-    |bytes, _| Ok(Value::from(bytes.read_f32()?)),
-    |bytes, _| Ok(Value::from(bytes.read_f64()?)),
-    |bytes, _| {
-        let length = bytes.read_u8()? as usize;
-        read_string(bytes, length)
-    },
-    |bytes, _| {
-        let length = bytes.read_u16()? as usize;
-        read_string(bytes, length)
-    },
-    |bytes, _| {
-        let length = bytes.read_u32()? as usize;
-        read_string(bytes, length)
-    },
-    |bytes, keys_table| {
-        let length = bytes.read_u8()? as usize;
-        read_object(bytes, length, keys_table)
-    },
-    |bytes, keys_table| {
-        let length = bytes.read_u16()? as usize;
-        read_object(bytes, length, keys_table)
-    },
-    |bytes, keys_table| {
-        let length = bytes.read_u32()? as usize;
-        read_object(bytes, length, keys_table)
-    },
-    |bytes, keys_table| {
-        let length = bytes.read_u8()? as usize;
-        read_array(bytes, length, keys_table)
-    },
-    |bytes, keys_table| {
-        let length = bytes.read_u16()? as usize;
-        read_array(bytes, length, keys_table)
-    },
-    |bytes, keys_table| {
-        let length = bytes.read_u32()? as usize;
-        read_array(bytes, length, keys_table)
-    },
-];
-
-pub fn read_value(
-    bytes: &mut ByteReader,
+type ReadValueFn<R> = fn(&mut R, &mut DecodeKeysTables) -> Result<Value, FracJsonError>;
+
+struct ReadValueFns<R>(PhantomData<R>);
+
+impl<R: ByteReader> ReadValueFns<R> {
+    const READ_VALUE_FROM_TYPE: [ReadValueFn<R>; 22] = [
+        |_, _| Ok(Value::Null),
+        |_, _| Ok(Value::Bool(false)),
+        |_, _| Ok(Value::Bool(true)),
+        |bytes, _| Ok(Value::from(bytes.read_i8()?)),
+        |bytes, _| Ok(Value::from(bytes.read_u8()?)),
+        |bytes, _| Ok(Value::from(bytes.read_i16()?)),
+        |bytes, _| Ok(Value::from(bytes.read_u16()?)),
+        |bytes, _| Ok(Value::from(bytes.read_i32()?)),
+        |bytes, _| Ok(Value::from(bytes.read_u32()?)),
+        |bytes, _| Ok(Value::from(bytes.read_i64()?)),
+        |bytes, _| Ok(Value::from(bytes.read_u64()?)),
+        |bytes, _| Ok(Value::from(bytes.read_f32()?)),
+        |bytes, _| Ok(Value::from(bytes.read_f64()?)),
+        |bytes, _| {
+            let length = bytes.read_u8()? as usize;
+            read_string(bytes, length)
+        },
+        |bytes, _| {
+            let length = bytes.read_u16()? as usize;
+            read_string(bytes, length)
+        },
+        |bytes, _| {
+            let length = bytes.read_u32()? as usize;
+            read_string(bytes, length)
+        },
+        |bytes, keys_table| {
+            let length = bytes.read_u8()? as usize;
+            read_object(bytes, length, keys_table)
+        },
+        |bytes, keys_table| {
+            let length = bytes.read_u16()? as usize;
+            read_object(bytes, length, keys_table)
+        },
+        |bytes, keys_table| {
+            let length = bytes.read_u32()? as usize;
+            read_object(bytes, length, keys_table)
+        },
+        |bytes, keys_table| {
+            let length = bytes.read_u8()? as usize;
+            read_array(bytes, length, keys_table)
+        },
+        |bytes, keys_table| {
+            let length = bytes.read_u16()? as usize;
+            read_array(bytes, length, keys_table)
+        },
+        |bytes, keys_table| {
+            let length = bytes.read_u32()? as usize;
+            read_array(bytes, length, keys_table)
+        },
+    ];
+}
+
+pub fn read_value<R: ByteReader>(
+    bytes: &mut R,
     keys_table: &mut DecodeKeysTables,
-) -> Result<Value, String> {
+) -> Result<Value, FracJsonError> {
+    let data_type_offset = bytes.offset();
     let data_type_char = bytes.read_u8()?;
     if data_type_char < DataTypes::TINY_STRING {
         let index = data_type_char as usize;
-        let value = READ_VALUE_FROM_TYPE[index](bytes, keys_table)?;
+        let value = ReadValueFns::<R>::READ_VALUE_FROM_TYPE[index](bytes, keys_table)?;
         return Ok(value);
     } else if data_type_char < DataTypes::TINY_OBJECT {
         let length = data_type_char - DataTypes::TINY_STRING;
@@ -87,7 +96,100 @@ pub fn read_value(
         let value = (data_type_char - DataTypes::TINY_INT) as i8 + DataTypes::TINY_INT_BIAS;
         return Ok(Value::from(value));
     } else {
-        return Err(format!("Reserved data type byte {}", data_type_char));
+        return Err(FracJsonError::ReservedType {
+            offset: data_type_offset,
+            type_byte: data_type_char,
+        });
+    }
+}
+
+/// Data type of a value, with scalars already read and the length of strings, objects and
+/// arrays whose contents follow.
+pub enum ValueHeader {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f32),
+    Double(f64),
+    String(usize),
+    Object(usize),
+    Array(usize),
+}
+
+pub fn read_value_header<R: ByteReader>(bytes: &mut R) -> Result<ValueHeader, FracJsonError> {
+    let data_type_offset = bytes.offset();
+    let data_type_char = bytes.read_u8()?;
+    let header = match data_type_char {
+        DataTypes::NULL => ValueHeader::Null,
+        DataTypes::FALSE => ValueHeader::Bool(false),
+        DataTypes::TRUE => ValueHeader::Bool(true),
+        DataTypes::INT8 => ValueHeader::Int(bytes.read_i8()? as i64),
+        DataTypes::UINT8 => ValueHeader::UInt(bytes.read_u8()? as u64),
+        DataTypes::INT16 => ValueHeader::Int(bytes.read_i16()? as i64),
+        DataTypes::UINT16 => ValueHeader::UInt(bytes.read_u16()? as u64),
+        DataTypes::INT32 => ValueHeader::Int(bytes.read_i32()? as i64),
+        DataTypes::UINT32 => ValueHeader::UInt(bytes.read_u32()? as u64),
+        DataTypes::INT64 => ValueHeader::Int(bytes.read_i64()?),
+        DataTypes::UINT64 => ValueHeader::UInt(bytes.read_u64()?),
+        DataTypes::FLOAT => ValueHeader::Float(bytes.read_f32()?),
+        DataTypes::DOUBLE => ValueHeader::Double(bytes.read_f64()?),
+        DataTypes::STRING8 => ValueHeader::String(bytes.read_u8()? as usize),
+        DataTypes::STRING16 => ValueHeader::String(bytes.read_u16()? as usize),
+        DataTypes::STRING32 => ValueHeader::String(bytes.read_u32()? as usize),
+        DataTypes::OBJECT8 => ValueHeader::Object(bytes.read_u8()? as usize),
+        DataTypes::OBJECT16 => ValueHeader::Object(bytes.read_u16()? as usize),
+        DataTypes::OBJECT32 => ValueHeader::Object(bytes.read_u32()? as usize),
+        DataTypes::ARRAY8 => ValueHeader::Array(bytes.read_u8()? as usize),
+        DataTypes::ARRAY16 => ValueHeader::Array(bytes.read_u16()? as usize),
+        DataTypes::ARRAY32 => ValueHeader::Array(bytes.read_u32()? as usize),
+        _ if data_type_char < DataTypes::TINY_OBJECT => {
+            ValueHeader::String((data_type_char - DataTypes::TINY_STRING) as usize)
+        }
+        _ if data_type_char < DataTypes::TINY_ARRAY => {
+            ValueHeader::Object((data_type_char - DataTypes::TINY_OBJECT) as usize)
+        }
+        _ if data_type_char < DataTypes::TINY_INT => {
+            ValueHeader::Array((data_type_char - DataTypes::TINY_ARRAY) as usize)
+        }
+        _ if data_type_char < DataTypes::RESERVED => {
+            let value = (data_type_char - DataTypes::TINY_INT) as i8 + DataTypes::TINY_INT_BIAS;
+            ValueHeader::Int(value as i64)
+        }
+        _ => {
+            return Err(FracJsonError::ReservedType {
+                offset: data_type_offset,
+                type_byte: data_type_char,
+            })
+        }
+    };
+    return Ok(header);
+}
+
+/// Reads past a value without decoding it. Immediate keys of skipped objects are still
+/// added to the local keys table, since later keys may reference them.
+pub fn skip_value<R: ByteReader, K: SkipKeysTables>(
+    bytes: &mut R,
+    keys_table: &mut K,
+) -> Result<(), FracJsonError> {
+    let header = read_value_header(bytes)?;
+    return skip_value_contents(header, bytes, keys_table);
+}
+
+/// Like `skip_value`, for a value whose header was already read.
+pub fn skip_value_contents<R: ByteReader, K: SkipKeysTables>(
+    header: ValueHeader,
+    bytes: &mut R,
+    keys_table: &mut K,
+) -> Result<(), FracJsonError> {
+    match header {
+        ValueHeader::String(length) => {
+            bytes.check_string_length(length)?;
+            bytes.skip(length)
+        }
+        ValueHeader::Object(length) => skip_object(bytes, length, keys_table),
+        ValueHeader::Array(length) => skip_array(bytes, length, keys_table),
+        _ => Ok(()),
     }
 }
 
@@ -95,109 +197,136 @@ pub fn write_value<'a, 'b: 'a, W: ByteWriter>(
     value: &'b Value,
     bytes: &mut W,
     keys_table: &mut EncodeKeysTables<'a>,
-) -> Result<(), String> {
+) -> Result<(), FracJsonError> {
     match value {
-        Value::Null => Ok(bytes.write_u8(DataTypes::NULL)),
-        Value::Bool(b) => match b {
-            false => Ok(bytes.write_u8(DataTypes::FALSE)),
-            true => Ok(bytes.write_u8(DataTypes::TRUE)),
-        },
+        Value::Null => write_null(bytes),
+        Value::Bool(b) => write_bool(*b, bytes),
         Value::Number(number) => {
             if let Some(n) = number.as_i64() {
-                if n >= DataTypes::TINY_INT_MIN as i64 && n < DataTypes::TINY_INT_MAX as i64 {
-                    let tiny_int =
-                        (n - DataTypes::TINY_INT_BIAS as i64) as u8 + DataTypes::TINY_INT;
-                    bytes.write_u8(tiny_int as u8);
-                    Ok(())
-                } else if n >= 0 {
-                    if n <= 0xFF {
-                        bytes.write_u8(DataTypes::UINT8);
-                        bytes.write_u8(n as u8);
-                        Ok(())
-                    } else if n <= 0xFFFF {
-                        bytes.write_u8(DataTypes::UINT16);
-                        bytes.write_u16(n as u16);
-                        Ok(())
-                    } else if n <= 0xFFFFFFFF {
-                        bytes.write_u8(DataTypes::UINT32);
-                        bytes.write_u32(n as u32);
-                        Ok(())
-                    } else {
-                        bytes.write_u8(DataTypes::UINT64);
-                        bytes.write_u64(n as u64);
-                        Ok(())
-                    }
-                } else {
-                    if n >= -0x80 {
-                        bytes.write_u8(DataTypes::INT8);
-                        bytes.write_i8(n as i8);
-                        Ok(())
-                    } else if n >= -0x8000 {
-                        bytes.write_u8(DataTypes::INT16);
-                        bytes.write_i16(n as i16);
-                        Ok(())
-                    } else if n >= -0x80000000 {
-                        bytes.write_u8(DataTypes::INT32);
-                        bytes.write_i32(n as i32);
-                        Ok(())
-                    } else {
-                        bytes.write_u8(DataTypes::INT64);
-                        bytes.write_i64(n);
-                        Ok(())
-                    }
-                }
+                write_i64(n, bytes)
             } else if let Some(n) = number.as_u64() {
-                bytes.write_u8(DataTypes::UINT64);
-                bytes.write_u64(n);
-                Ok(())
+                write_u64(n, bytes)
             } else if let Some(n) = number.as_f64() {
-                if can_be_represented_as_f32(n) {
-                    bytes.write_u8(DataTypes::FLOAT);
-                    bytes.write_f32(n as f32);
-                    Ok(())
-                } else {
-                    bytes.write_u8(DataTypes::DOUBLE);
-                    bytes.write_f64(n);
-                    Ok(())
-                }
+                write_f64(n, bytes)
             } else {
-                Err("Number is not an integer or float".to_string())
+                Err(FracJsonError::InvalidNumber {
+                    offset: bytes.position(),
+                })
             }
         }
-        Value::String(string) => {
-            write_var_length_data_type(
-                string.len(),
-                DataTypes::STRING8,
-                DataTypes::TINY_STRING,
-                DataTypes::TINY_OBJECT - DataTypes::TINY_STRING,
-                bytes,
-            )?;
-            write_string(string, bytes);
-            Ok(())
-        }
+        Value::String(string) => write_string_value(string, bytes),
         Value::Object(object) => {
-            write_var_length_data_type(
-                object.len(),
-                DataTypes::OBJECT8,
-                DataTypes::TINY_OBJECT,
-                DataTypes::TINY_ARRAY - DataTypes::TINY_OBJECT,
-                bytes,
-            )?;
+            write_object_header(object.len(), bytes)?;
             write_object(object, bytes, keys_table)
         }
         Value::Array(array) => {
-            write_var_length_data_type(
-                array.len(),
-                DataTypes::ARRAY8,
-                DataTypes::TINY_ARRAY,
-                DataTypes::TINY_INT - DataTypes::TINY_ARRAY,
-                bytes,
-            )?;
+            write_array_header(array.len(), bytes)?;
             write_array(array, bytes, keys_table)
         }
     }
 }
 
+pub fn write_string_value<W: ByteWriter>(string: &str, bytes: &mut W) -> Result<(), FracJsonError> {
+    write_var_length_data_type(
+        string.len(),
+        DataTypes::STRING8,
+        DataTypes::TINY_STRING,
+        DataTypes::TINY_OBJECT - DataTypes::TINY_STRING,
+        bytes,
+    )?;
+    write_string(string, bytes)
+}
+
+/// Writes the data type and length of an object, which must be followed by `length` entries.
+pub fn write_object_header<W: ByteWriter>(
+    length: usize,
+    bytes: &mut W,
+) -> Result<(), FracJsonError> {
+    write_var_length_data_type(
+        length,
+        DataTypes::OBJECT8,
+        DataTypes::TINY_OBJECT,
+        DataTypes::TINY_ARRAY - DataTypes::TINY_OBJECT,
+        bytes,
+    )
+}
+
+/// Writes the data type and length of an array, which must be followed by `length` values.
+pub fn write_array_header<W: ByteWriter>(
+    length: usize,
+    bytes: &mut W,
+) -> Result<(), FracJsonError> {
+    write_var_length_data_type(
+        length,
+        DataTypes::ARRAY8,
+        DataTypes::TINY_ARRAY,
+        DataTypes::TINY_INT - DataTypes::TINY_ARRAY,
+        bytes,
+    )
+}
+
+pub fn write_null<W: ByteWriter>(bytes: &mut W) -> Result<(), FracJsonError> {
+    bytes.write_u8(DataTypes::NULL)
+}
+
+pub fn write_bool<W: ByteWriter>(b: bool, bytes: &mut W) -> Result<(), FracJsonError> {
+    bytes.write_u8(if b { DataTypes::TRUE } else { DataTypes::FALSE })
+}
+
+pub fn write_i64<W: ByteWriter>(n: i64, bytes: &mut W) -> Result<(), FracJsonError> {
+    if n >= DataTypes::TINY_INT_MIN as i64 && n < DataTypes::TINY_INT_MAX as i64 {
+        let tiny_int = (n - DataTypes::TINY_INT_BIAS as i64) as u8 + DataTypes::TINY_INT;
+        bytes.write_u8(tiny_int)?;
+    } else if n >= 0 {
+        if n <= 0xFF {
+            bytes.write_u8(DataTypes::UINT8)?;
+            bytes.write_u8(n as u8)?;
+        } else if n <= 0xFFFF {
+            bytes.write_u8(DataTypes::UINT16)?;
+            bytes.write_u16(n as u16)?;
+        } else if n <= 0xFFFFFFFF {
+            bytes.write_u8(DataTypes::UINT32)?;
+            bytes.write_u32(n as u32)?;
+        } else {
+            bytes.write_u8(DataTypes::UINT64)?;
+            bytes.write_u64(n as u64)?;
+        }
+    } else {
+        if n >= -0x80 {
+            bytes.write_u8(DataTypes::INT8)?;
+            bytes.write_i8(n as i8)?;
+        } else if n >= -0x8000 {
+            bytes.write_u8(DataTypes::INT16)?;
+            bytes.write_i16(n as i16)?;
+        } else if n >= -0x80000000 {
+            bytes.write_u8(DataTypes::INT32)?;
+            bytes.write_i32(n as i32)?;
+        } else {
+            bytes.write_u8(DataTypes::INT64)?;
+            bytes.write_i64(n)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_u64<W: ByteWriter>(n: u64, bytes: &mut W) -> Result<(), FracJsonError> {
+    if let Ok(n) = i64::try_from(n) {
+        return write_i64(n, bytes);
+    }
+    bytes.write_u8(DataTypes::UINT64)?;
+    bytes.write_u64(n)
+}
+
+pub fn write_f64<W: ByteWriter>(n: f64, bytes: &mut W) -> Result<(), FracJsonError> {
+    if can_be_represented_as_f32(n) {
+        bytes.write_u8(DataTypes::FLOAT)?;
+        bytes.write_f32(n as f32)
+    } else {
+        bytes.write_u8(DataTypes::DOUBLE)?;
+        bytes.write_f64(n)
+    }
+}
+
 fn can_be_represented_as_f32(f: f64) -> bool {
     if f.is_nan() {
         return false;
@@ -206,7 +335,7 @@ fn can_be_represented_as_f32(f: f64) -> bool {
         return true;
     }
     let f_with_f32_precision = f as f32 as f64;
-    return f_with_f32_precision == f
+    return f_with_f32_precision == f;
 }
 
 fn write_var_length_data_type<W: ByteWriter>(
@@ -215,9 +344,9 @@ fn write_var_length_data_type<W: ByteWriter>(
     tiny_offset: u8,
     tiny_max: u8,
     bytes: &mut W,
-) -> Result<(), String> {
+) -> Result<(), FracJsonError> {
     if length < tiny_max as usize {
-        bytes.write_u8(length as u8 + tiny_offset);
+        bytes.write_u8(length as u8 + tiny_offset)?;
     } else {
         let additional_offset = if length <= 0xFF {
             0
@@ -226,13 +355,18 @@ fn write_var_length_data_type<W: ByteWriter>(
         } else if length <= 0xFFFFFFFF {
             2
         } else {
-            return Err(format!("Value length {} is too long", length));
+            return Err(FracJsonError::LimitExceeded {
+                offset: bytes.position(),
+                limit: Limit::ValueLength,
+                value: length,
+                max: 0xFFFFFFFF,
+            });
         };
-        bytes.write_u8(normal_offset + additional_offset);
+        bytes.write_u8(normal_offset + additional_offset)?;
         match additional_offset {
-            0 => bytes.write_u8(length as u8),
-            1 => bytes.write_u16(length as u16),
-            2 => bytes.write_u32(length as u32),
+            0 => bytes.write_u8(length as u8)?,
+            1 => bytes.write_u16(length as u16)?,
+            2 => bytes.write_u32(length as u32)?,
             _ => panic!("This should never happen"),
         }
     }
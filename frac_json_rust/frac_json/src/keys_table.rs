@@ -1,178 +1,911 @@
-use crate::byte_stream::{ByteReader, ByteWriter};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    byte_stream::{ByteReader, ByteWriter, SliceByteReader},
+    config::MAX_KEYS_TABLE_LAYERS,
+    error::{FracJsonError, Limit},
+};
 
 pub const MAX_TABLE_SIZE: usize = 0xFFFF;
 pub const MAX_KEY_LENGTH: usize = 0xFFFF;
+pub const MAX_TABLE_NAME_LENGTH: usize = 0xFFFF;
+
+/// Magic of a self-describing keys table. Its first byte takes the place of the config byte
+/// of the legacy layout, so older readers reject these tables instead of misreading them.
+const TABLE_MAGIC: [u8; 2] = *b"FK";
+const TABLE_FORMAT_VERSION: u8 = 1;
+
+/// Identity of a keys table, stored in the self-describing table layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeysTableMetadata {
+    /// Name or ID of the table
+    pub name: String,
+    /// Version of the table, chosen by whoever builds it
+    pub version: u32,
+    /// Creation time in seconds since the unix epoch
+    pub created_at: u64,
+}
+
+impl KeysTableMetadata {
+    /// Metadata with `created_at` set to the current time.
+    pub fn new(name: &str, version: u32) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        KeysTableMetadata {
+            name: name.to_string(),
+            version,
+            created_at,
+        }
+    }
+}
 
+/// Keys shared between many files, so they don't have to be stored in each file.
+#[derive(Debug, Clone)]
 pub struct GlobalKeysTable {
     table: Vec<String>,
+    /// key -> index, only built once a key is looked up while encoding
+    key_indices: OnceLock<HashMap<String, usize>>,
+    /// fingerprints of the first 0..=n keys, the last one is the fingerprint of the table
+    prefix_fingerprints: OnceLock<Vec<u32>>,
+    metadata: Option<KeysTableMetadata>,
+}
+
+impl PartialEq for GlobalKeysTable {
+    fn eq(&self, other: &Self) -> bool {
+        self.table == other.table
+    }
 }
 
+impl Eq for GlobalKeysTable {}
+
 impl GlobalKeysTable {
     pub fn new(table: Vec<String>) -> Self {
-        GlobalKeysTable { table }
+        GlobalKeysTable {
+            table,
+            key_indices: OnceLock::new(),
+            prefix_fingerprints: OnceLock::new(),
+            metadata: None,
+        }
+    }
+
+    /// Attaches metadata, which makes `to_bytes` write the self-describing layout.
+    /// Metadata isn't part of the fingerprint or of equality.
+    pub fn with_metadata(mut self, metadata: KeysTableMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn metadata(&self) -> Option<&KeysTableMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub(crate) fn clear_metadata(&mut self) {
+        self.metadata = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// All keys, in index order.
+    pub fn keys(&self) -> &[String] {
+        &self.table
+    }
+
+    /// Index `key` is encoded with. For duplicate keys, the first index is used.
+    pub fn index_of(&self, key: &str) -> Option<usize> {
+        self.find_key(key)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.table.get(index).map(String::as_str)
+    }
+
+    /// Short hash of the keys and their order. Files can store it to detect being decoded
+    /// with a different table than they were encoded with.
+    pub fn fingerprint(&self) -> u32 {
+        return *self.prefix_fingerprints().last().unwrap();
+    }
+
+    /// Whether this table is the table with `fingerprint`, or an extension of it that only
+    /// appended keys. Files encoded with such a table can be decoded with this one.
+    pub(crate) fn extends_fingerprint(&self, fingerprint: u32) -> bool {
+        return self.prefix_fingerprints().contains(&fingerprint);
+    }
+
+    fn prefix_fingerprints(&self) -> &[u32] {
+        self.prefix_fingerprints.get_or_init(|| {
+            // 32 bit FNV-1a over the length and bytes of each key
+            let mut hash: u32 = 0x811C9DC5;
+            let mut prefix_fingerprints = Vec::with_capacity(self.table.len() + 1);
+            prefix_fingerprints.push(hash);
+            for key in &self.table {
+                let length = (key.len() as u32).to_le_bytes();
+                for &byte in length.iter().chain(key.as_bytes()) {
+                    hash ^= byte as u32;
+                    hash = hash.wrapping_mul(0x01000193);
+                }
+                prefix_fingerprints.push(hash);
+            }
+            prefix_fingerprints
+        })
+    }
+
+    /// New version of the table, with the `keys` that aren't in it yet appended. Indices of
+    /// existing keys don't change, so files encoded with this table can be decoded with the
+    /// new one. The metadata version, if any, is incremented, which fails with `InvalidOptions`
+    /// at `u32::MAX`.
+    pub fn extended(&self, keys: Vec<String>) -> Result<GlobalKeysTable, FracJsonError> {
+        let mut table = self.table.clone();
+        let mut added: HashSet<&str> = HashSet::new();
+        for key in &keys {
+            if self.find_key(key).is_none() && added.insert(key.as_str()) {
+                table.push(key.clone());
+            }
+        }
+        if table.len() > MAX_TABLE_SIZE {
+            return Err(FracJsonError::LimitExceeded {
+                offset: 0,
+                limit: Limit::KeysTableSize,
+                value: table.len(),
+                max: MAX_TABLE_SIZE,
+            });
+        }
+        let mut extended = GlobalKeysTable::new(table);
+        if let Some(metadata) = &self.metadata {
+            let Some(version) = metadata.version.checked_add(1) else {
+                return Err(FracJsonError::InvalidOptions(format!(
+                    "keys table version {} can't be incremented",
+                    metadata.version
+                )));
+            };
+            extended.metadata = Some(KeysTableMetadata::new(&metadata.name, version));
+        }
+        return Ok(extended);
+    }
+
+    /// Parses a keys table, as created by `global_table_from_keys` or `global_table_from_json`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<GlobalKeysTable, FracJsonError> {
+        return GlobalKeysTable::read_keys_table(&mut SliceByteReader::make(bytes));
     }
 
-    pub fn read_keys_table(bytes: &mut ByteReader) -> Result<GlobalKeysTable, String> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FracJsonError> {
+        let mut bytes = Vec::new();
+        self.write_keys_table(&mut bytes)?;
+        return Ok(bytes);
+    }
+
+    /// Reads either the legacy layout (config byte 0) or the self-describing layout (magic "FK").
+    pub(crate) fn read_keys_table<R: ByteReader>(
+        bytes: &mut R,
+    ) -> Result<GlobalKeysTable, FracJsonError> {
+        let config_offset = bytes.offset();
         let config = bytes.read_u8()?;
+        if config == TABLE_MAGIC[0] {
+            let second = bytes.read_u8()?;
+            if second != TABLE_MAGIC[1] {
+                return Err(FracJsonError::InvalidMagic {
+                    offset: config_offset,
+                    magic: [config, second],
+                });
+            }
+            return GlobalKeysTable::read_described_table(bytes);
+        }
         if config != 0 {
-            return Err(format!("Unsupported keys table config {}", config));
+            return Err(FracJsonError::UnsupportedKeysTableConfig {
+                offset: config_offset,
+                config,
+            });
+        }
+        return Ok(GlobalKeysTable::new(GlobalKeysTable::read_key_mappings(
+            bytes,
+        )?));
+    }
+
+    fn read_described_table<R: ByteReader>(
+        bytes: &mut R,
+    ) -> Result<GlobalKeysTable, FracJsonError> {
+        let version_offset = bytes.offset();
+        let format_version = bytes.read_u8()?;
+        if format_version != TABLE_FORMAT_VERSION {
+            return Err(FracJsonError::UnsupportedVersion {
+                offset: version_offset,
+                version: format_version,
+            });
         }
+        let name_length = bytes.read_u16()?;
+        let name = bytes.read_string(name_length.into())?;
+        let version = bytes.read_u32()?;
+        let created_at = bytes.read_u64()?;
+        let keys = GlobalKeysTable::read_key_mappings(bytes)?;
+        let checksum_offset = bytes.offset();
+        let checksum = bytes.read_u32()?;
+        let table = GlobalKeysTable::new(keys);
+        let metadata = KeysTableMetadata {
+            name,
+            version,
+            created_at,
+        };
+        // the layout is canonical, so writing the table again reproduces the checksummed bytes
+        let mut content = Vec::new();
+        table.write_described_content(&metadata, &mut content)?;
+        let actual = crc32(&content);
+        if actual != checksum {
+            return Err(FracJsonError::ChecksumMismatch {
+                offset: checksum_offset,
+                expected: checksum,
+                actual,
+            });
+        }
+        return Ok(table.with_metadata(metadata));
+    }
+
+    fn read_key_mappings<R: ByteReader>(bytes: &mut R) -> Result<Vec<String>, FracJsonError> {
         let count = bytes.read_u16()?;
         let mut mappings: Vec<String> = Vec::new();
         mappings.reserve_exact(count.into());
         for _ in 0..count {
             mappings.push(GlobalKeysTable::read_key_mapping(bytes)?);
         }
-        return Ok(GlobalKeysTable::new(mappings));
+        return Ok(mappings);
     }
 
-    fn read_key_mapping(bytes: &mut ByteReader) -> Result<String, String> {
+    fn read_key_mapping<R: ByteReader>(bytes: &mut R) -> Result<String, FracJsonError> {
         let key_length = bytes.read_u16()?;
-        return Ok(bytes.read_string(key_length.into())?);
+        return bytes.read_string(key_length.into());
+    }
+
+    pub(crate) fn write_keys_table<W: ByteWriter>(
+        &self,
+        bytes: &mut W,
+    ) -> Result<(), FracJsonError> {
+        if let Some(metadata) = &self.metadata {
+            let mut content = Vec::new();
+            self.write_described_content(metadata, &mut content)?;
+            let checksum = crc32(&content);
+            bytes.write(&content)?;
+            bytes.write_u32(checksum)?;
+            return Ok(());
+        }
+        bytes.write_u8(0)?;
+        return self.write_key_mappings(bytes);
     }
 
-    pub fn write_keys_table<W: ByteWriter>(&self, bytes: &mut W) -> Result<(), String> {
+    /// Everything of the self-describing layout except the trailing checksum
+    fn write_described_content(
+        &self,
+        metadata: &KeysTableMetadata,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), FracJsonError> {
+        if metadata.name.len() > MAX_TABLE_NAME_LENGTH {
+            return Err(FracJsonError::InvalidOptions(format!(
+                "keys table name is {} bytes long, the maximum is {}",
+                metadata.name.len(),
+                MAX_TABLE_NAME_LENGTH
+            )));
+        }
+        bytes.write(&TABLE_MAGIC)?;
+        bytes.write_u8(TABLE_FORMAT_VERSION)?;
+        bytes.write_u16(metadata.name.len() as u16)?;
+        bytes.write_string(&metadata.name)?;
+        bytes.write_u32(metadata.version)?;
+        bytes.write_u64(metadata.created_at)?;
+        return self.write_key_mappings(bytes);
+    }
+
+    fn write_key_mappings<W: ByteWriter>(&self, bytes: &mut W) -> Result<(), FracJsonError> {
         let count = self.table.len();
         if count > MAX_TABLE_SIZE {
-            return Err(format!("Keys table too large! {count} keys"));
+            return Err(FracJsonError::LimitExceeded {
+                offset: bytes.position(),
+                limit: Limit::KeysTableSize,
+                value: count,
+                max: MAX_TABLE_SIZE,
+            });
         }
-        bytes.write_u8(0);
-        bytes.write_u16(count as u16);
+        bytes.write_u16(count as u16)?;
         for key in self.table.iter() {
             self.write_key_mapping(key, bytes)?;
         }
         return Ok(());
     }
 
-    fn write_key_mapping<W: ByteWriter>(&self, key: &String, bytes: &mut W) -> Result<(), String> {
+    fn write_key_mapping<W: ByteWriter>(
+        &self,
+        key: &str,
+        bytes: &mut W,
+    ) -> Result<(), FracJsonError> {
         if key.len() > MAX_KEY_LENGTH {
-            return Err(format!("Key '{}' too long! {}", key, key.len()));
+            return Err(FracJsonError::LimitExceeded {
+                offset: bytes.position(),
+                limit: Limit::KeyLength,
+                value: key.len(),
+                max: MAX_KEY_LENGTH,
+            });
         }
-        bytes.write_u16(key.len() as u16);
-        bytes.write_string(key);
+        bytes.write_u16(key.len() as u16)?;
+        bytes.write_string(key)?;
         return Ok(());
     }
 
-    pub fn lookup_index(&self, index: usize) -> Result<&String, String> {
+    pub(crate) fn lookup_index(
+        &self,
+        index: usize,
+        offset: usize,
+    ) -> Result<&String, FracJsonError> {
         if index >= self.table.len() {
-            return Err(format!(
-                "Index {index} is not in GlobalKeysTable of size {}",
-                self.table.len()
-            ));
+            return Err(FracJsonError::UnknownGlobalKeyIndex {
+                offset,
+                index,
+                table_size: self.table.len(),
+            });
         }
         return Ok(&self.table[index]);
     }
 
-    pub fn find_key(&self, key: &String) -> Option<usize> {
+    pub(crate) fn find_key(&self, key: &str) -> Option<usize> {
         if self.table.is_empty() {
             return None;
         }
-        return self.table.iter().position(|x| x == key);
+        let key_indices = self.key_indices.get_or_init(|| {
+            let mut key_indices = HashMap::with_capacity(self.table.len());
+            for (i, key) in self.table.iter().enumerate() {
+                // first occurrence wins for duplicate keys
+                key_indices.entry(key.clone()).or_insert(i);
+            }
+            key_indices
+        });
+        return key_indices.get(key).copied();
+    }
+}
+
+/// Whether `new` only appends keys to `old`, so that files encoded with `old` can be
+/// decoded with `new`.
+pub fn is_compatible_extension(old: &GlobalKeysTable, new: &GlobalKeysTable) -> bool {
+    return new.table.starts_with(&old.table);
+}
+
+/// Global keys tables stacked on top of each other, for example a shared base table and an
+/// application specific extension of it.
+///
+/// Indices continue from one layer into the next, so the set resolves like a single table
+/// of the keys of all layers, base layer first. Keys in multiple layers resolve to the lowest
+/// layer. Files encoded with a set record the fingerprint of each layer, so decoding them
+/// with a missing, extra or reordered layer fails with `KeysTableLayerMismatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalKeysTableSet {
+    layers: Vec<GlobalKeysTable>,
+}
+
+impl GlobalKeysTableSet {
+    pub fn new(layers: Vec<GlobalKeysTable>) -> Result<GlobalKeysTableSet, FracJsonError> {
+        if layers.is_empty() || layers.len() > MAX_KEYS_TABLE_LAYERS {
+            return Err(FracJsonError::InvalidOptions(format!(
+                "keys table set must have between 1 and {} layers, got {}",
+                MAX_KEYS_TABLE_LAYERS,
+                layers.len()
+            )));
+        }
+        let count: usize = layers.iter().map(|layer| layer.table.len()).sum();
+        if count > MAX_TABLE_SIZE {
+            return Err(FracJsonError::LimitExceeded {
+                offset: 0,
+                limit: Limit::KeysTableSize,
+                value: count,
+                max: MAX_TABLE_SIZE,
+            });
+        }
+        return Ok(GlobalKeysTableSet { layers });
+    }
+
+    /// Parses each layer with `GlobalKeysTable::from_bytes`, base layer first.
+    pub fn from_bytes(layers: &[&[u8]]) -> Result<GlobalKeysTableSet, FracJsonError> {
+        let layers = layers
+            .iter()
+            .map(|bytes| GlobalKeysTable::from_bytes(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        return GlobalKeysTableSet::new(layers);
+    }
+
+    pub fn layers(&self) -> &[GlobalKeysTable] {
+        &self.layers
+    }
+
+    pub fn fingerprints(&self) -> Vec<u32> {
+        self.layers
+            .iter()
+            .map(GlobalKeysTable::fingerprint)
+            .collect()
+    }
+
+    /// All layers as a single table.
+    pub fn to_table(&self) -> GlobalKeysTable {
+        let keys = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.table.iter().cloned())
+            .collect();
+        GlobalKeysTable::new(keys)
     }
 }
 
 struct LocalEncodeKeysTable<'a> {
-    encountered_keys: Vec<&'a String>,
+    /// key -> index, keys are borrowed from the encoded value where possible
+    key_indices: HashMap<Cow<'a, str>, usize>,
 }
 
-struct LocalDecodeKeysTable {
-    encountered_keys: Vec<String>,
+/// Immediate keys in the order they were encountered, either owned `String`s or keys
+/// borrowed from the decoded bytes.
+struct LocalDecodeKeysTable<K> {
+    encountered_keys: Vec<K>,
 }
 
 impl<'a> LocalEncodeKeysTable<'a> {
-    pub fn new(encountered_keys: Vec<&String>) -> LocalEncodeKeysTable {
-        LocalEncodeKeysTable { encountered_keys }
+    pub fn new(encountered_keys: Vec<&'a String>) -> LocalEncodeKeysTable<'a> {
+        let mut table = LocalEncodeKeysTable {
+            key_indices: HashMap::with_capacity(encountered_keys.len()),
+        };
+        for key in encountered_keys {
+            table.push_key(Cow::Borrowed(key));
+        }
+        table
     }
 
-    pub fn find_key(&self, key: &String) -> Option<usize> {
-        if self.encountered_keys.is_empty() {
+    pub fn find_key(&self, key: &str) -> Option<usize> {
+        if self.key_indices.is_empty() {
             return None;
         }
-        return self.encountered_keys.iter().position(|x| *x == key);
+        return self.key_indices.get(key).copied();
     }
 
-    pub fn push_key_ref(&mut self, key: &'a String) {
-        if self.encountered_keys.len() < MAX_TABLE_SIZE {
-            self.encountered_keys.push(key);
+    pub fn push_key(&mut self, key: Cow<'a, str>) {
+        let count = self.key_indices.len();
+        if count < MAX_TABLE_SIZE {
+            self.key_indices.entry(key).or_insert(count);
         }
     }
 }
 
-impl LocalDecodeKeysTable {
-    pub fn new() -> LocalDecodeKeysTable {
+impl<K> LocalDecodeKeysTable<K> {
+    pub fn new() -> LocalDecodeKeysTable<K> {
         LocalDecodeKeysTable {
             encountered_keys: Vec::new(),
         }
     }
 
-    pub fn lookup_index(&self, index: usize) -> Result<&String, String> {
+    pub fn lookup_index(&self, index: usize, offset: usize) -> Result<&K, FracJsonError> {
         if index >= self.encountered_keys.len() {
-            return Err(format!(
-                "Index {index} is not in LocalKeysTable of size {}",
-                self.encountered_keys.len()
-            ));
+            return Err(FracJsonError::UnknownLocalKeyIndex {
+                offset,
+                index,
+                table_size: self.encountered_keys.len(),
+            });
         }
         return Ok(&self.encountered_keys[index]);
     }
 
-    pub fn push_key(&mut self, key: &String) {
+    pub fn push_key(&mut self, key: K) {
         if self.encountered_keys.len() < MAX_TABLE_SIZE {
-            self.encountered_keys.push(key.clone());
+            self.encountered_keys.push(key);
         }
     }
 }
 
 pub struct EncodeKeysTables<'a> {
-    local_table: LocalEncodeKeysTable<'a>,
-    global_table: GlobalKeysTable,
+    local_table: LocalEncodeTable<'a>,
+    global_table: Option<&'a GlobalKeysTable>,
+}
+
+pub struct DecodeKeysTables<'a> {
+    local_table: LocalTable<'a, LocalDecodeKeysTable<String>>,
+    global_table: Option<&'a GlobalKeysTable>,
+}
+
+/// Local keys table of a single document, or of a session shared by many documents.
+enum LocalTable<'a, T> {
+    Document(T),
+    Session(&'a mut T),
+}
+
+/// Like `LocalTable`, but keys of a session have to outlive each encoded value, so they are
+/// always owned.
+enum LocalEncodeTable<'a> {
+    Document(LocalEncodeKeysTable<'a>),
+    Session(&'a mut LocalEncodeKeysTable<'static>),
+}
+
+/// Local keys table that persists across the documents of a session, so keys of earlier
+/// documents can be referenced by later ones.
+pub struct EncodeSession {
+    local_table: LocalEncodeKeysTable<'static>,
+}
+
+/// Decoding counterpart of `EncodeSession`.
+pub struct DecodeSession {
+    local_table: LocalDecodeKeysTable<String>,
+}
+
+/// Keys tables of a document that is read out of order by cursors. Cursors can go back to
+/// positions that were read before, so immediate keys are stored as their position and only
+/// added the first time they are read.
+pub struct CursorKeysTables<'t> {
+    local_table: &'t mut Vec<KeyPosition>,
+    global_table: Option<&'t GlobalKeysTable>,
+}
+
+/// Position of an immediate key string in a document
+#[derive(Debug, Clone, Copy)]
+pub struct KeyPosition {
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Keys tables that can keep track of keys while values are skipped.
+pub trait SkipKeysTables {
+    /// Reads past an immediate key of `length` bytes and adds it to the local keys table.
+    fn skip_immediate_key<R: ByteReader>(
+        &mut self,
+        bytes: &mut R,
+        length: usize,
+    ) -> Result<(), FracJsonError>;
+    fn check_local_index(&self, index: usize, offset: usize) -> Result<(), FracJsonError>;
+    fn check_global_index(&self, index: usize, offset: usize) -> Result<(), FracJsonError>;
+}
+
+/// Like `DecodeKeysTables`, but keys are borrowed from the decoded bytes and the global
+/// keys table instead of being copied.
+pub struct BorrowedDecodeKeysTables<'a> {
+    local_table: LocalDecodeKeysTable<Cow<'a, str>>,
+    global_table: Option<&'a GlobalKeysTable>,
+}
+
+impl EncodeSession {
+    pub fn new() -> EncodeSession {
+        EncodeSession {
+            local_table: LocalEncodeKeysTable::new(Vec::new()),
+        }
+    }
+
+    /// Starts over with an empty local keys table.
+    pub fn reset(&mut self) {
+        self.local_table.key_indices.clear();
+    }
 }
 
-pub struct DecodeKeysTables {
-    local_table: LocalDecodeKeysTable,
-    global_table: GlobalKeysTable,
+impl DecodeSession {
+    pub fn new() -> DecodeSession {
+        DecodeSession {
+            local_table: LocalDecodeKeysTable::new(),
+        }
+    }
+
+    /// Starts over with an empty local keys table.
+    pub fn reset(&mut self) {
+        self.local_table.encountered_keys.clear();
+    }
 }
 
 impl<'a> EncodeKeysTables<'a> {
     pub fn make(
-        local_table: Vec<&String>,
-        global_table: Option<GlobalKeysTable>,
-    ) -> EncodeKeysTables {
+        local_table: Vec<&'a String>,
+        global_table: Option<&'a GlobalKeysTable>,
+    ) -> EncodeKeysTables<'a> {
         EncodeKeysTables {
-            local_table: LocalEncodeKeysTable::new(local_table),
-            global_table: global_table.unwrap_or_else(|| GlobalKeysTable::new(Vec::new())),
+            local_table: LocalEncodeTable::Document(LocalEncodeKeysTable::new(local_table)),
+            global_table,
         }
     }
 
-    pub fn find_global_index(&self, key: &String) -> Option<usize> {
-        self.global_table.find_key(key)
+    /// Keys tables whose local table is the one of `session`.
+    pub fn make_session(
+        session: &'a mut EncodeSession,
+        global_table: Option<&'a GlobalKeysTable>,
+    ) -> EncodeKeysTables<'a> {
+        EncodeKeysTables {
+            local_table: LocalEncodeTable::Session(&mut session.local_table),
+            global_table,
+        }
+    }
+
+    pub fn find_global_index(&self, key: &str) -> Option<usize> {
+        self.global_table?.find_key(key)
+    }
+
+    pub fn find_local_index(&self, key: &str) -> Option<usize> {
+        match &self.local_table {
+            LocalEncodeTable::Document(table) => table.find_key(key),
+            LocalEncodeTable::Session(table) => table.find_key(key),
+        }
+    }
+
+    pub fn on_immediate_key(&mut self, key: Cow<'a, str>) {
+        match &mut self.local_table {
+            LocalEncodeTable::Document(table) => table.push_key(key),
+            LocalEncodeTable::Session(table) => table.push_key(Cow::Owned(key.into_owned())),
+        }
     }
+}
 
-    pub fn find_local_index(&self, key: &String) -> Option<usize> {
-        self.local_table.find_key(key)
+impl<T> LocalTable<'_, T> {
+    fn get(&self) -> &T {
+        match self {
+            LocalTable::Document(table) => table,
+            LocalTable::Session(table) => table,
+        }
     }
 
-    pub fn on_immediate_key<'b: 'a>(&mut self, key: &'b String) {
-        self.local_table.push_key_ref(key);
+    fn get_mut(&mut self) -> &mut T {
+        match self {
+            LocalTable::Document(table) => table,
+            LocalTable::Session(table) => table,
+        }
     }
 }
 
-impl DecodeKeysTables {
-    pub fn make(global_table: Option<GlobalKeysTable>) -> DecodeKeysTables {
+impl<'a> DecodeKeysTables<'a> {
+    pub fn make(global_table: Option<&'a GlobalKeysTable>) -> DecodeKeysTables<'a> {
+        DecodeKeysTables {
+            local_table: LocalTable::Document(LocalDecodeKeysTable::new()),
+            global_table,
+        }
+    }
+
+    /// Keys tables whose local table is the one of `session`.
+    pub fn make_session(
+        session: &'a mut DecodeSession,
+        global_table: Option<&'a GlobalKeysTable>,
+    ) -> DecodeKeysTables<'a> {
         DecodeKeysTables {
+            local_table: LocalTable::Session(&mut session.local_table),
+            global_table,
+        }
+    }
+
+    pub fn lookup_global_index(
+        &self,
+        index: usize,
+        offset: usize,
+    ) -> Result<&String, FracJsonError> {
+        lookup_global_index(self.global_table, index, offset)
+    }
+
+    pub fn lookup_local_index(
+        &self,
+        index: usize,
+        offset: usize,
+    ) -> Result<&String, FracJsonError> {
+        self.local_table.get().lookup_index(index, offset)
+    }
+
+    pub fn on_immediate_key(&mut self, key: &str) {
+        self.local_table.get_mut().push_key(key.to_string());
+    }
+}
+
+impl<'a> BorrowedDecodeKeysTables<'a> {
+    pub fn make(global_table: Option<&'a GlobalKeysTable>) -> BorrowedDecodeKeysTables<'a> {
+        BorrowedDecodeKeysTables {
             local_table: LocalDecodeKeysTable::new(),
-            global_table: global_table.unwrap_or_else(|| GlobalKeysTable::new(Vec::new())),
+            global_table,
         }
     }
 
-    pub fn lookup_global_index(&self, index: usize) -> Result<&String, String> {
-        self.global_table.lookup_index(index)
+    pub fn lookup_global_index(
+        &self,
+        index: usize,
+        offset: usize,
+    ) -> Result<&'a str, FracJsonError> {
+        Ok(lookup_global_index(self.global_table, index, offset)?)
     }
 
-    pub fn lookup_local_index(&self, index: usize) -> Result<&String, String> {
-        self.local_table.lookup_index(index)
+    pub fn lookup_local_index(
+        &self,
+        index: usize,
+        offset: usize,
+    ) -> Result<&Cow<'a, str>, FracJsonError> {
+        self.local_table.lookup_index(index, offset)
     }
 
-    pub fn on_immediate_key(&mut self, key: &String) {
+    pub fn on_immediate_key(&mut self, key: Cow<'a, str>) {
         self.local_table.push_key(key);
     }
 }
+
+impl SkipKeysTables for DecodeKeysTables<'_> {
+    fn skip_immediate_key<R: ByteReader>(
+        &mut self,
+        bytes: &mut R,
+        length: usize,
+    ) -> Result<(), FracJsonError> {
+        let key = bytes.read_string(length)?;
+        self.local_table.get_mut().push_key(key);
+        return Ok(());
+    }
+
+    fn check_local_index(&self, index: usize, offset: usize) -> Result<(), FracJsonError> {
+        self.lookup_local_index(index, offset)?;
+        return Ok(());
+    }
+
+    fn check_global_index(&self, index: usize, offset: usize) -> Result<(), FracJsonError> {
+        self.lookup_global_index(index, offset)?;
+        return Ok(());
+    }
+}
+
+impl<'t> CursorKeysTables<'t> {
+    pub fn make(
+        local_table: &'t mut Vec<KeyPosition>,
+        global_table: Option<&'t GlobalKeysTable>,
+    ) -> CursorKeysTables<'t> {
+        CursorKeysTables {
+            local_table,
+            global_table,
+        }
+    }
+
+    /// Whether the key at `offset` still has to be added to the local keys table
+    pub fn is_new_key(&self, offset: usize) -> bool {
+        match self.local_table.last() {
+            Some(last) => offset > last.offset && self.local_table.len() < MAX_TABLE_SIZE,
+            None => true,
+        }
+    }
+
+    pub fn on_immediate_key(&mut self, key: KeyPosition) {
+        if self.is_new_key(key.offset) {
+            self.local_table.push(key);
+        }
+    }
+
+    /// Only keys that come before `offset` can be referenced from there.
+    pub fn lookup_local_index(
+        &self,
+        index: usize,
+        offset: usize,
+    ) -> Result<KeyPosition, FracJsonError> {
+        let table_size = self.local_table.partition_point(|key| key.offset < offset);
+        if index >= table_size {
+            return Err(FracJsonError::UnknownLocalKeyIndex {
+                offset,
+                index,
+                table_size,
+            });
+        }
+        return Ok(self.local_table[index]);
+    }
+}
+
+impl SkipKeysTables for CursorKeysTables<'_> {
+    fn skip_immediate_key<R: ByteReader>(
+        &mut self,
+        bytes: &mut R,
+        length: usize,
+    ) -> Result<(), FracJsonError> {
+        let offset = bytes.offset();
+        if !self.is_new_key(offset) {
+            return bytes.skip(length);
+        }
+        // validates the key
+        bytes.read_string(length)?;
+        self.local_table.push(KeyPosition { offset, length });
+        return Ok(());
+    }
+
+    fn check_local_index(&self, index: usize, offset: usize) -> Result<(), FracJsonError> {
+        self.lookup_local_index(index, offset)?;
+        return Ok(());
+    }
+
+    fn check_global_index(&self, index: usize, offset: usize) -> Result<(), FracJsonError> {
+        lookup_global_index(self.global_table, index, offset)?;
+        return Ok(());
+    }
+}
+
+pub fn lookup_global_index(
+    global_table: Option<&GlobalKeysTable>,
+    index: usize,
+    offset: usize,
+) -> Result<&String, FracJsonError> {
+    match global_table {
+        Some(global_table) => global_table.lookup_index(index, offset),
+        None => Err(FracJsonError::UnknownGlobalKeyIndex {
+            offset,
+            index,
+            table_size: 0,
+        }),
+    }
+}
+
+/// CRC-32 (IEEE), as used for the checksum of self-describing keys tables
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFFFFFF;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    return !crc;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_global_duplicate_keys() {
+        let table = GlobalKeysTable::new(keys(&["id", "name", "id", "value", "name"]));
+        assert_eq!(Some(0), table.find_key("id"));
+        assert_eq!(Some(1), table.find_key("name"));
+        assert_eq!(Some(3), table.find_key("value"));
+        assert_eq!(None, table.find_key("missing"));
+        // the later duplicates can still be decoded
+        assert_eq!("id", table.lookup_index(2, 0).unwrap());
+        assert_eq!("name", table.lookup_index(4, 0).unwrap());
+    }
+
+    #[test]
+    fn test_global_find_key_matches_linear_scan() {
+        let table = GlobalKeysTable::new(
+            (0..1000)
+                .map(|i| format!("key{}", i % 700))
+                .chain(["".to_string(), "ключ".to_string()])
+                .collect(),
+        );
+        let linear_scan = |key: &str| table.table.iter().position(|x| x == key);
+        for i in 0..800 {
+            let key = format!("key{}", i);
+            assert_eq!(linear_scan(&key), table.find_key(&key), "{}", key);
+        }
+        for key in ["", "ключ", "key", "key00"] {
+            assert_eq!(linear_scan(key), table.find_key(key), "{}", key);
+        }
+        assert_eq!(None, GlobalKeysTable::new(Vec::new()).find_key(""));
+    }
+
+    #[test]
+    fn test_local_table_size_limit() {
+        let mut table = LocalEncodeKeysTable::new(Vec::new());
+        for i in 0..MAX_TABLE_SIZE + 10 {
+            table.push_key(Cow::Owned(i.to_string()));
+        }
+        assert_eq!(Some(0), table.find_key("0"));
+        assert_eq!(
+            Some(MAX_TABLE_SIZE - 1),
+            table.find_key(&(MAX_TABLE_SIZE - 1).to_string())
+        );
+        assert_eq!(None, table.find_key(&MAX_TABLE_SIZE.to_string()));
+        // pushing a key again keeps its first index
+        table.push_key(Cow::Borrowed("0"));
+        assert_eq!(Some(0), table.find_key("0"));
+
+        let mut table = LocalDecodeKeysTable::new();
+        for i in 0..MAX_TABLE_SIZE + 10 {
+            table.push_key(i.to_string());
+        }
+        assert_eq!("0", table.lookup_index(0, 0).unwrap());
+        assert!(table.lookup_index(MAX_TABLE_SIZE - 1, 0).is_ok());
+        assert!(matches!(
+            table.lookup_index(MAX_TABLE_SIZE, 7),
+            Err(FracJsonError::UnknownLocalKeyIndex { offset: 7, .. })
+        ));
+    }
+}
@@ -1,68 +1,286 @@
-use std::collections::HashMap;
+use std::{borrow::Borrow, collections::HashMap};
 
 use serde_json::Value;
 
-use crate::keys_table::{GlobalKeysTable, MAX_KEY_LENGTH, MAX_TABLE_SIZE};
+use crate::{
+    error::FracJsonError,
+    json_types::object::{back_reference_size, global_index_size, immediate_key_size},
+    keys_table::{GlobalKeysTable, MAX_KEY_LENGTH, MAX_TABLE_SIZE},
+};
 
-pub fn global_table_from_keys(keys: Vec<String>) -> Result<Vec<u8>, String> {
+pub fn global_table_from_keys(keys: Vec<String>) -> Result<Vec<u8>, FracJsonError> {
     let table = GlobalKeysTable::new(keys);
     let mut bytes: Vec<u8> = Vec::new();
     table.write_keys_table(&mut bytes)?;
     return Ok(bytes);
 }
 
-pub fn global_table_from_json(json: &Value) -> Result<Vec<u8>, String> {
+/// Table with all keys of `a`, followed by the keys of `b` that aren't in `a`. Files encoded
+/// with `a` can be decoded with the merged table.
+pub fn merge_global_tables(
+    a: &GlobalKeysTable,
+    b: &GlobalKeysTable,
+) -> Result<GlobalKeysTable, FracJsonError> {
+    let mut merged = a.extended(b.keys().to_vec())?;
+    merged.clear_metadata();
+    return Ok(merged);
+}
+
+/// Differences between two versions of a keys table.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KeysTableDiff {
+    /// Keys in the new table but not in the old one
+    pub added: Vec<String>,
+    /// Keys in the old table but not in the new one
+    pub removed: Vec<String>,
+    /// Keys in both tables, but with a different index
+    pub moved: Vec<MovedKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovedKey {
+    pub key: String,
+    pub old_index: usize,
+    pub new_index: usize,
+}
+
+impl KeysTableDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// Compares the keys of `old` and `new`. Keys that occur multiple times in a table are
+/// compared by their first index.
+pub fn diff_global_tables(old: &GlobalKeysTable, new: &GlobalKeysTable) -> KeysTableDiff {
+    let mut diff = KeysTableDiff::default();
+    for (old_index, key) in old.keys().iter().enumerate() {
+        if old.index_of(key) != Some(old_index) {
+            continue;
+        }
+        match new.index_of(key) {
+            Some(new_index) if new_index != old_index => diff.moved.push(MovedKey {
+                key: key.clone(),
+                old_index,
+                new_index,
+            }),
+            Some(_) => (),
+            None => diff.removed.push(key.clone()),
+        }
+    }
+    for (new_index, key) in new.keys().iter().enumerate() {
+        if new.index_of(key) == Some(new_index) && old.index_of(key).is_none() {
+            diff.added.push(key.clone());
+        }
+    }
+    return diff;
+}
+
+pub fn global_table_from_json(json: &Value) -> Result<Vec<u8>, FracJsonError> {
     global_table_from_json_limited(json, None, None)
 }
 
+/// Builds a keys table from the keys of `json`, see `KeysTableBuilder::build`.
+///
+/// Only keys that are estimated to save bytes over storing them in the file are included,
+/// taking the back references of repeated keys into account. The empty key, and short keys
+/// that would only get multi byte indices, are left out. The keys are ordered by number of
+/// occurrences, not in the order they appear in `json`.
 pub fn global_table_from_json_limited(
     json: &Value,
     max_count: Option<usize>,
     occurrence_cutoff: Option<usize>,
-) -> Result<Vec<u8>, String> {
-    let max_count = max_count.unwrap_or(MAX_TABLE_SIZE);
-    let occurrence_cutoff = occurrence_cutoff.unwrap_or(1);
-    if max_count > MAX_TABLE_SIZE {
-        return Err(format!(
-            "max_count {} is greater than MAX_GLOBAL_TABLE_SIZE {}",
-            max_count, MAX_TABLE_SIZE
-        ));
+) -> Result<Vec<u8>, FracJsonError> {
+    let mut builder = KeysTableBuilder::new();
+    builder.add_sample(json);
+    return builder.build(max_count, occurrence_cutoff)?.to_bytes();
+}
+
+/// Collects statistics about object keys over any number of sample documents, to build a
+/// global keys table without holding all samples in memory at once.
+///
+/// Builders of separate shards of a corpus can be combined with `merge`.
+#[derive(Debug, Clone, Default)]
+pub struct KeysTableBuilder {
+    key_usages: HashMap<String, KeyUsage>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct KeyUsage {
+    occurrences: usize,
+    /// Number of samples the key occurs in, each of which would store it once as an immediate key
+    samples: usize,
+    /// Bytes taken by back references to the key, when it is not in the global table
+    back_reference_bytes: usize,
+}
+
+impl KeyUsage {
+    /// Estimated size of all occurrences when the key is not in the global table
+    fn local_size(&self, key_length: usize) -> usize {
+        self.samples * immediate_key_size(key_length) + self.back_reference_bytes
+    }
+
+    /// Estimated bytes saved by placing the key at `global_index`, negative if it costs bytes
+    fn savings(&self, key_length: usize, global_index: usize) -> i64 {
+        let global_size = self.occurrences * global_index_size(global_index);
+        self.local_size(key_length) as i64 - global_size as i64
     }
-    let mut key_usages: HashMap<&String, usize> = HashMap::new();
-    let mut pending_objects: Vec<&Value> = vec![json];
-    while pending_objects.len() > 0 {
-        let value = pending_objects.pop();
-        match value {
-            Some(value) => match value {
-                Value::Array(array) => pending_objects.extend(array),
+}
+
+impl KeysTableBuilder {
+    pub fn new() -> Self {
+        KeysTableBuilder::default()
+    }
+
+    /// Adds one document. Keys are counted in the order the encoder writes them, so that the
+    /// back references they would otherwise get can be estimated.
+    pub fn add_sample(&mut self, sample: &Value) {
+        // key -> local index, in order of first occurrence
+        let mut local_keys: HashMap<&str, usize> = HashMap::new();
+        // (key of the entry, value), popped in the order they are encoded
+        let mut pending: Vec<(Option<&String>, &Value)> = vec![(None, sample)];
+        while let Some((key, value)) = pending.pop() {
+            if let Some(key) = key {
+                self.add_key(key, &mut local_keys);
+            }
+            match value {
+                Value::Array(array) => pending.extend(array.iter().rev().map(|v| (None, v))),
                 Value::Object(object) => {
-                    for (k, v) in object {
-                        if k.len() > MAX_KEY_LENGTH {
-                            continue;
-                        }
-                        key_usages
-                            .entry(k)
-                            .and_modify(|count| *count += 1)
-                            .or_insert(1);
-                        pending_objects.push(v);
-                    }
+                    pending.extend(object.iter().rev().map(|(k, v)| (Some(k), v)))
                 }
                 _ => (),
-            },
-            None => break,
+            }
+        }
+    }
+
+    fn add_key<'a>(&mut self, key: &'a str, local_keys: &mut HashMap<&'a str, usize>) {
+        if key.len() > MAX_KEY_LENGTH {
+            return;
+        }
+        let usage = match self.key_usages.get_mut(key) {
+            Some(usage) => usage,
+            None => self.key_usages.entry(key.to_string()).or_default(),
+        };
+        usage.occurrences += 1;
+        match local_keys.get(key) {
+            Some(&local_index) => usage.back_reference_bytes += back_reference_size(local_index),
+            None => {
+                usage.samples += 1;
+                local_keys.insert(key, local_keys.len());
+            }
         }
     }
 
-    let mut key_usages: Vec<(&String, usize)> = key_usages.iter().map(|(k, v)| (*k, *v)).collect();
-    key_usages.sort_by(|a, b| b.1.cmp(&a.1));
+    pub fn add_samples<I>(&mut self, samples: I)
+    where
+        I: IntoIterator,
+        I::Item: Borrow<Value>,
+    {
+        for sample in samples {
+            self.add_sample(sample.borrow());
+        }
+    }
 
-    let key_usages = if max_count > 0 {
-        key_usages.iter().take(max_count)
-    } else {
-        key_usages.iter().take(usize::MAX)
-    };
-    let key_usages = key_usages.filter(|(_k, v)| *v >= occurrence_cutoff);
+    /// Adds the statistics of another builder, as if its samples had been added to this one.
+    pub fn merge(&mut self, other: KeysTableBuilder) {
+        for (key, other_usage) in other.key_usages {
+            let usage = self.key_usages.entry(key).or_default();
+            usage.occurrences += other_usage.occurrences;
+            usage.samples += other_usage.samples;
+            usage.back_reference_bytes += other_usage.back_reference_bytes;
+        }
+    }
 
-    let keys: Vec<String> = key_usages.map(|(k, _v)| (*k).clone()).collect();
-    return global_table_from_keys(keys);
+    /// Number of distinct keys seen so far.
+    pub fn key_count(&self) -> usize {
+        self.key_usages.len()
+    }
+
+    /// Builds a table of the keys with the largest estimated savings over storing them in each
+    /// file. Keys that would not save any bytes are left out.
+    ///
+    /// The selected keys are ordered by number of occurrences, so that the most referenced keys
+    /// get the one byte indices. This, rather than ordering by savings, minimizes the encoded
+    /// size: the local size a key saves doesn't depend on its index, only the cost of its global
+    /// indices does, and their total `occurrences * index size` is smallest when the keys with
+    /// the most occurrences get the shortest indices. Keys that no longer save bytes at their
+    /// final index are dropped.
+    ///
+    /// `max_count` defaults to `MAX_TABLE_SIZE`, 0 also means no limit. Keys occurring less
+    /// than `occurrence_cutoff` times (default 1) are left out.
+    pub fn build(
+        &self,
+        max_count: Option<usize>,
+        occurrence_cutoff: Option<usize>,
+    ) -> Result<GlobalKeysTable, FracJsonError> {
+        let keys = self.select_keys(max_count, occurrence_cutoff, None)?;
+        return Ok(GlobalKeysTable::new(keys));
+    }
+
+    /// Like `build`, but only appends keys to `base` that it doesn't have yet, see
+    /// `GlobalKeysTable::extended`. `max_count` limits the number of added keys.
+    pub fn build_extension(
+        &self,
+        base: &GlobalKeysTable,
+        max_count: Option<usize>,
+        occurrence_cutoff: Option<usize>,
+    ) -> Result<GlobalKeysTable, FracJsonError> {
+        let keys = self.select_keys(max_count, occurrence_cutoff, Some(base))?;
+        return base.extended(keys);
+    }
+
+    fn select_keys(
+        &self,
+        max_count: Option<usize>,
+        occurrence_cutoff: Option<usize>,
+        base: Option<&GlobalKeysTable>,
+    ) -> Result<Vec<String>, FracJsonError> {
+        let max_count = max_count.unwrap_or(MAX_TABLE_SIZE);
+        let occurrence_cutoff = occurrence_cutoff.unwrap_or(1);
+        if max_count > MAX_TABLE_SIZE {
+            return Err(FracJsonError::InvalidOptions(format!(
+                "max_count {} is greater than MAX_GLOBAL_TABLE_SIZE {}",
+                max_count, MAX_TABLE_SIZE
+            )));
+        }
+        let first_index = base.map_or(0, GlobalKeysTable::len);
+        let max_count = if max_count > 0 {
+            max_count
+        } else {
+            MAX_TABLE_SIZE
+        };
+        let max_count = max_count.min(MAX_TABLE_SIZE.saturating_sub(first_index));
+
+        // estimated with the first free index, the exact index is only known once keys are ordered
+        let mut candidates: Vec<(&String, &KeyUsage, i64)> = self
+            .key_usages
+            .iter()
+            .filter(|(k, usage)| {
+                usage.occurrences >= occurrence_cutoff
+                    && base.is_none_or(|base| base.find_key(k).is_none())
+            })
+            .map(|(k, usage)| (k, usage, usage.savings(k.len(), first_index)))
+            .filter(|(_k, _usage, savings)| *savings > 0)
+            .collect();
+        // ties are ordered by key, so the same samples always give the same table
+        candidates.sort_by(|(a_key, _, a_savings), (b_key, _, b_savings)| {
+            b_savings.cmp(a_savings).then_with(|| a_key.cmp(b_key))
+        });
+        candidates.truncate(max_count);
+        candidates.sort_by(|(a_key, a_usage, a_savings), (b_key, b_usage, b_savings)| {
+            b_usage
+                .occurrences
+                .cmp(&a_usage.occurrences)
+                .then_with(|| b_savings.cmp(a_savings))
+                .then_with(|| a_key.cmp(b_key))
+        });
+
+        let mut keys: Vec<String> = Vec::with_capacity(candidates.len());
+        for (key, usage, _) in candidates {
+            if usage.savings(key.len(), first_index + keys.len()) > 0 {
+                keys.push(key.clone());
+            }
+        }
+        return Ok(keys);
+    }
 }
@@ -1,12 +1,42 @@
+#![allow(clippy::needless_return)]
+
+mod archive;
 mod byte_stream;
+mod codec;
 mod config;
+mod cursor;
+mod de;
+mod error;
+mod extract;
 mod frac_json_file;
+mod frac_value;
 mod json_types;
 mod keys_table;
 mod keys_table_utils;
+mod options;
+mod ser;
+mod stream;
 mod test;
+mod transcode;
 
-pub use frac_json_file::{decode, encode};
+pub use archive::{ArchiveReader, ArchiveRecords, ArchiveWriter};
+pub use codec::{Decoder, Encoder};
+pub use cursor::{FracCursor, FracDocument, FracElements, FracEntries};
+pub use de::{from_reader, from_slice};
+pub use error::{FracJsonError, Limit};
+pub use extract::{extract, extract_many};
+pub use frac_json_file::{
+    decode, decode_from_reader, decode_with_options, encode, encode_to_writer,
+};
+pub use frac_value::{decode_borrowed, FracValue};
+pub use keys_table::{
+    is_compatible_extension, GlobalKeysTable, GlobalKeysTableSet, KeysTableMetadata,
+};
 pub use keys_table_utils::{
-    global_table_from_json, global_table_from_json_limited, global_table_from_keys,
+    diff_global_tables, global_table_from_json, global_table_from_json_limited,
+    global_table_from_keys, merge_global_tables, KeysTableBuilder, KeysTableDiff, MovedKey,
 };
+pub use options::{DecodeLimits, DecodeOptions, EncodeOptions, TrustedInput, Utf8Validation};
+pub use ser::{to_vec, to_writer};
+pub use stream::{StreamReader, StreamWriter};
+pub use transcode::{transcode_from_json, transcode_to_json};
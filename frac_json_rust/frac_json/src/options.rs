@@ -0,0 +1,95 @@
+/// How strings and immediate keys are checked for valid UTF-8 while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Validation {
+    /// Reject invalid UTF-8 with `FracJsonError::InvalidUtf8`
+    #[default]
+    Strict,
+    /// Replace invalid sequences with U+FFFD
+    Lossy,
+    /// Skip validation. Can only be created with [`Utf8Validation::trusted`].
+    Trusted(TrustedInput),
+}
+
+/// Marker that can only be created through the unsafe [`Utf8Validation::trusted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedInput(());
+
+impl Utf8Validation {
+    /// Skip UTF-8 validation when decoding.
+    ///
+    /// # Safety
+    ///
+    /// All strings and keys in the decoded data must be valid UTF-8, for example because the
+    /// data was produced by this library. Invalid UTF-8 results in undefined behavior.
+    pub unsafe fn trusted() -> Utf8Validation {
+        Utf8Validation::Trusted(TrustedInput(()))
+    }
+}
+
+/// Upper bounds enforced while decoding untrusted data.
+///
+/// Exceeding any of them results in `FracJsonError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum nesting depth of objects and arrays. Default is 512.
+    pub max_depth: usize,
+    /// Maximum size of the decompressed data of zstd compressed files. Default is 1 GB.
+    pub max_decompressed_size: usize,
+    /// Maximum length of a string value in bytes. Default is unlimited.
+    pub max_string_length: usize,
+    /// Maximum number of entries of a single object or array. Default is unlimited.
+    pub max_container_length: usize,
+    /// Maximum number of object and array entries in the whole document. Default is unlimited.
+    pub max_total_elements: usize,
+}
+
+impl DecodeLimits {
+    pub fn unlimited() -> DecodeLimits {
+        DecodeLimits {
+            max_depth: usize::MAX,
+            max_decompressed_size: usize::MAX,
+            max_string_length: usize::MAX,
+            max_container_length: usize::MAX,
+            max_total_elements: usize::MAX,
+        }
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_depth: 512,
+            max_decompressed_size: 1024 * 1024 * 1024,
+            ..DecodeLimits::unlimited()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions<'a> {
+    /// bytes of an external keys table
+    pub global_keys_table_bytes: Option<&'a [u8]>,
+    /// bytes of each layer of a `GlobalKeysTableSet`, base layer first. Can't be combined
+    /// with `global_keys_table_bytes`
+    pub global_keys_table_layers: Option<&'a [&'a [u8]]>,
+    /// pre trained zstandard dictionary
+    pub zstd_dict: Option<&'a [u8]>,
+    pub utf8_validation: Utf8Validation,
+    pub limits: DecodeLimits,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions<'a> {
+    /// bytes of an external keys table
+    pub global_keys_table_bytes: Option<&'a [u8]>,
+    /// bytes of each layer of a `GlobalKeysTableSet`, base layer first. Can't be combined
+    /// with `global_keys_table_bytes`
+    pub global_keys_table_layers: Option<&'a [&'a [u8]]>,
+    /// zstd compression level, no compression if not set
+    pub compression_level: Option<i32>,
+    /// pre trained zstandard dictionary, requires compression_level
+    pub zstd_dict: Option<&'a [u8]>,
+    /// store a fingerprint of the global keys table, so decoding with a different table
+    /// fails with `KeysTableMismatch`
+    pub keys_table_fingerprint: bool,
+}
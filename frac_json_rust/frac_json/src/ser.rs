@@ -0,0 +1,655 @@
+use std::io::Write;
+
+use serde::{
+    ser::{self, Impossible},
+    Serialize,
+};
+
+use crate::{
+    byte_stream::ByteWriter,
+    codec::{EncodeBody, Encoder},
+    error::FracJsonError,
+    json_types::{
+        object::{write_key, write_transient_key},
+        value::{
+            write_array_header, write_bool, write_f64, write_i64, write_null, write_object_header,
+            write_string_value, write_u64,
+        },
+    },
+    keys_table::EncodeKeysTables,
+};
+
+/// Serializes `value` to an uncompressed file without a global keys table.
+///
+/// The output is the same as `encode(&serde_json::to_value(value)?, None, None, None)`,
+/// without building the intermediate `Value`, as long as no map contains a key twice.
+/// `to_value` keeps only the last entry of a duplicate key, while all entries are written
+/// here.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, FracJsonError> {
+    return Encoder::new(None, None, None)?.to_vec(value);
+}
+
+/// Like `to_vec`, but writes the file directly into `writer`.
+pub fn to_writer<W: Write, T: Serialize + ?Sized>(
+    writer: W,
+    value: &T,
+) -> Result<(), FracJsonError> {
+    return Encoder::new(None, None, None)?.to_writer(value, writer);
+}
+
+pub(crate) struct SerializeBody<'t, T: ?Sized>(pub &'t T);
+
+impl<T: Serialize + ?Sized> EncodeBody for SerializeBody<'_, T> {
+    fn write_body<'a, W: ByteWriter>(
+        &'a self,
+        bytes: &mut W,
+        keys_table: &mut EncodeKeysTables<'a>,
+    ) -> Result<(), FracJsonError> {
+        self.0.serialize(Serializer { bytes, keys_table })
+    }
+}
+
+fn number_out_of_range() -> FracJsonError {
+    FracJsonError::Serde("number does not fit into 64 bits".to_string())
+}
+
+/// Writes values in the same way as `write_value` does for the equivalent `serde_json::Value`.
+struct Serializer<'s, 'a, W: ByteWriter> {
+    bytes: &'s mut W,
+    keys_table: &'s mut EncodeKeysTables<'a>,
+}
+
+impl<'s, 'a, W: ByteWriter> Serializer<'s, 'a, W> {
+    /// Writes `{"variant": ` of an externally tagged enum variant
+    fn write_variant_key(&mut self, variant: &'static str) -> Result<(), FracJsonError> {
+        write_object_header(1, self.bytes)?;
+        write_key(variant, self.bytes, self.keys_table)
+    }
+
+    fn compound(
+        self,
+        kind: ContainerKind,
+        length: Option<usize>,
+    ) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        let buffer = match length {
+            Some(length) => {
+                match kind {
+                    ContainerKind::Object => write_object_header(length, self.bytes)?,
+                    ContainerKind::Array => write_array_header(length, self.bytes)?,
+                }
+                None
+            }
+            // the length prefix can only be written once all entries are known
+            None => Some(Vec::new()),
+        };
+        return Ok(Compound {
+            bytes: self.bytes,
+            keys_table: self.keys_table,
+            kind,
+            expected_length: length,
+            length: 0,
+            buffer,
+        });
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::Serializer for Serializer<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+    type SerializeSeq = Compound<'s, 'a, W>;
+    type SerializeTuple = Compound<'s, 'a, W>;
+    type SerializeTupleStruct = Compound<'s, 'a, W>;
+    type SerializeTupleVariant = Compound<'s, 'a, W>;
+    type SerializeMap = Compound<'s, 'a, W>;
+    type SerializeStruct = Compound<'s, 'a, W>;
+    type SerializeStructVariant = Compound<'s, 'a, W>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), FracJsonError> {
+        write_bool(v, self.bytes)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), FracJsonError> {
+        write_i64(v.into(), self.bytes)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), FracJsonError> {
+        write_i64(v.into(), self.bytes)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), FracJsonError> {
+        write_i64(v.into(), self.bytes)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), FracJsonError> {
+        write_i64(v, self.bytes)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), FracJsonError> {
+        if let Ok(v) = i64::try_from(v) {
+            return write_i64(v, self.bytes);
+        }
+        if let Ok(v) = u64::try_from(v) {
+            return write_u64(v, self.bytes);
+        }
+        return Err(number_out_of_range());
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), FracJsonError> {
+        write_i64(v.into(), self.bytes)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), FracJsonError> {
+        write_i64(v.into(), self.bytes)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), FracJsonError> {
+        write_i64(v.into(), self.bytes)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), FracJsonError> {
+        write_u64(v, self.bytes)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), FracJsonError> {
+        match u64::try_from(v) {
+            Ok(v) => write_u64(v, self.bytes),
+            Err(_) => Err(number_out_of_range()),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), FracJsonError> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), FracJsonError> {
+        // like serde_json, NaN and infinity become null
+        if !v.is_finite() {
+            return write_null(self.bytes);
+        }
+        write_f64(v, self.bytes)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), FracJsonError> {
+        write_string_value(v.encode_utf8(&mut [0; 4]), self.bytes)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), FracJsonError> {
+        write_string_value(v, self.bytes)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), FracJsonError> {
+        write_array_header(v.len(), self.bytes)?;
+        for byte in v {
+            write_i64((*byte).into(), self.bytes)?;
+        }
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), FracJsonError> {
+        write_null(self.bytes)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), FracJsonError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), FracJsonError> {
+        write_null(self.bytes)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), FracJsonError> {
+        write_null(self.bytes)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), FracJsonError> {
+        write_string_value(variant, self.bytes)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), FracJsonError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), FracJsonError> {
+        self.write_variant_key(variant)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.compound(ContainerKind::Array, len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.compound(ContainerKind::Array, Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.compound(ContainerKind::Array, Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.write_variant_key(variant)?;
+        self.compound(ContainerKind::Array, Some(len))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.compound(ContainerKind::Object, len)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.compound(ContainerKind::Object, Some(len))
+    }
+
+    fn serialize_struct_variant(
+        mut self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'s, 'a, W>, FracJsonError> {
+        self.write_variant_key(variant)?;
+        self.compound(ContainerKind::Object, Some(len))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ContainerKind {
+    Object,
+    Array,
+}
+
+/// Object or array that is being serialized.
+///
+/// When the number of entries is known up front, they are written directly after the
+/// header. Otherwise they are collected in `buffer` and written once the container ends.
+struct Compound<'s, 'a, W: ByteWriter> {
+    bytes: &'s mut W,
+    keys_table: &'s mut EncodeKeysTables<'a>,
+    kind: ContainerKind,
+    expected_length: Option<usize>,
+    length: usize,
+    buffer: Option<Vec<u8>>,
+}
+
+impl<'s, 'a, W: ByteWriter> Compound<'s, 'a, W> {
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        match &mut self.buffer {
+            Some(buffer) => value.serialize(Serializer {
+                bytes: buffer,
+                keys_table: self.keys_table,
+            }),
+            None => value.serialize(Serializer {
+                bytes: &mut *self.bytes,
+                keys_table: self.keys_table,
+            }),
+        }
+    }
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), FracJsonError> {
+        self.length += 1;
+        match &mut self.buffer {
+            Some(buffer) => key.serialize(MapKeySerializer {
+                bytes: buffer,
+                keys_table: self.keys_table,
+            }),
+            None => key.serialize(MapKeySerializer {
+                bytes: &mut *self.bytes,
+                keys_table: self.keys_table,
+            }),
+        }
+    }
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FracJsonError> {
+        self.length += 1;
+        match &mut self.buffer {
+            Some(buffer) => write_key(key, buffer, self.keys_table)?,
+            None => write_key(key, self.bytes, self.keys_table)?,
+        }
+        self.serialize_value(value)
+    }
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        self.length += 1;
+        self.serialize_value(value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        match self.buffer {
+            Some(buffer) => {
+                match self.kind {
+                    ContainerKind::Object => write_object_header(self.length, self.bytes)?,
+                    ContainerKind::Array => write_array_header(self.length, self.bytes)?,
+                }
+                self.bytes.write(&buffer)
+            }
+            None => {
+                if self.expected_length != Some(self.length) {
+                    return Err(FracJsonError::Serde(format!(
+                        "expected {} entries but got {}",
+                        self.expected_length.unwrap_or_default(),
+                        self.length
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeSeq for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        Compound::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeTuple for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        Compound::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeTupleStruct for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        Compound::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeTupleVariant for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        Compound::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeMap for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), FracJsonError> {
+        Compound::serialize_key(self, key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        Compound::serialize_value(self, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeStruct for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FracJsonError> {
+        Compound::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::SerializeStructVariant for Compound<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FracJsonError> {
+        Compound::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<(), FracJsonError> {
+        Compound::end(self)
+    }
+}
+
+fn key_must_be_a_string() -> FracJsonError {
+    FracJsonError::Serde("key must be a string".to_string())
+}
+
+/// Writes map keys. Like serde_json, numbers, bools and chars are converted to strings.
+struct MapKeySerializer<'s, 'a, W: ByteWriter> {
+    bytes: &'s mut W,
+    keys_table: &'s mut EncodeKeysTables<'a>,
+}
+
+impl<'s, 'a, W: ByteWriter> MapKeySerializer<'s, 'a, W> {
+    fn write(self, key: &str) -> Result<(), FracJsonError> {
+        write_transient_key(key, self.bytes, self.keys_table)
+    }
+}
+
+impl<'s, 'a, W: ByteWriter> ser::Serializer for MapKeySerializer<'s, 'a, W> {
+    type Ok = ();
+    type Error = FracJsonError;
+    type SerializeSeq = Impossible<(), FracJsonError>;
+    type SerializeTuple = Impossible<(), FracJsonError>;
+    type SerializeTupleStruct = Impossible<(), FracJsonError>;
+    type SerializeTupleVariant = Impossible<(), FracJsonError>;
+    type SerializeMap = Impossible<(), FracJsonError>;
+    type SerializeStruct = Impossible<(), FracJsonError>;
+    type SerializeStructVariant = Impossible<(), FracJsonError>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), FracJsonError> {
+        self.write(if v { "true" } else { "false" })
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), FracJsonError> {
+        self.write(&v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), FracJsonError> {
+        self.write(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), FracJsonError> {
+        self.write(v)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit(self) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), FracJsonError> {
+        write_key(variant, self.bytes, self.keys_table)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), FracJsonError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, FracJsonError> {
+        Err(key_must_be_a_string())
+    }
+}
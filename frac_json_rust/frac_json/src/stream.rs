@@ -0,0 +1,539 @@
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    byte_stream::StrRead,
+    codec::{DecodeBody, Decoder, Encoder, ValueBody},
+    de::DeserializeBody,
+    error::{FracJsonError, Limit},
+    json_types::value::skip_value,
+    keys_table::{DecodeKeysTables, DecodeSession, EncodeSession},
+    options::{DecodeOptions, EncodeOptions},
+    ser::SerializeBody,
+};
+
+const STREAM_MAGIC: &[u8; 2] = b"FS";
+const STREAM_VERSION: u8 = 0;
+const STREAM_HEADER_SIZE: usize = 3;
+/// Config bit of streams whose records share a local keys table
+const SESSION_FLAG: u8 = 0b00010000;
+/// Size of the length prefix of each record
+const RECORD_LENGTH_SIZE: usize = 4;
+/// Record flag of session streams, set when the local keys table is cleared before the record
+const RECORD_RESET: u8 = 0b00000001;
+
+/// Writes a stream of many frac_json records.
+///
+/// A stream starts with the magic bytes "FS" and a config byte, holding the version and
+/// whether it is a session stream. It is followed by the records, each a complete frac_json
+/// file prefixed with its size as a little endian u32.
+///
+/// Records of a normal stream are encoded independently of each other, so every record can
+/// be decoded on its own. In a session stream, created with `new_session`, the local keys
+/// table is kept from one record to the next, so keys that occurred in earlier records are
+/// written as back-references. Each record is additionally prefixed with a flags byte
+/// marking reset points, where the table starts empty again. Reading can only start at a
+/// reset point, their offsets are returned by `reset_offsets`.
+pub struct StreamWriter<W: Write> {
+    writer: W,
+    encoder: Encoder,
+    /// Position in the stream, including the header
+    offset: usize,
+    session: Option<WriterSession>,
+}
+
+struct WriterSession {
+    keys: EncodeSession,
+    /// Number of records between reset points
+    reset_interval: usize,
+    /// Number of records since the last reset point, 0 if the next record is one
+    records: usize,
+    /// Offsets of the reset points written so far
+    reset_offsets: Vec<usize>,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Starts a new stream by writing the stream header to `writer`.
+    pub fn new(writer: W, encoder: Encoder) -> Result<StreamWriter<W>, FracJsonError> {
+        return StreamWriter::write_header(writer, encoder, None);
+    }
+
+    /// Starts a new session stream, whose records share a local keys table. The table is
+    /// cleared every `reset_interval` records.
+    pub fn new_session(
+        writer: W,
+        encoder: Encoder,
+        reset_interval: usize,
+    ) -> Result<StreamWriter<W>, FracJsonError> {
+        let session = WriterSession::new(reset_interval)?;
+        return StreamWriter::write_header(writer, encoder, Some(session));
+    }
+
+    fn write_header(
+        mut writer: W,
+        encoder: Encoder,
+        session: Option<WriterSession>,
+    ) -> Result<StreamWriter<W>, FracJsonError> {
+        let mut config = STREAM_VERSION;
+        if session.is_some() {
+            config |= SESSION_FLAG;
+        }
+        writer.write_all(STREAM_MAGIC).map_err(FracJsonError::Io)?;
+        writer.write_all(&[config]).map_err(FracJsonError::Io)?;
+        return Ok(StreamWriter {
+            writer,
+            encoder,
+            offset: STREAM_HEADER_SIZE,
+            session,
+        });
+    }
+
+    pub fn from_options(
+        writer: W,
+        options: &EncodeOptions,
+    ) -> Result<StreamWriter<W>, FracJsonError> {
+        return StreamWriter::new(writer, Encoder::from_options(options)?);
+    }
+
+    /// Offsets of the reset points of a session stream written by this writer, each the
+    /// position of the record's length prefix. A `StreamReader` can start reading there,
+    /// see `StreamReader::seek`. Records of a normal stream can be read from any record.
+    pub fn reset_offsets(&self) -> &[usize] {
+        match &self.session {
+            Some(session) => &session.reset_offsets,
+            None => &[],
+        }
+    }
+
+    /// Encodes `json` and appends it as a record.
+    pub fn write(&mut self, json: &Value) -> Result<(), FracJsonError> {
+        let Some(session) = &mut self.session else {
+            let record = self.encoder.encode(json)?;
+            return self.write_record(None, &record);
+        };
+        let flags = session.start_record();
+        let record = self.encoder.encode_in_session(json, &mut session.keys);
+        return self.finish_session_record(flags, record);
+    }
+
+    /// Serializes `value` and appends it as a record, see `Encoder::to_vec`.
+    pub fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FracJsonError> {
+        let Some(session) = &mut self.session else {
+            let record = self.encoder.to_vec(value)?;
+            return self.write_record(None, &record);
+        };
+        let flags = session.start_record();
+        let record = self
+            .encoder
+            .encode_in_session(&SerializeBody(value), &mut session.keys);
+        return self.finish_session_record(flags, record);
+    }
+
+    /// Appends an already encoded frac_json file as a record. In a session stream it is
+    /// written as a reset point, and the following record is one as well.
+    pub fn write_encoded(&mut self, frac_json_bytes: &[u8]) -> Result<(), FracJsonError> {
+        let Some(session) = &mut self.session else {
+            return self.write_record(None, frac_json_bytes);
+        };
+        session.records = 0;
+        return self.write_record(Some(RECORD_RESET), frac_json_bytes);
+    }
+
+    /// Makes the next record of a session stream a reset point.
+    pub fn reset(&mut self) {
+        if let Some(session) = &mut self.session {
+            session.records = 0;
+        }
+    }
+
+    fn finish_session_record(
+        &mut self,
+        flags: u8,
+        record: Result<Vec<u8>, FracJsonError>,
+    ) -> Result<(), FracJsonError> {
+        let result = record.and_then(|record| self.write_record(Some(flags), &record));
+        if result.is_err() {
+            // keys of the failed record may be in the table, but readers won't see them
+            self.reset();
+        }
+        return result;
+    }
+
+    /// Writes the length prefix, the flags byte of session streams and the record.
+    fn write_record(
+        &mut self,
+        flags: Option<u8>,
+        frac_json_bytes: &[u8],
+    ) -> Result<(), FracJsonError> {
+        let Ok(length) = u32::try_from(frac_json_bytes.len()) else {
+            return Err(FracJsonError::LimitExceeded {
+                offset: self.offset,
+                limit: Limit::ValueLength,
+                value: frac_json_bytes.len(),
+                max: u32::MAX as usize,
+            });
+        };
+        self.writer
+            .write_all(&length.to_le_bytes())
+            .map_err(FracJsonError::Io)?;
+        if let (Some(session), Some(RECORD_RESET)) = (&mut self.session, flags) {
+            session.reset_offsets.push(self.offset);
+        }
+        if let Some(flags) = flags {
+            self.writer.write_all(&[flags]).map_err(FracJsonError::Io)?;
+            self.offset += 1;
+        }
+        self.writer
+            .write_all(frac_json_bytes)
+            .map_err(FracJsonError::Io)?;
+        self.offset += RECORD_LENGTH_SIZE + frac_json_bytes.len();
+        return Ok(());
+    }
+
+    pub fn flush(&mut self) -> Result<(), FracJsonError> {
+        return self.writer.flush().map_err(FracJsonError::Io);
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, FracJsonError> {
+        self.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+impl<W: Read + Write + Seek> StreamWriter<W> {
+    /// Appends records to an existing stream, for example a file opened for reading and
+    /// appending. The stream header is read and checked first, `append` fails with
+    /// `InvalidOptions` for session streams, which have to be opened with `append_session`.
+    /// The records are written at the end of `writer`.
+    pub fn append(writer: W, encoder: Encoder) -> Result<StreamWriter<W>, FracJsonError> {
+        return StreamWriter::open_end(writer, encoder, None);
+    }
+
+    /// Like `append`, for a session stream. The first appended record is a reset point.
+    pub fn append_session(
+        writer: W,
+        encoder: Encoder,
+        reset_interval: usize,
+    ) -> Result<StreamWriter<W>, FracJsonError> {
+        let session = WriterSession::new(reset_interval)?;
+        return StreamWriter::open_end(writer, encoder, Some(session));
+    }
+
+    fn open_end(
+        mut writer: W,
+        encoder: Encoder,
+        session: Option<WriterSession>,
+    ) -> Result<StreamWriter<W>, FracJsonError> {
+        writer.seek(SeekFrom::Start(0)).map_err(FracJsonError::Io)?;
+        let is_session = read_header(&mut writer)?;
+        if is_session != session.is_some() {
+            return Err(FracJsonError::InvalidOptions(if is_session {
+                "stream is a session stream, use append_session".to_string()
+            } else {
+                "stream is not a session stream, use append".to_string()
+            }));
+        }
+        let end = writer.seek(SeekFrom::End(0)).map_err(FracJsonError::Io)?;
+        return Ok(StreamWriter {
+            writer,
+            encoder,
+            offset: end as usize,
+            session,
+        });
+    }
+}
+
+impl WriterSession {
+    fn new(reset_interval: usize) -> Result<WriterSession, FracJsonError> {
+        if reset_interval == 0 {
+            return Err(FracJsonError::InvalidOptions(
+                "reset_interval has to be at least 1".to_string(),
+            ));
+        }
+        return Ok(WriterSession {
+            keys: EncodeSession::new(),
+            reset_interval,
+            records: 0,
+            reset_offsets: Vec::new(),
+        });
+    }
+
+    /// Clears the keys table at reset points and returns the flags of the next record.
+    fn start_record(&mut self) -> u8 {
+        let flags = if self.records == 0 {
+            self.keys.reset();
+            RECORD_RESET
+        } else {
+            0
+        };
+        self.records = (self.records + 1) % self.reset_interval;
+        return flags;
+    }
+}
+
+/// Reads the records of a stream written by `StreamWriter` one by one.
+///
+/// As an iterator it yields the decoded records. A truncated or otherwise invalid stream
+/// ends the iteration after its error. In normal streams a record that fails to decode does
+/// not affect the following ones, in session streams it ends the iteration as well.
+///
+/// Errors in the framing of the stream, like a truncated record or a record exceeding
+/// `max_decompressed_size`, carry the offset in the stream. Errors from decoding a record
+/// carry the offset relative to the start of that record's frac_json bytes.
+pub struct StreamReader<R: Read> {
+    reader: R,
+    decoder: Decoder,
+    /// Position in the stream
+    offset: usize,
+    /// Bytes of the last record read
+    record: Vec<u8>,
+    /// Keys table shared by the records of a session stream
+    session: Option<DecodeSession>,
+    /// Whether the next record has to be a reset point, after seeking in a session stream
+    require_reset: bool,
+    finished: bool,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Reads and checks the stream header from `reader`.
+    pub fn new(mut reader: R, decoder: Decoder) -> Result<StreamReader<R>, FracJsonError> {
+        let is_session = read_header(&mut reader)?;
+        return Ok(StreamReader {
+            reader,
+            decoder,
+            offset: STREAM_HEADER_SIZE,
+            record: Vec::new(),
+            session: is_session.then(DecodeSession::new),
+            require_reset: false,
+            finished: false,
+        });
+    }
+
+    pub fn from_options(
+        reader: R,
+        options: &DecodeOptions,
+    ) -> Result<StreamReader<R>, FracJsonError> {
+        return StreamReader::new(reader, Decoder::from_options(options)?);
+    }
+
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Whether the records share a local keys table, see `StreamWriter::new_session`.
+    pub fn is_session(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Reads the bytes of the next record, a complete frac_json file, without decoding it.
+    /// Returns `None` at the end of the stream.
+    ///
+    /// Records of a session stream are still read through to keep track of their keys, and
+    /// can reference keys of earlier records, so they can't be decoded on their own.
+    pub fn next_record(&mut self) -> Result<Option<&[u8]>, FracJsonError> {
+        if !self.read_record()? {
+            return Ok(None);
+        }
+        if self.session.is_some() {
+            self.decode_record(SkipBody)?;
+        }
+        return Ok(Some(&self.record));
+    }
+
+    /// Decodes the next record into a `Value`.
+    pub fn next_value(&mut self) -> Result<Option<Value>, FracJsonError> {
+        if !self.read_record()? {
+            return Ok(None);
+        }
+        return Ok(Some(self.decode_record(ValueBody)?));
+    }
+
+    /// Deserializes the next record into a `T`, see `Decoder::from_slice`.
+    pub fn next_deserialized<T: DeserializeOwned>(&mut self) -> Result<Option<T>, FracJsonError> {
+        if !self.read_record()? {
+            return Ok(None);
+        }
+        return Ok(Some(self.decode_record(DeserializeBody::new())?));
+    }
+
+    fn decode_record<'s, B: DecodeBody<'s>>(
+        &'s mut self,
+        body: B,
+    ) -> Result<B::Output, FracJsonError> {
+        let Some(session) = &mut self.session else {
+            return self.decoder.decode_body(&self.record, body);
+        };
+        let result = self.decoder.decode_in_session(&self.record, body, session);
+        if result.is_err() {
+            // keys of the following records can't be resolved anymore
+            self.finished = true;
+        }
+        return result;
+    }
+
+    /// Reads the next record into `self.record`. Returns false at the end of the stream.
+    fn read_record(&mut self) -> Result<bool, FracJsonError> {
+        if self.finished {
+            return Ok(false);
+        }
+        let result = self.try_read_record();
+        if !matches!(result, Ok(true)) {
+            self.finished = true;
+        }
+        return result;
+    }
+
+    fn try_read_record(&mut self) -> Result<bool, FracJsonError> {
+        let mut length_bytes = [0u8; RECORD_LENGTH_SIZE];
+        let available = read_full(&mut self.reader, &mut length_bytes)?;
+        if available == 0 {
+            return Ok(false);
+        }
+        if available < RECORD_LENGTH_SIZE {
+            return Err(FracJsonError::UnexpectedEof {
+                offset: self.offset,
+                needed: RECORD_LENGTH_SIZE,
+                available,
+            });
+        }
+        self.offset += RECORD_LENGTH_SIZE;
+        let length = u32::from_le_bytes(length_bytes) as usize;
+        if let Some(session) = &mut self.session {
+            let mut flags = [0u8; 1];
+            if read_full(&mut self.reader, &mut flags)? == 0 {
+                return Err(FracJsonError::UnexpectedEof {
+                    offset: self.offset,
+                    needed: 1,
+                    available: 0,
+                });
+            }
+            if flags[0] & RECORD_RESET != 0 {
+                session.reset();
+            } else if self.require_reset {
+                return Err(FracJsonError::NotAResetPoint {
+                    offset: self.offset - RECORD_LENGTH_SIZE,
+                });
+            }
+            self.require_reset = false;
+            self.offset += 1;
+        }
+        // a record can't be larger than the decoder is willing to decompress
+        let max_size = self.decoder.limits().max_decompressed_size;
+        if length > max_size {
+            return Err(FracJsonError::LimitExceeded {
+                offset: self.offset,
+                limit: Limit::DecompressedSize,
+                value: length,
+                max: max_size,
+            });
+        }
+        // grow the buffer while reading, instead of trusting the length prefix
+        self.record.clear();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut self.record)
+            .map_err(FracJsonError::Io)?;
+        if self.record.len() < length {
+            return Err(FracJsonError::UnexpectedEof {
+                offset: self.offset,
+                needed: length,
+                available: self.record.len(),
+            });
+        }
+        self.offset += length;
+        return Ok(true);
+    }
+}
+
+impl<R: Read + Seek> StreamReader<R> {
+    /// Reads the stream header from the start of `reader` and starts reading at the record
+    /// at `offset`, the position of its length prefix. In a session stream the record has to
+    /// be a reset point, see `StreamWriter::reset_offsets`, otherwise reading it fails with
+    /// `NotAResetPoint`.
+    pub fn seek(
+        mut reader: R,
+        decoder: Decoder,
+        offset: usize,
+    ) -> Result<StreamReader<R>, FracJsonError> {
+        reader.seek(SeekFrom::Start(0)).map_err(FracJsonError::Io)?;
+        let mut stream_reader = StreamReader::new(reader, decoder)?;
+        if offset < STREAM_HEADER_SIZE {
+            return Err(FracJsonError::InvalidOptions(format!(
+                "offset {} is inside the stream header",
+                offset
+            )));
+        }
+        stream_reader
+            .reader
+            .seek(SeekFrom::Start(offset as u64))
+            .map_err(FracJsonError::Io)?;
+        stream_reader.offset = offset;
+        stream_reader.require_reset = stream_reader.session.is_some();
+        return Ok(stream_reader);
+    }
+}
+
+impl<R: Read> Iterator for StreamReader<R> {
+    type Item = Result<Value, FracJsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_value().transpose()
+    }
+}
+
+/// Reads and checks the stream header, returns whether it is a session stream.
+fn read_header<R: Read>(reader: &mut R) -> Result<bool, FracJsonError> {
+    let mut header = [0u8; STREAM_HEADER_SIZE];
+    let available = read_full(reader, &mut header)?;
+    if available < STREAM_HEADER_SIZE {
+        return Err(FracJsonError::UnexpectedEof {
+            offset: 0,
+            needed: STREAM_HEADER_SIZE,
+            available,
+        });
+    }
+    if header[..2] != *STREAM_MAGIC {
+        return Err(FracJsonError::InvalidMagic {
+            offset: 0,
+            magic: [header[0], header[1]],
+        });
+    }
+    let version = header[2] & 0b00001111;
+    if version > STREAM_VERSION {
+        return Err(FracJsonError::UnsupportedVersion { offset: 2, version });
+    }
+    return Ok(header[2] & SESSION_FLAG != 0);
+}
+
+/// Reads past a record of a session stream, adding its keys to the keys table.
+struct SkipBody;
+
+impl<'de> DecodeBody<'de> for SkipBody {
+    type Output = ();
+
+    fn read_body<R: StrRead<'de>>(
+        self,
+        mut bytes: R,
+        mut keys_table: DecodeKeysTables,
+    ) -> Result<(), FracJsonError> {
+        skip_value(&mut bytes, &mut keys_table)
+    }
+}
+
+/// Reads until `buf` is full or the end of `reader` is reached. Returns the number of bytes
+/// read.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, FracJsonError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(FracJsonError::Io(err)),
+        }
+    }
+    return Ok(filled);
+}
@@ -1,9 +1,21 @@
-
 #[cfg(test)]
 mod tests {
+	use crate::{
+		decode, decode_borrowed, decode_from_reader, decode_with_options, diff_global_tables,
+		encode, encode_to_writer, extract, extract_many, from_reader, from_slice,
+		global_table_from_json, global_table_from_json_limited, global_table_from_keys,
+		is_compatible_extension, merge_global_tables, to_vec, to_writer, transcode_from_json,
+		transcode_to_json, ArchiveReader, ArchiveWriter, DecodeLimits, DecodeOptions, Decoder,
+		EncodeOptions, Encoder, FracDocument, FracJsonError, FracValue, GlobalKeysTable,
+		GlobalKeysTableSet, KeysTableBuilder, KeysTableDiff, KeysTableMetadata, Limit, MovedKey,
+		StreamReader, StreamWriter, Utf8Validation,
+	};
+	use serde::{Deserialize, Serialize};
 	use serde_json::Value;
-	use crate::{encode, decode};
-
+	use std::{
+		borrow::Cow,
+		collections::{BTreeMap, HashMap},
+	};
 
 	fn test_file(s: &str, encoded_size: usize) {
 		let value = serde_json::from_str::<Value>(s).unwrap();
@@ -183,6 +195,7 @@ mod tests {
 	}
 	
 	#[test]
+	#[allow(clippy::identity_op)]
 	fn test_combined() {
 		let s = include_str!("./test_files/combined.json");
 		test_file(s, 3+1+18*2+123+2*1+2*2+2*4+2*8+4+8+1+94+3*2+27+4+21+13);
@@ -193,4 +206,2202 @@ mod tests {
 		let s = include_str!("./test_files/combined.json");
 		test_file_compressed(s);
 	}
+
+	#[test]
+	fn test_encode_to_writer() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let mut frac_json_bytes = Vec::new();
+		encode_to_writer(&value, &mut frac_json_bytes, &EncodeOptions::default()).unwrap();
+		assert_eq!(encode(&value, None, None, None).unwrap(), frac_json_bytes);
+	}
+
+	#[test]
+	fn test_encode_to_writer_compressed() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let global_table =
+			global_table_from_keys(vec!["key1".to_string(), "key2".to_string()]).unwrap();
+		let options = EncodeOptions {
+			global_keys_table_bytes: Some(&global_table),
+			compression_level: Some(3),
+			..Default::default()
+		};
+		let mut frac_json_bytes = Vec::new();
+		encode_to_writer(&value, &mut frac_json_bytes, &options).unwrap();
+		assert_eq!(
+			value,
+			decode(&frac_json_bytes, Some(&global_table), None).unwrap()
+		);
+	}
+
+	#[test]
+	fn test_encode_to_writer_io_error() {
+		struct FailingWriter;
+		impl std::io::Write for FailingWriter {
+			fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+				Err(std::io::Error::other("disk full"))
+			}
+			fn flush(&mut self) -> std::io::Result<()> {
+				Ok(())
+			}
+		}
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let err = encode_to_writer(&value, FailingWriter, &EncodeOptions::default()).unwrap_err();
+		assert!(matches!(err, FracJsonError::Io(_)));
+	}
+
+	#[test]
+	fn test_decode_from_reader() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let global_table =
+			global_table_from_keys(vec!["key1".to_string(), "key2".to_string()]).unwrap();
+		let options = DecodeOptions {
+			global_keys_table_bytes: Some(&global_table),
+			..Default::default()
+		};
+		for compression_level in [None, Some(3)] {
+			let frac_json_bytes =
+				encode(&value, Some(&global_table), compression_level, None).unwrap();
+			assert_eq!(
+				value,
+				decode_from_reader(frac_json_bytes.as_slice(), &options).unwrap()
+			);
+		}
+	}
+
+	#[test]
+	fn test_decode_from_reader_errors() {
+		// string8 of length 100 with only 1 byte
+		let err =
+			decode_from_reader(&b"FJ\x00\x0D\x64a"[..], &DecodeOptions::default()).unwrap_err();
+		assert!(matches!(
+			err,
+			FracJsonError::UnexpectedEof {
+				offset: 5,
+				needed: 100,
+				available: 1
+			}
+		));
+		let err =
+			decode_from_reader(&b"FJ\x00\x18\xC3\x28"[..], &DecodeOptions::default()).unwrap_err();
+		assert!(matches!(err, FracJsonError::InvalidUtf8 { offset: 4 }));
+
+		let s = include_str!("./test_files/string16.json");
+		let value = serde_json::from_str::<Value>(s).unwrap();
+		let frac_json_bytes = encode(&value, None, Some(3), None).unwrap();
+		let limits = DecodeLimits {
+			max_decompressed_size: 502,
+			..Default::default()
+		};
+		let options = DecodeOptions {
+			limits,
+			..Default::default()
+		};
+		assert_limit_exceeded(
+			decode_from_reader(frac_json_bytes.as_slice(), &options),
+			Limit::DecompressedSize,
+		);
+	}
+
+	#[test]
+	fn test_reusable_encoder_decoder() {
+		let global_table = GlobalKeysTable::new(vec!["key1".to_string(), "key2".to_string()]);
+		let global_table_bytes = global_table.to_bytes().unwrap();
+		assert_eq!(
+			global_table,
+			GlobalKeysTable::from_bytes(&global_table_bytes).unwrap()
+		);
+		// raw content dictionary
+		let zstd_dict = b"key1 key2 value string array object".repeat(10);
+		let encoder = Encoder::new(Some(global_table.clone()), Some(3), Some(&zstd_dict)).unwrap();
+		let decoder = Decoder::new(Some(global_table), Some(&zstd_dict));
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		for _ in 0..3 {
+			let frac_json_bytes = encoder.encode(&value).unwrap();
+			assert_eq!(value, decoder.decode(&frac_json_bytes).unwrap());
+			assert_eq!(
+				value,
+				decode(
+					&frac_json_bytes,
+					Some(&global_table_bytes),
+					Some(&zstd_dict)
+				)
+				.unwrap()
+			);
+			let mut streamed_bytes = Vec::new();
+			encoder
+				.encode_to_writer(&value, &mut streamed_bytes)
+				.unwrap();
+			assert_eq!(
+				value,
+				decoder
+					.decode_from_reader(streamed_bytes.as_slice())
+					.unwrap()
+			);
+		}
+		assert!(matches!(
+			Encoder::new(None, None, Some(&zstd_dict)),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+		let frac_json_bytes = encoder.encode(&value).unwrap();
+		let err = Decoder::new(None, None)
+			.decode(&frac_json_bytes)
+			.unwrap_err();
+		assert!(matches!(err, FracJsonError::MissingZstdDict));
+	}
+
+	#[test]
+	fn test_shared_encoder_decoder() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<Encoder>();
+		assert_send_sync::<Decoder>();
+
+		let encoder = std::sync::Arc::new(Encoder::new(None, Some(3), None).unwrap());
+		let decoder = std::sync::Arc::new(Decoder::new(None, None));
+		let threads: Vec<_> = (0..4)
+			.map(|i| {
+				let encoder = encoder.clone();
+				let decoder = decoder.clone();
+				std::thread::spawn(move || {
+					let value = serde_json::json!({ "thread": i, "values": [i, i + 1] });
+					assert_eq!(
+						value,
+						decoder.decode(&encoder.encode(&value).unwrap()).unwrap()
+					);
+				})
+			})
+			.collect();
+		for thread in threads {
+			thread.join().unwrap();
+		}
+	}
+
+	#[test]
+	fn test_decode_borrowed() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let frac_value = decode_borrowed(&frac_json_bytes).unwrap();
+		assert_eq!(value, Value::from(frac_value.clone()));
+		// re-encoding keeps the same order and encoding
+		assert_eq!(frac_json_bytes, to_vec(&frac_value).unwrap());
+		assert_eq!(value, Value::from(frac_value.into_owned()));
+
+		let json = serde_json::json!([{"key": "value", "global": 1}, {"key": "", "global": 2.5}]);
+		let global_table = GlobalKeysTable::new(vec!["global".to_string()]);
+		let encoder = Encoder::new(Some(global_table.clone()), None, None).unwrap();
+		let decoder = Decoder::new(Some(global_table), None);
+		let frac_json_bytes = encoder.encode(&json).unwrap();
+		let frac_value = decoder.decode_borrowed(&frac_json_bytes).unwrap();
+		let input = frac_json_bytes.as_ptr_range();
+		let array = frac_value.as_array().unwrap();
+		for (i, entry) in array.iter().enumerate() {
+			let object = entry.as_object().unwrap();
+			// immediate and back referenced keys borrow from the input, global keys from the table
+			assert!(matches!(&object[0].0, Cow::Borrowed(key) if input.contains(&key.as_ptr())));
+			assert!(matches!(&object[1].0, Cow::Borrowed(key) if !input.contains(&key.as_ptr())));
+			assert_eq!(
+				json[i]["global"].as_f64(),
+				entry.get("global").unwrap().as_f64()
+			);
+		}
+		assert!(matches!(
+			array[0].get("key"),
+			Some(FracValue::String(Cow::Borrowed("value")))
+		));
+		assert_eq!(Some(""), array[1].get("key").unwrap().as_str());
+		assert_eq!(None, array[1].get("missing"));
+
+		// compressed files can't be borrowed from, but decode to the same value
+		let frac_json_bytes = encode(&value, None, Some(3), None).unwrap();
+		let frac_value = decode_borrowed(&frac_json_bytes).unwrap();
+		assert_eq!(value, Value::from(frac_value));
+	}
+
+	#[test]
+	fn test_cursor() {
+		// "a", "b" and "c" are first used in the subtree that is skipped by the first lookup
+		let json = serde_json::json!({
+			"skipped": [{"a": 1, "b": {"c": 2}}],
+			"list": [{"a": 3, "c": 4}, {"b": "x"}],
+			"name": "document",
+		});
+		let frac_json_bytes = encode(&json, None, None, None).unwrap();
+		let document = FracDocument::new(&frac_json_bytes).unwrap();
+		let root = document.root();
+		let list = root.get("list").unwrap().unwrap();
+		assert_eq!(Some(2), list.len().unwrap());
+		let c = list.index(0).unwrap().unwrap().get("c").unwrap().unwrap();
+		assert_eq!(Some(4), c.as_i64().unwrap());
+		assert_eq!(
+			Some("x"),
+			list.index(1)
+				.unwrap()
+				.unwrap()
+				.get("b")
+				.unwrap()
+				.unwrap()
+				.as_str()
+				.unwrap()
+				.as_deref()
+		);
+		assert!(list.index(2).unwrap().is_none());
+		assert!(root.get("missing").unwrap().is_none());
+		assert!(root.index(0).unwrap().is_none());
+		// going back to a part that was skipped before
+		let skipped = root
+			.get("skipped")
+			.unwrap()
+			.unwrap()
+			.index(0)
+			.unwrap()
+			.unwrap();
+		assert_eq!(
+			Some(2),
+			skipped
+				.get("b")
+				.unwrap()
+				.unwrap()
+				.get("c")
+				.unwrap()
+				.unwrap()
+				.as_u64()
+				.unwrap()
+		);
+		let name = root
+			.get("name")
+			.unwrap()
+			.unwrap()
+			.as_str()
+			.unwrap()
+			.unwrap();
+		assert!(matches!(name, Cow::Borrowed("document")));
+
+		let keys: Vec<_> = root
+			.iter_entries()
+			.unwrap()
+			.unwrap()
+			.map(|entry| entry.unwrap().0.into_owned())
+			.collect();
+		assert_eq!(vec!["skipped", "list", "name"], keys);
+		for entry in root.iter_entries().unwrap().unwrap() {
+			let (key, value) = entry.unwrap();
+			assert_eq!(json[key.as_ref()], value.to_value().unwrap());
+		}
+		let elements: Vec<_> = list
+			.iter_elements()
+			.unwrap()
+			.unwrap()
+			.map(|element| element.unwrap().to_value().unwrap())
+			.collect();
+		assert_eq!(json["list"].as_array().unwrap(), &elements);
+		assert_eq!(json, root.to_value().unwrap());
+	}
+
+	#[test]
+	fn test_cursor_options() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let global_table = GlobalKeysTable::new(vec!["string8".to_string(), "array8".to_string()]);
+		let encoder = Encoder::new(Some(global_table.clone()), Some(3), None).unwrap();
+		let frac_json_bytes = encoder.encode(&value).unwrap();
+		let decoder = Decoder::new(Some(global_table), None);
+		let document = decoder.document(&frac_json_bytes).unwrap();
+		assert_eq!(value, document.root().to_value().unwrap());
+		let (key, _) = document
+			.root()
+			.iter_entries()
+			.unwrap()
+			.unwrap()
+			.next()
+			.unwrap()
+			.unwrap();
+		assert_eq!(value.as_object().unwrap().keys().next().unwrap(), &key);
+
+		let limits = DecodeLimits {
+			max_depth: 1,
+			..DecodeLimits::default()
+		};
+		let decoder = decoder.with_limits(limits);
+		let document = decoder.document(&frac_json_bytes).unwrap();
+		let result = document.root().to_value();
+		assert!(matches!(
+			result,
+			Err(FracJsonError::LimitExceeded {
+				limit: Limit::Depth,
+				..
+			})
+		));
+
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let document = FracDocument::new(&frac_json_bytes[..frac_json_bytes.len() - 1]).unwrap();
+		assert!(matches!(
+			document.root().to_value(),
+			Err(FracJsonError::UnexpectedEof { .. })
+		));
+	}
+
+	#[test]
+	fn test_extract() {
+		let json = serde_json::json!({
+			"meta": {"id": 7, "tags": ["a", "b"]},
+			"data": {"children": [{"title": "first"}, {"title": "second", "a/b": 1, "m~n": 2}]},
+			"": "empty key",
+		});
+		let frac_json_bytes = encode(&json, None, Some(3), None).unwrap();
+		let options = DecodeOptions::default();
+		let extract_value = |pointer| extract(&frac_json_bytes, pointer, &options).unwrap();
+		assert_eq!(Some(json.clone()), extract_value(""));
+		assert_eq!(
+			Some(Value::from("second")),
+			extract_value("/data/children/1/title")
+		);
+		assert_eq!(Some(Value::from(1)), extract_value("/data/children/1/a~1b"));
+		assert_eq!(Some(Value::from(2)), extract_value("/data/children/1/m~0n"));
+		assert_eq!(Some(json["meta"].clone()), extract_value("/meta"));
+		assert_eq!(Some(Value::from("empty key")), extract_value("/"));
+		for missing in [
+			"/missing",
+			"/meta/tags/2",
+			"/meta/tags/01",
+			"/meta/tags/-",
+			"/meta/id/0",
+			"/data/children/x",
+		] {
+			assert_eq!(None, extract_value(missing), "{}", missing);
+		}
+		assert!(matches!(
+			extract(&frac_json_bytes, "meta", &options),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+
+		let pointers = [
+			"/data/children/1/title",
+			"/meta/id",
+			"/missing",
+			"/meta",
+			"/meta/tags/1",
+			"/data/children/0/title",
+		];
+		let values = extract_many(&frac_json_bytes, &pointers, &options).unwrap();
+		let expected: Vec<_> = pointers
+			.iter()
+			.map(|pointer| json.pointer(pointer).cloned())
+			.collect();
+		assert_eq!(expected, values);
+	}
+
+	#[test]
+	fn test_duplicate_keys() {
+		// later entries replace earlier ones in all decode targets
+		let json = br#"{"a": 1, "b": {"c": 1}, "a": 2, "b": {"c": 2, "d": 3}}"#;
+		let mut frac_json_bytes = Vec::new();
+		transcode_from_json(&json[..], &mut frac_json_bytes, &EncodeOptions::default()).unwrap();
+		let expected = serde_json::json!({"a": 2, "b": {"c": 2, "d": 3}});
+		assert_eq!(expected, decode(&frac_json_bytes, None, None).unwrap());
+		let frac_value = decode_borrowed(&frac_json_bytes).unwrap();
+		assert_eq!(Some(2), frac_value.get("a").and_then(FracValue::as_i64));
+		let document = FracDocument::new(&frac_json_bytes).unwrap();
+		assert_eq!(
+			Some(2),
+			document.root().get("a").unwrap().unwrap().as_i64().unwrap()
+		);
+		assert_eq!(
+			Some(3),
+			document
+				.root()
+				.pointer("/b/d")
+				.unwrap()
+				.unwrap()
+				.as_i64()
+				.unwrap()
+		);
+		let options = DecodeOptions::default();
+		assert_eq!(
+			Some(Value::from(2)),
+			extract(&frac_json_bytes, "/b/c", &options).unwrap()
+		);
+		let values = extract_many(&frac_json_bytes, &["/a", "/b/c", "/b"], &options).unwrap();
+		assert_eq!(
+			vec![
+				Some(expected["a"].clone()),
+				Some(Value::from(2)),
+				Some(expected["b"].clone())
+			],
+			values
+		);
+	}
+
+	#[test]
+	fn test_extract_with_decoder() {
+		let global_table = GlobalKeysTable::new(vec!["title".to_string()]);
+		let encoder = Encoder::new(Some(global_table.clone()), None, None).unwrap();
+		let decoder = Decoder::new(Some(global_table), None);
+		let records: Vec<Value> = (0..3)
+			.map(|i| serde_json::json!({"skipped": {"title": i}, "level": i, "record": {"title": format!("record {}", i)}}))
+			.collect();
+		for record in records {
+			let frac_json_bytes = encoder.encode(&record).unwrap();
+			assert_eq!(
+				Some(record["record"]["title"].clone()),
+				decoder.extract(&frac_json_bytes, "/record/title").unwrap()
+			);
+			let values = decoder
+				.extract_many(&frac_json_bytes, &["/level", "/record/title"])
+				.unwrap();
+			assert_eq!(
+				vec![
+					Some(record["level"].clone()),
+					Some(record["record"]["title"].clone())
+				],
+				values
+			);
+		}
+	}
+
+	#[test]
+	fn test_transcode_from_json() {
+		let s = include_str!("./test_files/combined.json");
+		let value = serde_json::from_str::<Value>(s).unwrap();
+		let mut frac_json_bytes = Vec::new();
+		transcode_from_json(
+			s.as_bytes(),
+			&mut frac_json_bytes,
+			&EncodeOptions::default(),
+		)
+		.unwrap();
+		assert_eq!(encode(&value, None, None, None).unwrap(), frac_json_bytes);
+
+		let global_table =
+			global_table_from_keys(vec!["data".to_string(), "children".to_string()]).unwrap();
+		let options = EncodeOptions {
+			global_keys_table_bytes: Some(&global_table),
+			compression_level: Some(3),
+			..Default::default()
+		};
+		let mut frac_json_bytes = Vec::new();
+		transcode_from_json(s.as_bytes(), &mut frac_json_bytes, &options).unwrap();
+		assert_eq!(
+			value,
+			decode(&frac_json_bytes, Some(&global_table), None).unwrap()
+		);
+
+		let json = r#"{"int": -5, "big": 18446744073709551615, "float": 1.5, "nested": [[], {}, {"int": null}]}"#;
+		let mut frac_json_bytes = Vec::new();
+		Encoder::new(None, None, None)
+			.unwrap()
+			.transcode_from_json(json.as_bytes(), &mut frac_json_bytes)
+			.unwrap();
+		let value = serde_json::from_str::<Value>(json).unwrap();
+		assert_eq!(encode(&value, None, None, None).unwrap(), frac_json_bytes);
+	}
+
+	#[test]
+	fn test_transcode_from_json_errors() {
+		for json in ["{\"a\": [1, 2", "[1, 2] 3", "{\"a\" 1}", ""] {
+			let mut frac_json_bytes = Vec::new();
+			let err = transcode_from_json(
+				json.as_bytes(),
+				&mut frac_json_bytes,
+				&EncodeOptions::default(),
+			)
+			.unwrap_err();
+			assert!(matches!(err, FracJsonError::Serde(_)), "{:?}", err);
+		}
+	}
+
+	#[test]
+	fn test_transcode_to_json() {
+		let s = include_str!("./test_files/combined.json");
+		let value = serde_json::from_str::<Value>(s).unwrap();
+		for compression_level in [None, Some(3)] {
+			let frac_json_bytes = encode(&value, None, compression_level, None).unwrap();
+			let mut json = Vec::new();
+			transcode_to_json(
+				&frac_json_bytes[..],
+				&mut json,
+				false,
+				&DecodeOptions::default(),
+			)
+			.unwrap();
+			assert_eq!(serde_json::to_vec(&value).unwrap(), json);
+			let mut json = Vec::new();
+			transcode_to_json(
+				&frac_json_bytes[..],
+				&mut json,
+				true,
+				&DecodeOptions::default(),
+			)
+			.unwrap();
+			assert_eq!(serde_json::to_vec_pretty(&value).unwrap(), json);
+		}
+
+		// keys from both tables need escaping
+		let keys = vec!["quote\"d".to_string(), "line\nbreak".to_string()];
+		let global_table = GlobalKeysTable::new(keys.clone());
+		let value = serde_json::json!([{"quote\"d": 1, "tab\tbed": "\u{1}"}, {"line\nbreak": {}, "tab\tbed": []}]);
+		let frac_json_bytes = Encoder::new(Some(global_table.clone()), None, None)
+			.unwrap()
+			.encode(&value)
+			.unwrap();
+		let decoder = Decoder::new(Some(global_table), None);
+		let mut json = Vec::new();
+		decoder
+			.transcode_to_json(&frac_json_bytes, &mut json, false)
+			.unwrap();
+		assert_eq!(serde_json::to_vec(&value).unwrap(), json);
+		let mut json = Vec::new();
+		decoder
+			.transcode_to_json_from_reader(&frac_json_bytes[..], &mut json, true)
+			.unwrap();
+		assert_eq!(serde_json::to_vec_pretty(&value).unwrap(), json);
+	}
+
+	#[test]
+	fn test_transcode_to_json_floats() {
+		let transcode = |frac_json_bytes: &[u8]| {
+			let mut json = Vec::new();
+			transcode_to_json(frac_json_bytes, &mut json, false, &DecodeOptions::default())
+				.unwrap();
+			String::from_utf8(json).unwrap()
+		};
+		let mut float = b"FJ\x00\x0B".to_vec();
+		float.extend(0.1f32.to_le_bytes());
+		assert_eq!("0.1", transcode(&float));
+		let mut double = b"FJ\x00\x0C".to_vec();
+		double.extend(0.1f64.to_le_bytes());
+		assert_eq!("0.1", transcode(&double));
+		let mut nan = b"FJ\x00\x13\x02\x0B".to_vec();
+		nan.extend(f32::NAN.to_le_bytes());
+		nan.push(0x0C);
+		nan.extend(f64::INFINITY.to_le_bytes());
+		assert_eq!("[null,null]", transcode(&nan));
+		let value = serde_json::json!([1.5, -2.25e-10, 1e300, 3]);
+		assert_eq!(
+			serde_json::to_string(&value).unwrap(),
+			transcode(&encode(&value, None, None, None).unwrap())
+		);
+	}
+
+	#[test]
+	fn test_transcode_to_json_errors() {
+		let mut json = Vec::new();
+		let err = transcode_to_json(
+			&b"FJ\x00\x13\x02\x03"[..],
+			&mut json,
+			false,
+			&DecodeOptions::default(),
+		)
+		.unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::UnexpectedEof { .. }),
+			"{:?}",
+			err
+		);
+		let options = DecodeOptions {
+			limits: DecodeLimits {
+				max_depth: 1,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let frac_json_bytes = encode(&serde_json::json!([[1]]), None, None, None).unwrap();
+		let err = transcode_to_json(&frac_json_bytes[..], &mut json, false, &options).unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::LimitExceeded {
+					limit: Limit::Depth,
+					..
+				}
+			),
+			"{:?}",
+			err
+		);
+	}
+
+	#[test]
+	fn test_stream() {
+		let records: Vec<Value> = (0..100)
+			.map(|i| serde_json::json!({"id": i, "body": format!("comment {}", i), "replies": [], "score": i as f64 / 4.0}))
+			.collect();
+		for compression_level in [None, Some(3)] {
+			let options = EncodeOptions {
+				compression_level,
+				..Default::default()
+			};
+			let mut writer = StreamWriter::from_options(Vec::new(), &options).unwrap();
+			for record in &records[..50] {
+				writer.write(record).unwrap();
+			}
+			let mut stream_bytes = writer.into_inner().unwrap();
+			assert_eq!(b"FS\x00", &stream_bytes[..3]);
+			let mut writer = StreamWriter::append(
+				std::io::Cursor::new(&mut stream_bytes),
+				Encoder::from_options(&options).unwrap(),
+			)
+			.unwrap();
+			for record in &records[50..] {
+				writer.serialize(record).unwrap();
+			}
+			writer.flush().unwrap();
+			let reader =
+				StreamReader::from_options(&stream_bytes[..], &DecodeOptions::default()).unwrap();
+			let decoded: Vec<Value> = reader.map(Result::unwrap).collect();
+			assert_eq!(records, decoded);
+
+			let mut reader =
+				StreamReader::new(&stream_bytes[..], Decoder::new(None, None)).unwrap();
+			let first = reader.next_record().unwrap().unwrap().to_vec();
+			assert_eq!(records[0], reader.decoder().decode(&first).unwrap());
+			let second: BTreeMap<String, Value> = reader.next_deserialized().unwrap().unwrap();
+			assert_eq!(records[1]["body"], second["body"]);
+			assert_eq!(98, reader.count());
+		}
+
+		let mut writer =
+			StreamWriter::new(Vec::new(), Encoder::new(None, None, None).unwrap()).unwrap();
+		writer
+			.write_encoded(&encode(&records[0], None, None, None).unwrap())
+			.unwrap();
+		let stream_bytes = writer.into_inner().unwrap();
+		let mut reader = StreamReader::new(&stream_bytes[..], Decoder::new(None, None)).unwrap();
+		assert_eq!(Some(records[0].clone()), reader.next_value().unwrap());
+		assert!(reader.next_value().unwrap().is_none());
+	}
+
+	#[test]
+	fn test_stream_session() {
+		let records: Vec<Value> = (0..100)
+			.map(|i| serde_json::json!({"id": i, "author": format!("user {}", i % 7), "subreddit_name_prefixed": "r/rust", "replies": [{"id": i}]}))
+			.collect();
+		let write_stream = |session: Option<usize>, compression_level: Option<i32>| {
+			let encoder = Encoder::new(None, compression_level, None).unwrap();
+			let mut writer = match session {
+				Some(reset_interval) => {
+					StreamWriter::new_session(Vec::new(), encoder, reset_interval).unwrap()
+				}
+				None => StreamWriter::new(Vec::new(), encoder).unwrap(),
+			};
+			for (i, record) in records.iter().enumerate() {
+				if i % 2 == 0 {
+					writer.write(record).unwrap();
+				} else {
+					writer.serialize(record).unwrap();
+				}
+			}
+			writer.into_inner().unwrap()
+		};
+		let read_stream = |stream_bytes: &[u8]| -> Vec<Value> {
+			StreamReader::new(stream_bytes, Decoder::new(None, None))
+				.unwrap()
+				.map(Result::unwrap)
+				.collect()
+		};
+
+		let plain = write_stream(None, None);
+		for compression_level in [None, Some(3)] {
+			for reset_interval in [1, 10, 1000] {
+				let stream_bytes = write_stream(Some(reset_interval), compression_level);
+				assert_eq!(b"FS\x10", &stream_bytes[..3]);
+				assert_eq!(records, read_stream(&stream_bytes));
+				let mut reader =
+					StreamReader::new(&stream_bytes[..], Decoder::new(None, None)).unwrap();
+				assert!(reader.is_session());
+				assert!(reader.next_record().unwrap().is_some());
+				let second: BTreeMap<String, Value> = reader.next_deserialized().unwrap().unwrap();
+				assert_eq!(records[1]["author"], second["author"]);
+				assert_eq!(records[2..], reader.map(Result::unwrap).collect::<Vec<_>>());
+			}
+		}
+		// with a reset before every record, only the flags bytes are added
+		assert_eq!(
+			plain.len() + records.len(),
+			write_stream(Some(1), None).len()
+		);
+		assert!(write_stream(Some(10), None).len() < plain.len() * 2 / 3);
+
+		// appended records, pre encoded records and explicit resets
+		let mut stream_bytes = Vec::new();
+		let mut writer = StreamWriter::new_session(
+			&mut stream_bytes,
+			Encoder::new(None, None, None).unwrap(),
+			100,
+		)
+		.unwrap();
+		writer.write(&records[0]).unwrap();
+		writer
+			.write_encoded(&encode(&records[1], None, None, None).unwrap())
+			.unwrap();
+		writer.write(&records[2]).unwrap();
+		writer.reset();
+		writer.write(&records[3]).unwrap();
+		// every record of the first writer is a reset point, of the appended ones only the first
+		let mut reset_offsets = writer.reset_offsets().to_vec();
+		assert_eq!(3, reset_offsets[0]);
+		let mut writer = StreamWriter::append_session(
+			std::io::Cursor::new(&mut stream_bytes),
+			Encoder::new(None, None, None).unwrap(),
+			100,
+		)
+		.unwrap();
+		writer.write(&records[4]).unwrap();
+		writer.write(&records[5]).unwrap();
+		reset_offsets.extend(writer.reset_offsets());
+		assert_eq!(records[..6], read_stream(&stream_bytes));
+
+		// reading can start at every reset point, but not at other records
+		assert_eq!(5, reset_offsets.len());
+		for (first_record, offset) in reset_offsets.iter().enumerate() {
+			let reader = StreamReader::seek(
+				std::io::Cursor::new(&stream_bytes),
+				Decoder::new(None, None),
+				*offset,
+			)
+			.unwrap();
+			assert_eq!(
+				records[first_record..6],
+				reader.map(Result::unwrap).collect::<Vec<_>>()
+			);
+		}
+		let mut reader = StreamReader::seek(
+			std::io::Cursor::new(&stream_bytes),
+			Decoder::new(None, None),
+			reset_offsets[4],
+		)
+		.unwrap();
+		let record_offset = reset_offsets[4] + 5 + reader.next_record().unwrap().unwrap().len();
+		let mut reader = StreamReader::seek(
+			std::io::Cursor::new(&stream_bytes),
+			Decoder::new(None, None),
+			record_offset,
+		)
+		.unwrap();
+		let err = reader.next().unwrap().unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::NotAResetPoint { offset } if offset == record_offset),
+			"{:?}",
+			err
+		);
+		assert!(reader.next().is_none());
+
+		// appending checks the kind of stream
+		let err = StreamWriter::append(
+			std::io::Cursor::new(&mut stream_bytes),
+			Encoder::new(None, None, None).unwrap(),
+		)
+		.err()
+		.unwrap();
+		assert!(matches!(err, FracJsonError::InvalidOptions(_)), "{:?}", err);
+		let err = StreamWriter::append_session(
+			std::io::Cursor::new(plain.clone()),
+			Encoder::new(None, None, None).unwrap(),
+			10,
+		)
+		.err()
+		.unwrap();
+		assert!(matches!(err, FracJsonError::InvalidOptions(_)), "{:?}", err);
+		let err = StreamWriter::append(
+			std::io::Cursor::new(b"FJ\x00".to_vec()),
+			Encoder::new(None, None, None).unwrap(),
+		)
+		.err()
+		.unwrap();
+		assert!(
+			matches!(err, FracJsonError::InvalidMagic { .. }),
+			"{:?}",
+			err
+		);
+
+		let encoder = Encoder::new(None, None, None).unwrap();
+		assert!(matches!(
+			StreamWriter::new_session(Vec::new(), encoder, 0).err(),
+			Some(FracJsonError::InvalidOptions(_))
+		));
+	}
+
+	#[test]
+	fn test_stream_errors() {
+		let err = StreamReader::new(&b"FJ\x00"[..], Decoder::new(None, None))
+			.err()
+			.unwrap();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::InvalidMagic {
+					offset: 0,
+					magic: [b'F', b'J']
+				}
+			),
+			"{:?}",
+			err
+		);
+		let err = StreamReader::new(&b"FS\x01"[..], Decoder::new(None, None))
+			.err()
+			.unwrap();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::UnsupportedVersion {
+					offset: 2,
+					version: 1
+				}
+			),
+			"{:?}",
+			err
+		);
+		let err = StreamReader::new(&b"FS"[..], Decoder::new(None, None))
+			.err()
+			.unwrap();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::UnexpectedEof {
+					offset: 0,
+					needed: 3,
+					available: 2
+				}
+			),
+			"{:?}",
+			err
+		);
+
+		// an invalid record doesn't end the stream, a truncated one does
+		let mut writer =
+			StreamWriter::new(Vec::new(), Encoder::new(None, None, None).unwrap()).unwrap();
+		writer.write_encoded(b"FJ\x00\xFE").unwrap();
+		writer.write(&Value::Null).unwrap();
+		let mut stream_bytes = writer.into_inner().unwrap();
+		stream_bytes.extend(b"\x04\x00\x00\x00FJ");
+		let mut reader = StreamReader::new(&stream_bytes[..], Decoder::new(None, None)).unwrap();
+		assert!(matches!(
+			reader.next(),
+			Some(Err(FracJsonError::ReservedType { .. }))
+		));
+		assert!(matches!(reader.next(), Some(Ok(Value::Null))));
+		let err = reader.next().unwrap().unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::UnexpectedEof {
+					offset: 23,
+					needed: 4,
+					available: 2
+				}
+			),
+			"{:?}",
+			err
+		);
+		assert!(reader.next().is_none());
+
+		let limits = DecodeLimits {
+			max_decompressed_size: 3,
+			..Default::default()
+		};
+		let mut reader = StreamReader::new(
+			&stream_bytes[..],
+			Decoder::new(None, None).with_limits(limits),
+		)
+		.unwrap();
+		let err = reader.next().unwrap().unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::LimitExceeded {
+					offset: 7,
+					limit: Limit::DecompressedSize,
+					value: 4,
+					max: 3
+				}
+			),
+			"{:?}",
+			err
+		);
+	}
+
+	#[test]
+	fn test_archive() {
+		let records: Vec<Value> = (0..250)
+			.map(|i| serde_json::json!({"id": i, "body": "x".repeat(i % 13), "children": (0..i % 4).collect::<Vec<_>>()}))
+			.collect();
+		let encoder = || Encoder::new(None, None, None).unwrap();
+		let writers = [
+			ArchiveWriter::new(Vec::new(), encoder()).unwrap(),
+			ArchiveWriter::new(Vec::new(), Encoder::new(None, Some(3), None).unwrap()).unwrap(),
+			ArchiveWriter::with_blocks(Vec::new(), encoder(), 1, 3).unwrap(),
+			ArchiveWriter::with_blocks(Vec::new(), encoder(), 16, 3).unwrap(),
+			ArchiveWriter::with_blocks(Vec::new(), encoder(), 1000, 3).unwrap(),
+		];
+		for mut writer in writers {
+			for (i, record) in records.iter().enumerate() {
+				match i % 3 {
+					0 => writer.write(record).unwrap(),
+					1 => writer.serialize(record).unwrap(),
+					_ => writer
+						.write_encoded(&encode(record, None, None, None).unwrap())
+						.unwrap(),
+				}
+			}
+			assert_eq!(records.len(), writer.len());
+			let archive_bytes = writer.close().unwrap();
+			assert_eq!(b"FA", &archive_bytes[..2]);
+			assert_eq!(b"FA", &archive_bytes[archive_bytes.len() - 2..]);
+
+			let mut reader = ArchiveReader::new(
+				std::io::Cursor::new(&archive_bytes),
+				Decoder::new(None, None),
+			)
+			.unwrap();
+			assert_eq!(records.len(), reader.len());
+			for n in [137, 0, 249, 16, 15, 137] {
+				assert_eq!(Some(records[n].clone()), reader.get(n).unwrap());
+			}
+			assert!(reader.get(250).unwrap().is_none());
+			let record = reader.get_record(42).unwrap().unwrap().to_vec();
+			assert_eq!(records[42], reader.decoder().decode(&record).unwrap());
+			let deserialized: BTreeMap<String, Value> =
+				reader.get_deserialized(7).unwrap().unwrap();
+			assert_eq!(records[7]["body"], deserialized["body"]);
+			let range: Vec<Value> = reader.range(100..120).map(Result::unwrap).collect();
+			assert_eq!(records[100..120], range);
+			assert_eq!(3, reader.range(247..300).count());
+			assert_eq!(
+				records,
+				reader.iter().map(Result::unwrap).collect::<Vec<_>>()
+			);
+		}
+
+		let archive_bytes = ArchiveWriter::new(Vec::new(), Encoder::new(None, None, None).unwrap())
+			.unwrap()
+			.close()
+			.unwrap();
+		let mut reader = ArchiveReader::new(
+			std::io::Cursor::new(&archive_bytes),
+			Decoder::new(None, None),
+		)
+		.unwrap();
+		assert!(reader.is_empty());
+		assert!(reader.iter().next().is_none());
+	}
+
+	#[test]
+	fn test_archive_errors() {
+		let open = |archive_bytes: &[u8]| {
+			ArchiveReader::new(
+				std::io::Cursor::new(archive_bytes.to_vec()),
+				Decoder::new(None, None),
+			)
+			.err()
+			.unwrap()
+		};
+		let mut writer =
+			ArchiveWriter::new(Vec::new(), Encoder::new(None, None, None).unwrap()).unwrap();
+		writer.write(&serde_json::json!({"a": 1})).unwrap();
+		writer.write(&serde_json::json!([1, 2])).unwrap();
+		let archive_bytes = writer.close().unwrap();
+		assert!(matches!(
+			open(&archive_bytes[..archive_bytes.len() - 1]),
+			FracJsonError::InvalidMagic { .. }
+		));
+		assert!(matches!(
+			open(b"FA\x00FA"),
+			FracJsonError::UnexpectedEof { offset: 3, .. }
+		));
+		assert!(matches!(
+			open(b"FS\x00"),
+			FracJsonError::InvalidMagic { offset: 0, .. }
+		));
+		assert!(matches!(
+			open(b"FA\x01"),
+			FracJsonError::UnsupportedVersion {
+				offset: 2,
+				version: 1
+			}
+		));
+		// second record offset points past the index
+		let mut corrupted = archive_bytes.clone();
+		let index_offset = u64::from_le_bytes(
+			corrupted[corrupted.len() - 10..corrupted.len() - 2]
+				.try_into()
+				.unwrap(),
+		) as usize;
+		corrupted[index_offset + 12] = 0xFF;
+		assert!(
+			matches!(open(&corrupted), FracJsonError::InvalidArchiveIndex { offset, .. } if offset == index_offset + 12)
+		);
+		let err =
+			ArchiveWriter::with_blocks(Vec::new(), Encoder::new(None, None, None).unwrap(), 0, 3)
+				.err()
+				.unwrap();
+		assert!(matches!(err, FracJsonError::InvalidOptions(_)));
+		let err = ArchiveWriter::with_blocks(
+			Vec::new(),
+			Encoder::new(None, Some(3), None).unwrap(),
+			16,
+			3,
+		)
+		.err()
+		.unwrap();
+		assert!(matches!(err, FracJsonError::InvalidOptions(_)));
+	}
+
+	#[test]
+	fn test_keys_table_fingerprint() {
+		let value = serde_json::json!({"title": "a", "children": [{"title": "b"}]});
+		let keys_table =
+			global_table_from_keys(vec!["title".to_string(), "children".to_string()]).unwrap();
+		let other_table =
+			global_table_from_keys(vec!["children".to_string(), "title".to_string()]).unwrap();
+		let fingerprint = GlobalKeysTable::from_bytes(&keys_table)
+			.unwrap()
+			.fingerprint();
+		let other_fingerprint = GlobalKeysTable::from_bytes(&other_table)
+			.unwrap()
+			.fingerprint();
+		assert_ne!(fingerprint, other_fingerprint);
+		assert_eq!(0x811C9DC5, GlobalKeysTable::new(Vec::new()).fingerprint());
+
+		for compression_level in [None, Some(3)] {
+			let options = EncodeOptions {
+				global_keys_table_bytes: Some(&keys_table),
+				compression_level,
+				keys_table_fingerprint: true,
+				..Default::default()
+			};
+			let frac_json_bytes = Encoder::from_options(&options)
+				.unwrap()
+				.encode(&value)
+				.unwrap();
+			// version 1, so that decoders without fingerprint support reject the file
+			assert_eq!(1, frac_json_bytes[2] & 0b00001111);
+			assert_eq!(fingerprint.to_le_bytes(), frac_json_bytes[3..7]);
+			assert_eq!(
+				value,
+				decode(&frac_json_bytes, Some(&keys_table), None).unwrap()
+			);
+			let decode_options = DecodeOptions {
+				global_keys_table_bytes: Some(&keys_table),
+				..Default::default()
+			};
+			assert_eq!(
+				value,
+				decode_from_reader(&frac_json_bytes[..], &decode_options).unwrap()
+			);
+
+			let err = decode(&frac_json_bytes, Some(&other_table), None).unwrap_err();
+			assert!(
+				matches!(err, FracJsonError::KeysTableMismatch { offset: 3, expected, actual: Some(actual) }
+				if expected == fingerprint && actual == other_fingerprint),
+				"{:?}",
+				err
+			);
+			let err =
+				decode_from_reader(&frac_json_bytes[..], &DecodeOptions::default()).unwrap_err();
+			assert!(
+				matches!(err, FracJsonError::KeysTableMismatch { actual: None, .. }),
+				"{:?}",
+				err
+			);
+		}
+
+		// without the option or without a keys table, no fingerprint is stored
+		let frac_json_bytes = encode(&value, Some(&keys_table), None, None).unwrap();
+		assert_eq!(b"FJ\x00", &frac_json_bytes[..3]);
+		let encoder = Encoder::new(None, None, None)
+			.unwrap()
+			.with_keys_table_fingerprint(true);
+		let frac_json_bytes = encoder.encode(&value).unwrap();
+		assert_eq!(b"FJ\x00", &frac_json_bytes[..3]);
+		assert_eq!(
+			value,
+			decode(&frac_json_bytes, Some(&other_table), None).unwrap()
+		);
+	}
+
+	#[test]
+	fn test_keys_table_set() {
+		let value =
+			serde_json::json!({"id": 1, "title": "a", "service_field": {"id": 2, "local": true}});
+		let base = global_table_from_keys(vec!["id".to_string(), "title".to_string()]).unwrap();
+		let overlay =
+			global_table_from_keys(vec!["service_field".to_string(), "id".to_string()]).unwrap();
+		let other = global_table_from_keys(vec!["other".to_string()]).unwrap();
+		let layers: [&[u8]; 2] = [&base, &overlay];
+		let set = GlobalKeysTableSet::from_bytes(&layers).unwrap();
+		assert_eq!(2, set.layers().len());
+		assert_eq!(
+			GlobalKeysTable::new(
+				vec!["id", "title", "service_field", "id"]
+					.into_iter()
+					.map(String::from)
+					.collect()
+			),
+			set.to_table()
+		);
+		let fingerprints = set.fingerprints();
+
+		let encoder = Encoder::new(None, None, None)
+			.unwrap()
+			.with_keys_table_set(set.clone());
+		let frac_json_bytes = encoder.encode(&value).unwrap();
+		assert_eq!(b"FJ\x81\x02", &frac_json_bytes[..4]);
+		assert_eq!(fingerprints[0].to_le_bytes(), frac_json_bytes[4..8]);
+		assert_eq!(fingerprints[1].to_le_bytes(), frac_json_bytes[8..12]);
+		// only "local" is stored in the file, keys of both layers are referenced by index
+		let contains = |needle: &[u8]| {
+			frac_json_bytes
+				.windows(needle.len())
+				.any(|window| window == needle)
+		};
+		assert!(contains(b"local") && !contains(b"title") && !contains(b"service_field"));
+		let decoder = Decoder::new(None, None).with_keys_table_set(set.clone());
+		assert_eq!(value, decoder.decode(&frac_json_bytes).unwrap());
+
+		let options = EncodeOptions {
+			global_keys_table_layers: Some(&layers),
+			compression_level: Some(3),
+			..Default::default()
+		};
+		let compressed_bytes = Encoder::from_options(&options)
+			.unwrap()
+			.encode(&value)
+			.unwrap();
+		let decode_options = DecodeOptions {
+			global_keys_table_layers: Some(&layers),
+			..Default::default()
+		};
+		assert_eq!(
+			value,
+			decode_with_options(&compressed_bytes, &decode_options).unwrap()
+		);
+		assert_eq!(
+			value,
+			decode_from_reader(&compressed_bytes[..], &decode_options).unwrap()
+		);
+		let both_options = DecodeOptions {
+			global_keys_table_bytes: Some(&base),
+			global_keys_table_layers: Some(&layers),
+			..Default::default()
+		};
+		assert!(matches!(
+			Decoder::from_options(&both_options),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+
+		// missing, reordered and extra layers
+		let err = decode(&frac_json_bytes, Some(&base), None).unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::KeysTableLayerMismatch { offset: 8, layer: 1, expected: Some(expected), actual: None }
+			if expected == fingerprints[1]),
+			"{:?}",
+			err
+		);
+		let err = decode(&frac_json_bytes, None, None).unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::KeysTableLayerMismatch {
+					offset: 4,
+					layer: 0,
+					actual: None,
+					..
+				}
+			),
+			"{:?}",
+			err
+		);
+		let reordered: [&[u8]; 2] = [&overlay, &base];
+		let decoder = Decoder::new(None, None)
+			.with_keys_table_set(GlobalKeysTableSet::from_bytes(&reordered).unwrap());
+		let err = decoder.decode(&frac_json_bytes).unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::KeysTableLayerMismatch {
+					offset: 4,
+					layer: 0,
+					expected: Some(_),
+					actual: Some(_)
+				}
+			),
+			"{:?}",
+			err
+		);
+		let extra: [&[u8]; 3] = [&base, &overlay, &other];
+		let decoder = Decoder::new(None, None)
+			.with_keys_table_set(GlobalKeysTableSet::from_bytes(&extra).unwrap());
+		let err = decoder.decode(&frac_json_bytes).unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::KeysTableLayerMismatch {
+					offset: 3,
+					layer: 2,
+					expected: None,
+					actual: Some(_)
+				}
+			),
+			"{:?}",
+			err
+		);
+
+		// a single layer set is interchangeable with a plain table
+		let single = GlobalKeysTableSet::from_bytes(&[&base]).unwrap();
+		let frac_json_bytes = Encoder::new(None, None, None)
+			.unwrap()
+			.with_keys_table_set(single)
+			.encode(&value)
+			.unwrap();
+		assert_eq!(value, decode(&frac_json_bytes, Some(&base), None).unwrap());
+		assert!(matches!(
+			decode(&frac_json_bytes, Some(&other), None),
+			Err(FracJsonError::KeysTableLayerMismatch { layer: 0, .. })
+		));
+
+		assert!(matches!(
+			GlobalKeysTableSet::new(Vec::new()),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+		let big_layer = GlobalKeysTable::new((0..0x8000).map(|i| i.to_string()).collect());
+		assert!(matches!(
+			GlobalKeysTableSet::new(vec![big_layer.clone(), big_layer]),
+			Err(FracJsonError::LimitExceeded {
+				limit: Limit::KeysTableSize,
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn test_keys_table_extension() {
+		let v1_value = serde_json::json!({"id": 1, "title": "a"});
+		let v1 = GlobalKeysTable::new(vec!["id".to_string(), "title".to_string()]).with_metadata(
+			KeysTableMetadata {
+				name: "docs".to_string(),
+				version: 1,
+				created_at: 0,
+			},
+		);
+		let v2 = v1
+			.extended(vec![
+				"tags".to_string(),
+				"id".to_string(),
+				"tags".to_string(),
+			])
+			.unwrap();
+		assert_eq!(
+			GlobalKeysTable::new(
+				vec!["id", "title", "tags"]
+					.into_iter()
+					.map(String::from)
+					.collect()
+			),
+			v2
+		);
+		assert_eq!("docs", v2.metadata().unwrap().name);
+		assert_eq!(2, v2.metadata().unwrap().version);
+		assert!(is_compatible_extension(&v1, &v2));
+		assert!(is_compatible_extension(&v1, &v1));
+		assert!(!is_compatible_extension(&v2, &v1));
+		let regenerated = GlobalKeysTable::new(vec![
+			"title".to_string(),
+			"id".to_string(),
+			"tags".to_string(),
+		]);
+		assert!(!is_compatible_extension(&v1, &regenerated));
+
+		// files encoded with v1 decode with v2, but not the other way around
+		let v1_bytes = v1.to_bytes().unwrap();
+		let v2_bytes = v2.to_bytes().unwrap();
+		let options = EncodeOptions {
+			global_keys_table_bytes: Some(&v1_bytes),
+			keys_table_fingerprint: true,
+			..Default::default()
+		};
+		let frac_json_bytes = Encoder::from_options(&options)
+			.unwrap()
+			.encode(&v1_value)
+			.unwrap();
+		assert_eq!(
+			v1_value,
+			decode(&frac_json_bytes, Some(&v2_bytes), None).unwrap()
+		);
+		let err = decode(
+			&frac_json_bytes,
+			Some(&regenerated.to_bytes().unwrap()),
+			None,
+		)
+		.unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::KeysTableMismatch { .. }),
+			"{:?}",
+			err
+		);
+		let v2_value = serde_json::json!({"id": 1, "tags": []});
+		let options = EncodeOptions {
+			global_keys_table_bytes: Some(&v2_bytes),
+			keys_table_fingerprint: true,
+			..Default::default()
+		};
+		let frac_json_bytes = Encoder::from_options(&options)
+			.unwrap()
+			.encode(&v2_value)
+			.unwrap();
+		let err = decode(&frac_json_bytes, Some(&v1_bytes), None).unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::KeysTableMismatch { .. }),
+			"{:?}",
+			err
+		);
+
+		// only the top layer of a set can be extended
+		let base = GlobalKeysTable::new(vec!["base".to_string()]);
+		let encoder = Encoder::new(None, None, None)
+			.unwrap()
+			.with_keys_table_set(GlobalKeysTableSet::new(vec![base.clone(), v1.clone()]).unwrap());
+		let frac_json_bytes = encoder.encode(&v1_value).unwrap();
+		let decoder = Decoder::new(None, None)
+			.with_keys_table_set(GlobalKeysTableSet::new(vec![base.clone(), v2.clone()]).unwrap());
+		assert_eq!(v1_value, decoder.decode(&frac_json_bytes).unwrap());
+		let extended_base = base.extended(vec!["more".to_string()]).unwrap();
+		let decoder = Decoder::new(None, None)
+			.with_keys_table_set(GlobalKeysTableSet::new(vec![extended_base, v1.clone()]).unwrap());
+		let err = decoder.decode(&frac_json_bytes).unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::KeysTableLayerMismatch { layer: 0, .. }),
+			"{:?}",
+			err
+		);
+
+		// new keys from samples are appended after the existing ones
+		let mut builder = KeysTableBuilder::new();
+		builder.add_samples(
+			(0..10).map(|i| serde_json::json!({"title": "x", "tags": [i], "category": "y"})),
+		);
+		let v3 = builder.build_extension(&v2, None, None).unwrap();
+		assert_eq!(
+			GlobalKeysTable::new(
+				vec!["id", "title", "tags", "category"]
+					.into_iter()
+					.map(String::from)
+					.collect()
+			),
+			v3
+		);
+		assert_eq!(3, v3.metadata().unwrap().version);
+		assert!(is_compatible_extension(&v2, &v3));
+		assert_eq!(v2, builder.build_extension(&v2, None, Some(11)).unwrap());
+		let full = GlobalKeysTable::new((0..0xFFFF).map(|i| i.to_string()).collect());
+		assert_eq!(full, builder.build_extension(&full, None, None).unwrap());
+		assert!(matches!(
+			full.extended(vec!["new".to_string()]),
+			Err(FracJsonError::LimitExceeded {
+				limit: Limit::KeysTableSize,
+				..
+			})
+		));
+		let last_version = v1.with_metadata(KeysTableMetadata {
+			name: "docs".to_string(),
+			version: u32::MAX,
+			created_at: 0,
+		});
+		assert!(matches!(
+			last_version.extended(vec!["new".to_string()]),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+	}
+
+	#[test]
+	fn test_keys_table_inspection() {
+		let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+		let a = GlobalKeysTable::from_bytes(
+			&global_table_from_keys(keys(&["id", "title", "id"])).unwrap(),
+		)
+		.unwrap();
+		assert_eq!(3, a.len());
+		assert!(!a.is_empty());
+		assert_eq!(keys(&["id", "title", "id"]), a.keys());
+		assert_eq!(Some(0), a.index_of("id"));
+		assert_eq!(Some(1), a.index_of("title"));
+		assert_eq!(None, a.index_of("tags"));
+		assert_eq!(Some("title"), a.get(1));
+		assert_eq!(None, a.get(3));
+
+		let b = GlobalKeysTable::new(keys(&["tags", "title", "name"]));
+		let merged = merge_global_tables(&a, &b).unwrap();
+		assert_eq!(keys(&["id", "title", "id", "tags", "name"]), merged.keys());
+		assert!(is_compatible_extension(&a, &merged));
+		assert_eq!(
+			None,
+			merge_global_tables(&a.clone().with_metadata(KeysTableMetadata::new("a", 1)), &b)
+				.unwrap()
+				.metadata()
+		);
+
+		let diff = diff_global_tables(&a, &b);
+		assert_eq!(keys(&["tags", "name"]), diff.added);
+		assert_eq!(keys(&["id"]), diff.removed);
+		assert!(diff.moved.is_empty());
+		let diff = diff_global_tables(&b, &a);
+		assert_eq!(keys(&["id"]), diff.added);
+		assert_eq!(keys(&["tags", "name"]), diff.removed);
+		assert!(diff.moved.is_empty());
+		let reordered = GlobalKeysTable::new(keys(&["title", "id"]));
+		let diff = diff_global_tables(&a, &reordered);
+		assert_eq!(
+			vec![
+				MovedKey {
+					key: "id".to_string(),
+					old_index: 0,
+					new_index: 1
+				},
+				MovedKey {
+					key: "title".to_string(),
+					old_index: 1,
+					new_index: 0
+				},
+			],
+			diff.moved
+		);
+		assert!(diff.added.is_empty() && diff.removed.is_empty());
+		assert_eq!(KeysTableDiff::default(), diff_global_tables(&a, &a));
+		assert!(diff_global_tables(&a, &a).is_empty());
+	}
+
+	#[test]
+	fn test_keys_table_metadata() {
+		let value = serde_json::json!({"title": "a", "children": [{"title": "b"}]});
+		let keys = vec!["title".to_string(), "children".to_string()];
+		let metadata = KeysTableMetadata {
+			name: "docs".to_string(),
+			version: 3,
+			created_at: 1700000000,
+		};
+		let table = GlobalKeysTable::new(keys.clone()).with_metadata(metadata.clone());
+		let table_bytes = table.to_bytes().unwrap();
+		assert_eq!(b"FK\x01", &table_bytes[..3]);
+		let read_table = GlobalKeysTable::from_bytes(&table_bytes).unwrap();
+		assert_eq!(Some(&metadata), read_table.metadata());
+		assert_eq!(table, read_table);
+		// metadata doesn't affect the fingerprint, so files stay decodable with either layout
+		let legacy_bytes = global_table_from_keys(keys).unwrap();
+		let legacy_table = GlobalKeysTable::from_bytes(&legacy_bytes).unwrap();
+		assert_eq!(None, legacy_table.metadata());
+		assert_eq!(legacy_table.fingerprint(), read_table.fingerprint());
+		let frac_json_bytes = encode(&value, Some(&table_bytes), None, None).unwrap();
+		assert_eq!(
+			frac_json_bytes,
+			encode(&value, Some(&legacy_bytes), None, None).unwrap()
+		);
+		assert_eq!(
+			value,
+			decode(&frac_json_bytes, Some(&table_bytes), None).unwrap()
+		);
+		assert!(KeysTableMetadata::new("docs", 1).created_at > 1700000000);
+
+		let checksum_offset = table_bytes.len() - 4;
+		let mut corrupted = table_bytes.clone();
+		corrupted[10] ^= 1;
+		let err = GlobalKeysTable::from_bytes(&corrupted).unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::ChecksumMismatch { offset, .. } if offset == checksum_offset),
+			"{:?}",
+			err
+		);
+		let err = GlobalKeysTable::from_bytes(&table_bytes[..checksum_offset]).unwrap_err();
+		assert!(
+			matches!(err, FracJsonError::UnexpectedEof { .. }),
+			"{:?}",
+			err
+		);
+		let mut corrupted = table_bytes.clone();
+		corrupted[2] = 9;
+		let err = GlobalKeysTable::from_bytes(&corrupted).unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::UnsupportedVersion {
+					offset: 2,
+					version: 9
+				}
+			),
+			"{:?}",
+			err
+		);
+		let err = GlobalKeysTable::from_bytes(b"FX\x01").unwrap_err();
+		assert!(
+			matches!(
+				err,
+				FracJsonError::InvalidMagic {
+					offset: 0,
+					magic: [b'F', b'X']
+				}
+			),
+			"{:?}",
+			err
+		);
+		let long_name = table.with_metadata(KeysTableMetadata::new(&"n".repeat(0x10000), 1));
+		assert!(matches!(
+			long_name.to_bytes(),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+	}
+
+	#[test]
+	fn test_keys_table_builder() {
+		let samples: Vec<Value> = (0..20)
+			.map(|i| {
+				serde_json::json!({
+					"id": i,
+					"tags": [{"name": "a"}, {"name": "b"}],
+					"meta": if i % 4 == 0 { serde_json::json!({"rare": true}) } else { Value::Null },
+				})
+			})
+			.collect();
+		let mut builder = KeysTableBuilder::new();
+		builder.add_samples(&samples);
+		let table = builder.build(None, None).unwrap();
+		assert_eq!(
+			GlobalKeysTable::new(
+				vec!["name", "meta", "tags", "id", "rare"]
+					.into_iter()
+					.map(String::from)
+					.collect()
+			),
+			table
+		);
+		assert_eq!(
+			table.to_bytes().unwrap(),
+			global_table_from_json(&Value::Array(samples.clone())).unwrap()
+		);
+
+		// shards merged together count the same as one builder over all samples
+		let mut shards: Vec<KeysTableBuilder> = samples
+			.chunks(7)
+			.map(|chunk| {
+				let mut shard = KeysTableBuilder::new();
+				shard.add_samples(chunk.iter().cloned());
+				shard
+			})
+			.collect();
+		let mut merged = shards.pop().unwrap();
+		for shard in shards {
+			merged.merge(shard);
+		}
+		assert_eq!(5, merged.key_count());
+		assert_eq!(table, merged.build(None, None).unwrap());
+		assert_eq!(
+			GlobalKeysTable::from_bytes(
+				&global_table_from_json_limited(&Value::Array(samples), Some(2), None).unwrap()
+			)
+			.unwrap(),
+			merged.build(Some(2), None).unwrap()
+		);
+		assert_eq!(
+			4,
+			merged.build(None, Some(20)).unwrap().to_bytes().unwrap()[1]
+		);
+		assert!(matches!(
+			merged.build(Some(0x10000), None),
+			Err(FracJsonError::InvalidOptions(_))
+		));
+	}
+
+	#[test]
+	fn test_keys_table_builder_savings() {
+		// "a" is stored as a 2 byte immediate key in each sample anyway, the long key saves the most
+		let long_key = "a_rather_long_key_name".to_string();
+		let samples: Vec<Value> = (0..10)
+			.map(|i| {
+				let mut object = serde_json::Map::new();
+				object.insert("a".to_string(), Value::from(i));
+				if i < 2 {
+					object.insert(long_key.clone(), Value::Null);
+				}
+				Value::Object(object)
+			})
+			.collect();
+		let mut builder = KeysTableBuilder::new();
+		builder.add_samples(&samples);
+		assert_eq!(
+			GlobalKeysTable::new(vec!["a".to_string(), long_key.clone()]),
+			builder.build(None, None).unwrap()
+		);
+		assert_eq!(
+			GlobalKeysTable::new(vec![long_key.clone()]),
+			builder.build(Some(1), None).unwrap()
+		);
+
+		// repeats within one sample are one byte back references, only the first occurrence saves bytes
+		let mut builder = KeysTableBuilder::new();
+		builder.add_sample(&serde_json::json!([{"x": 1}, {"x": 2}, {"x": 3}, {"long_key": 4}]));
+		assert_eq!(
+			GlobalKeysTable::new(vec!["x".to_string(), "long_key".to_string()]),
+			builder.build(None, None).unwrap()
+		);
+		assert_eq!(
+			GlobalKeysTable::new(vec!["long_key".to_string()]),
+			builder.build(Some(1), None).unwrap()
+		);
+
+		// the most frequent keys get the one byte indices
+		let keys: Vec<String> = (0..100).map(|i| format!("key_{:03}", i)).collect();
+		let samples: Vec<Value> = (0..10)
+			.map(|i| {
+				let object: serde_json::Map<String, Value> = keys
+					.iter()
+					.enumerate()
+					.filter(|(k, _)| k % 10 >= i)
+					.map(|(_, key)| (key.clone(), Value::Null))
+					.collect();
+				Value::Object(object)
+			})
+			.collect();
+		let mut builder = KeysTableBuilder::new();
+		builder.add_samples(&samples);
+		let table = builder.build(None, None).unwrap();
+		let occurrences = |index: usize| {
+			table.lookup_index(index, 0).unwrap()[4..]
+				.parse::<usize>()
+				.unwrap() % 10
+				+ 1
+		};
+		assert!((0..99).all(|index| occurrences(index) >= occurrences(index + 1)));
+		assert_eq!(10, occurrences(0));
+		assert_eq!(1, occurrences(99));
+	}
+
+	#[derive(Serialize, Deserialize, PartialEq, Debug)]
+	enum TestEnum {
+		Unit,
+		Newtype(i32),
+		Tuple(u8, String),
+		Struct { a: bool, b: Option<f32> },
+	}
+
+	#[derive(Serialize)]
+	struct TestInner {
+		id: u64,
+		name: &'static str,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		parent: Option<u32>,
+	}
+
+	#[derive(Serialize)]
+	struct TestStruct {
+		int8: i8,
+		int64: i64,
+		big: u64,
+		float: f32,
+		double: f64,
+		nan: f64,
+		character: char,
+		text: String,
+		long_text: String,
+		bytes: serde_bytes_like::Bytes,
+		unit: (),
+		tuple: (i16, bool, Option<()>),
+		enums: Vec<TestEnum>,
+		inners: Vec<TestInner>,
+		map: BTreeMap<i32, &'static str>,
+		#[serde(flatten)]
+		extra: HashMap<String, u16>,
+	}
+
+	mod serde_bytes_like {
+		pub struct Bytes(pub Vec<u8>);
+
+		impl serde::Serialize for Bytes {
+			fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+				serializer.serialize_bytes(&self.0)
+			}
+		}
+	}
+
+	fn make_test_struct() -> TestStruct {
+		TestStruct {
+			int8: -100,
+			int64: -0x1_0000_0000,
+			big: u64::MAX,
+			float: 1.1,
+			double: 0.1,
+			nan: f64::NAN,
+			character: 'ß',
+			text: "text".to_string(),
+			long_text: "long text ".repeat(100),
+			bytes: serde_bytes_like::Bytes(vec![0, 200, 255]),
+			unit: (),
+			tuple: (-1000, true, None),
+			enums: vec![
+				TestEnum::Unit,
+				TestEnum::Newtype(5),
+				TestEnum::Tuple(1, "a".to_string()),
+				TestEnum::Struct {
+					a: true,
+					b: Some(2.5),
+				},
+				TestEnum::Struct { a: false, b: None },
+			],
+			inners: (0..10)
+				.map(|i| TestInner {
+					id: i,
+					name: "inner",
+					parent: if i % 2 == 0 { Some(i as u32) } else { None },
+				})
+				.collect(),
+			map: BTreeMap::from([(1, "one"), (-2, "minus two")]),
+			extra: HashMap::from([("extra".to_string(), 300)]),
+		}
+	}
+
+	#[test]
+	fn test_serialize() {
+		let test_struct = make_test_struct();
+		let value = serde_json::to_value(&test_struct).unwrap();
+		let frac_json_bytes = to_vec(&test_struct).unwrap();
+		assert_eq!(encode(&value, None, None, None).unwrap(), frac_json_bytes);
+		assert_eq!(value, decode(&frac_json_bytes, None, None).unwrap());
+
+		let mut written_bytes = Vec::new();
+		to_writer(&mut written_bytes, &test_struct).unwrap();
+		assert_eq!(frac_json_bytes, written_bytes);
+	}
+
+	#[test]
+	fn test_serialize_with_encoder() {
+		let test_struct = make_test_struct();
+		let value = serde_json::to_value(&test_struct).unwrap();
+		let global_table = GlobalKeysTable::new(vec!["id".to_string(), "Struct".to_string()]);
+		let global_table_bytes = global_table.to_bytes().unwrap();
+		let encoder = Encoder::new(Some(global_table), Some(3), None).unwrap();
+		let frac_json_bytes = encoder.to_vec(&test_struct).unwrap();
+		assert_eq!(
+			value,
+			decode(&frac_json_bytes, Some(&global_table_bytes), None).unwrap()
+		);
+		let mut written_bytes = Vec::new();
+		encoder.to_writer(&test_struct, &mut written_bytes).unwrap();
+		assert_eq!(
+			value,
+			decode(&written_bytes, Some(&global_table_bytes), None).unwrap()
+		);
+	}
+
+	#[test]
+	fn test_serialize_errors() {
+		let map = HashMap::from([(vec![1], 1)]);
+		assert!(matches!(to_vec(&map), Err(FracJsonError::Serde(_))));
+		assert!(matches!(to_vec(&u128::MAX), Err(FracJsonError::Serde(_))));
+	}
+
+	#[derive(Serialize, Deserialize, PartialEq, Debug)]
+	struct TestRecord<'a> {
+		id: u64,
+		name: &'a str,
+		#[serde(borrow)]
+		label: Cow<'a, str>,
+		tags: Vec<&'a str>,
+		small: i8,
+		score: Option<f64>,
+		missing: Option<bool>,
+		enums: Vec<TestEnum>,
+		map: BTreeMap<i32, String>,
+		children: Vec<TestRecordChild>,
+	}
+
+	#[derive(Serialize, Deserialize, PartialEq, Debug)]
+	struct TestRecordChild {
+		name: String,
+		value: u16,
+	}
+
+	fn make_test_record(name: &str) -> TestRecord<'_> {
+		TestRecord {
+			id: 0x1_0000_0000,
+			name,
+			label: Cow::Borrowed("label"),
+			tags: vec!["a", "b"],
+			small: -1,
+			score: Some(0.1),
+			missing: None,
+			enums: vec![
+				TestEnum::Unit,
+				TestEnum::Newtype(-5),
+				TestEnum::Tuple(1, "a".to_string()),
+				TestEnum::Struct {
+					a: true,
+					b: Some(2.5),
+				},
+			],
+			map: BTreeMap::from([(1, "one".to_string()), (-2, "minus two".to_string())]),
+			children: (0..5)
+				.map(|i| TestRecordChild {
+					name: format!("child {}", i),
+					value: i * 100,
+				})
+				.collect(),
+		}
+	}
+
+	#[test]
+	fn test_deserialize() {
+		let record = make_test_record("record");
+		let frac_json_bytes = to_vec(&record).unwrap();
+		let deserialized: TestRecord = from_slice(&frac_json_bytes).unwrap();
+		assert_eq!(record, deserialized);
+		// strings of uncompressed files are borrowed from the input
+		assert!(frac_json_bytes
+			.as_ptr_range()
+			.contains(&deserialized.name.as_ptr()));
+		assert!(matches!(deserialized.label, Cow::Borrowed(_)));
+
+		let value: Value = from_slice(&frac_json_bytes).unwrap();
+		assert_eq!(decode(&frac_json_bytes, None, None).unwrap(), value);
+		let value: Value = from_reader(frac_json_bytes.as_slice()).unwrap();
+		assert_eq!(decode(&frac_json_bytes, None, None).unwrap(), value);
+
+		let serialized_value = serde_json::to_value(make_test_struct()).unwrap();
+		let frac_json_bytes = to_vec(&make_test_struct()).unwrap();
+		assert_eq!(
+			serialized_value,
+			from_slice::<Value>(&frac_json_bytes).unwrap()
+		);
+	}
+
+	#[test]
+	fn test_deserialize_with_decoder() {
+		let record = make_test_record("record");
+		let global_table = GlobalKeysTable::new(vec!["name".to_string(), "Struct".to_string()]);
+		let encoder = Encoder::new(Some(global_table.clone()), Some(3), None).unwrap();
+		let decoder = Decoder::new(Some(global_table), None);
+		let frac_json_bytes = encoder.to_vec(&record).unwrap();
+
+		// strings of compressed files can't be borrowed
+		let result = decoder.from_slice::<TestRecord>(&frac_json_bytes);
+		assert!(matches!(result, Err(FracJsonError::Serde(_))));
+		let value: Value = decoder.from_slice(&frac_json_bytes).unwrap();
+		assert_eq!(serde_json::to_value(&record).unwrap(), value);
+		let value: Value = decoder.from_reader(frac_json_bytes.as_slice()).unwrap();
+		assert_eq!(serde_json::to_value(&record).unwrap(), value);
+		let children: Value =
+			decoder.from_slice::<Value>(&frac_json_bytes).unwrap()["children"].take();
+		let children: Vec<TestRecordChild> = serde_json::from_value(children).unwrap();
+		assert_eq!(record.children, children);
+	}
+
+	#[test]
+	fn test_deserialize_ignored_any() {
+		#[derive(Deserialize, PartialEq, Debug)]
+		struct Partial {
+			items: Vec<TestRecordChild>,
+		}
+		// "name" and "value" are first used in the skipped subtree, and referenced later
+		let json: Value = serde_json::from_str(
+			r#"{
+			"skipped": {"name": "x", "nested": [1, 2.5, "string", {"value": null}]},
+			"items": [{"name": "a", "value": 1}, {"name": "b", "value": 2}],
+			"after": "skipped too"
+		}"#,
+		)
+		.unwrap();
+		let frac_json_bytes = encode(&json, None, None, None).unwrap();
+		let partial: Partial = from_slice(&frac_json_bytes).unwrap();
+		assert_eq!(
+			vec![
+				TestRecordChild {
+					name: "a".to_string(),
+					value: 1
+				},
+				TestRecordChild {
+					name: "b".to_string(),
+					value: 2
+				},
+			],
+			partial.items
+		);
+
+		// skipped subtrees are still checked against the limits
+		let limits = DecodeLimits {
+			max_depth: 1,
+			..DecodeLimits::default()
+		};
+		let result = Decoder::new(None, None)
+			.with_limits(limits)
+			.from_slice::<Partial>(&frac_json_bytes);
+		assert!(matches!(
+			result,
+			Err(FracJsonError::LimitExceeded {
+				limit: Limit::Depth,
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn test_deserialize_errors() {
+		let frac_json_bytes = to_vec(&serde_json::json!({"id": "not a number"})).unwrap();
+		assert!(matches!(
+			from_slice::<TestRecordChild>(&frac_json_bytes),
+			Err(FracJsonError::Serde(_))
+		));
+		let frac_json_bytes = to_vec(&serde_json::json!([300])).unwrap();
+		assert!(matches!(
+			from_slice::<Vec<u8>>(&frac_json_bytes),
+			Err(FracJsonError::Serde(_))
+		));
+		let frac_json_bytes = to_vec(&serde_json::json!({"Unit": null, "Newtype": 1})).unwrap();
+		assert!(matches!(
+			from_slice::<TestEnum>(&frac_json_bytes),
+			Err(FracJsonError::Serde(_))
+		));
+		assert!(matches!(
+			from_slice::<Value>(b"FJ\x00\xFE"),
+			Err(FracJsonError::ReservedType { offset: 3, .. })
+		));
+	}
+
+	#[test]
+	fn test_error_invalid_magic() {
+		let err = decode(b"XJ\x00\x00", None, None).unwrap_err();
+		assert!(matches!(err, FracJsonError::InvalidMagic { offset: 0, .. }));
+	}
+
+	#[test]
+	fn test_error_unexpected_eof() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/string8.json")).unwrap();
+		let mut frac_json_bytes = encode(&value, None, None, None).unwrap();
+		frac_json_bytes.truncate(50);
+		let err = decode(&frac_json_bytes, None, None).unwrap_err();
+		assert!(matches!(
+			err,
+			FracJsonError::UnexpectedEof {
+				offset: 5,
+				needed: 100,
+				available: 45
+			}
+		));
+	}
+
+	#[test]
+	fn test_error_reserved_type() {
+		let err = decode(b"FJ\x00\xFE", None, None).unwrap_err();
+		assert!(matches!(
+			err,
+			FracJsonError::ReservedType {
+				offset: 3,
+				type_byte: 0xFE
+			}
+		));
+	}
+
+	#[test]
+	fn test_error_unknown_global_key_index() {
+		let value = serde_json::from_str::<Value>(r#"{"a": 1, "b": 2}"#).unwrap();
+		let keys_table = global_table_from_keys(vec!["a".to_string(), "b".to_string()]).unwrap();
+		let frac_json_bytes = encode(&value, Some(&keys_table), None, None).unwrap();
+		let smaller_keys_table = global_table_from_keys(vec!["a".to_string()]).unwrap();
+		let err = decode(&frac_json_bytes, Some(&smaller_keys_table), None).unwrap_err();
+		assert!(matches!(
+			err,
+			FracJsonError::UnknownGlobalKeyIndex {
+				offset: 6,
+				index: 1,
+				table_size: 1
+			}
+		));
+	}
+
+	// tiny string of length 2 with an invalid continuation byte
+	const INVALID_UTF8_STRING: &[u8] = b"FJ\x00\x18\xC3\x28";
+
+	#[test]
+	fn test_invalid_utf8_strict() {
+		let err = decode(INVALID_UTF8_STRING, None, None).unwrap_err();
+		assert!(matches!(err, FracJsonError::InvalidUtf8 { offset: 4 }));
+	}
+
+	#[test]
+	fn test_invalid_utf8_lossy() {
+		let options = DecodeOptions {
+			utf8_validation: Utf8Validation::Lossy,
+			..Default::default()
+		};
+		let value = decode_with_options(INVALID_UTF8_STRING, &options).unwrap();
+		assert_eq!(Value::String("\u{FFFD}(".to_string()), value);
+	}
+
+	#[test]
+	fn test_trusted_utf8() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let options = DecodeOptions {
+			utf8_validation: unsafe { Utf8Validation::trusted() },
+			..Default::default()
+		};
+		let decoded_value = decode_with_options(&frac_json_bytes, &options).unwrap();
+		assert_eq!(value, decoded_value);
+	}
+
+	fn decode_with_limits(
+		frac_json_bytes: &[u8],
+		limits: DecodeLimits,
+	) -> Result<Value, FracJsonError> {
+		let options = DecodeOptions {
+			limits,
+			..Default::default()
+		};
+		decode_with_options(frac_json_bytes, &options)
+	}
+
+	fn assert_limit_exceeded(result: Result<Value, FracJsonError>, expected_limit: Limit) {
+		match result {
+			Err(FracJsonError::LimitExceeded { limit, .. }) => assert_eq!(expected_limit, limit),
+			other => panic!("expected {:?} limit error, got {:?}", expected_limit, other),
+		}
+	}
+
+	#[test]
+	fn test_limit_depth() {
+		let value = serde_json::from_str::<Value>("[[[[1]]]]").unwrap();
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let limits = DecodeLimits {
+			max_depth: 4,
+			..Default::default()
+		};
+		assert_eq!(value, decode_with_limits(&frac_json_bytes, limits).unwrap());
+		let limits = DecodeLimits {
+			max_depth: 3,
+			..Default::default()
+		};
+		assert_limit_exceeded(decode_with_limits(&frac_json_bytes, limits), Limit::Depth);
+	}
+
+	#[test]
+	fn test_limit_default_depth() {
+		// 100k nested tiny arrays of length 1
+		let mut frac_json_bytes = b"FJ\x00".to_vec();
+		frac_json_bytes.extend(vec![0x9F; 100_000]);
+		frac_json_bytes.push(0x00);
+		assert_limit_exceeded(decode(&frac_json_bytes, None, None), Limit::Depth);
+	}
+
+	#[test]
+	fn test_limit_container_length() {
+		let s = include_str!("./test_files/array8.json");
+		let value = serde_json::from_str::<Value>(s).unwrap();
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let limits = DecodeLimits {
+			max_container_length: 10,
+			..Default::default()
+		};
+		assert_limit_exceeded(
+			decode_with_limits(&frac_json_bytes, limits),
+			Limit::ContainerLength,
+		);
+	}
+
+	#[test]
+	fn test_limit_total_elements() {
+		let value = serde_json::from_str::<Value>("[[1, 2], [3, 4], [5, 6]]").unwrap();
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let limits = DecodeLimits {
+			max_total_elements: 9,
+			..Default::default()
+		};
+		assert_eq!(value, decode_with_limits(&frac_json_bytes, limits).unwrap());
+		let limits = DecodeLimits {
+			max_total_elements: 8,
+			..Default::default()
+		};
+		assert_limit_exceeded(
+			decode_with_limits(&frac_json_bytes, limits),
+			Limit::TotalElements,
+		);
+	}
+
+	#[test]
+	fn test_limit_string_length() {
+		let s = include_str!("./test_files/string16.json");
+		let value = serde_json::from_str::<Value>(s).unwrap();
+		let frac_json_bytes = encode(&value, None, None, None).unwrap();
+		let limits = DecodeLimits {
+			max_string_length: 100,
+			..Default::default()
+		};
+		assert_limit_exceeded(
+			decode_with_limits(&frac_json_bytes, limits),
+			Limit::StringLength,
+		);
+	}
+
+	#[test]
+	fn test_limit_decompressed_size() {
+		let s = include_str!("./test_files/string16.json");
+		let value = serde_json::from_str::<Value>(s).unwrap();
+		let frac_json_bytes = encode(&value, None, Some(3), None).unwrap();
+		let limits = DecodeLimits {
+			max_decompressed_size: 503,
+			..Default::default()
+		};
+		assert_eq!(value, decode_with_limits(&frac_json_bytes, limits).unwrap());
+		let limits = DecodeLimits {
+			max_decompressed_size: 502,
+			..Default::default()
+		};
+		assert_limit_exceeded(
+			decode_with_limits(&frac_json_bytes, limits),
+			Limit::DecompressedSize,
+		);
+	}
+
+	#[test]
+	fn test_large_compressed() {
+		// highly compressible, decompresses to well over 10 MB
+		let value = Value::Array(vec![Value::String("a".repeat(1000)); 20_000]);
+		let frac_json_bytes = encode(&value, None, Some(3), None).unwrap();
+		assert!(frac_json_bytes.len() < 1024 * 1024);
+		assert_eq!(value, decode(&frac_json_bytes, None, None).unwrap());
+	}
+
+	#[test]
+	fn test_compressed_without_content_size() {
+		let value =
+			serde_json::from_str::<Value>(include_str!("./test_files/combined.json")).unwrap();
+		let uncompressed = encode(&value, None, None, None).unwrap();
+		// streaming compression does not store the content size in the frame header
+		let mut frac_json_bytes = b"FJ\x10".to_vec();
+		let mut encoder = zstd::stream::write::Encoder::new(&mut frac_json_bytes, 3).unwrap();
+		std::io::Write::write_all(&mut encoder, &uncompressed[3..]).unwrap();
+		encoder.finish().unwrap();
+		assert_eq!(value, decode(&frac_json_bytes, None, None).unwrap());
+		let limits = DecodeLimits {
+			max_decompressed_size: uncompressed.len() - 3,
+			..Default::default()
+		};
+		assert_eq!(value, decode_with_limits(&frac_json_bytes, limits).unwrap());
+		let limits = DecodeLimits {
+			max_decompressed_size: uncompressed.len() - 4,
+			..Default::default()
+		};
+		assert_limit_exceeded(
+			decode_with_limits(&frac_json_bytes, limits),
+			Limit::DecompressedSize,
+		);
+	}
+
+	#[test]
+	fn test_huge_length_prefix() {
+		// array32 claiming 0xFFFFFFFF entries without any content
+		let err = decode(b"FJ\x00\x15\xFF\xFF\xFF\xFF", None, None).unwrap_err();
+		assert!(matches!(
+			err,
+			FracJsonError::UnexpectedEof { offset: 8, .. }
+		));
+	}
 }
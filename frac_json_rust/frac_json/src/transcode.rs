@@ -0,0 +1,397 @@
+use std::{
+    cell::Cell,
+    fmt,
+    io::{BufWriter, Read, Write},
+};
+
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::ser::{CompactFormatter, Formatter, PrettyFormatter};
+
+use crate::{
+    byte_stream::{ByteWriter, StrRead},
+    codec::{DecodeBody, Decoder, EncodeBody, Encoder},
+    error::FracJsonError,
+    json_types::{
+        object::{read_key_ref, write_transient_key, KeyRef},
+        value::{
+            read_value_header, write_array_header, write_bool, write_f64, write_i64, write_null,
+            write_object_header, write_string_value, write_u64, ValueHeader,
+        },
+    },
+    keys_table::{DecodeKeysTables, EncodeKeysTables},
+    options::{DecodeOptions, EncodeOptions},
+};
+
+/// Encodes JSON text read from `reader` directly into `writer`, without building a
+/// `serde_json::Value`. Use `json.as_bytes()` as the reader for a `&str`.
+///
+/// The output is the same as `encode_to_writer(&serde_json::from_reader(reader)?, ..)`, as
+/// long as no object contains a key twice. Since objects and arrays are prefixed with their
+/// length, each one is buffered in its encoded form until its end is reached.
+pub fn transcode_from_json<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    options: &EncodeOptions,
+) -> Result<(), FracJsonError> {
+    return Encoder::from_options(options)?.transcode_from_json(reader, writer);
+}
+
+/// Writes a frac_json file read from `reader` as JSON text into `writer`, without building
+/// a `serde_json::Value`. Use `&frac_json_bytes[..]` as the reader for bytes in memory.
+///
+/// `pretty` indents the output like `serde_json::to_writer_pretty`. `FLOAT` values are
+/// written with the shortest representation of the `f32`, not of its conversion to `f64`.
+/// NaN and infinity are written as `null`.
+pub fn transcode_to_json<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    pretty: bool,
+    options: &DecodeOptions,
+) -> Result<(), FracJsonError> {
+    return Decoder::from_options(options)?.transcode_to_json_from_reader(reader, writer, pretty);
+}
+
+fn json_error(error: serde_json::Error) -> FracJsonError {
+    if error.is_io() {
+        return FracJsonError::Io(error.into());
+    }
+    return FracJsonError::Serde(error.to_string());
+}
+
+/// JSON text that is encoded while it is parsed.
+pub(crate) struct JsonBody<R: Read> {
+    /// Taken when the body is written
+    reader: Cell<Option<R>>,
+}
+
+impl<R: Read> JsonBody<R> {
+    pub fn new(reader: R) -> JsonBody<R> {
+        JsonBody {
+            reader: Cell::new(Some(reader)),
+        }
+    }
+}
+
+impl<R: Read> EncodeBody for JsonBody<R> {
+    fn write_body<'a, W: ByteWriter>(
+        &'a self,
+        bytes: &mut W,
+        keys_table: &mut EncodeKeysTables<'a>,
+    ) -> Result<(), FracJsonError> {
+        let Some(reader) = self.reader.take() else {
+            return Err(FracJsonError::InvalidOptions(
+                "JSON input can only be encoded once".to_string(),
+            ));
+        };
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let mut write_error = None;
+        let seed = JsonSeed {
+            bytes,
+            keys_table,
+            write_error: &mut write_error,
+        };
+        let result = seed.deserialize(&mut deserializer);
+        // a failed write is reported to serde_json as a custom error, return the original
+        if let Some(error) = write_error {
+            return Err(error);
+        }
+        result.map_err(json_error)?;
+        return deserializer.end().map_err(json_error);
+    }
+}
+
+/// Writes the next JSON value in the same way as `write_value`.
+struct JsonSeed<'s, 'a, W: ByteWriter> {
+    bytes: &'s mut W,
+    keys_table: &'s mut EncodeKeysTables<'a>,
+    write_error: &'s mut Option<FracJsonError>,
+}
+
+impl<'s, 'a, W: ByteWriter> JsonSeed<'s, 'a, W> {
+    /// Passes errors from writing to the deserializer, keeping the original for later.
+    fn check<E: de::Error>(self, result: Result<(), FracJsonError>) -> Result<(), E> {
+        match result {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                let message = error.to_string();
+                *self.write_error = Some(error);
+                return Err(E::custom(message));
+            }
+        }
+    }
+
+    /// Writes the header and the buffered entries of an object or array, once their number
+    /// is known.
+    fn write_container<E: de::Error>(
+        self,
+        header: fn(usize, &mut W) -> Result<(), FracJsonError>,
+        length: usize,
+        buffer: &[u8],
+    ) -> Result<(), E> {
+        let result = header(length, self.bytes).and_then(|()| self.bytes.write(buffer));
+        return self.check(result);
+    }
+}
+
+impl<'de, W: ByteWriter> DeserializeSeed<'de> for JsonSeed<'_, '_, W> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, W: ByteWriter> Visitor<'de> for JsonSeed<'_, '_, W> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any JSON value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        let result = write_null(self.bytes);
+        self.check(result)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<(), E> {
+        let result = write_bool(v, self.bytes);
+        self.check(result)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<(), E> {
+        let result = write_i64(v, self.bytes);
+        self.check(result)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<(), E> {
+        let result = write_u64(v, self.bytes);
+        self.check(result)
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<(), E> {
+        let result = write_f64(v, self.bytes);
+        self.check(result)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<(), E> {
+        let result = write_string_value(v, self.bytes);
+        self.check(result)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut buffer = Vec::new();
+        let mut length = 0;
+        loop {
+            let element = JsonSeed {
+                bytes: &mut buffer,
+                keys_table: &mut *self.keys_table,
+                write_error: &mut *self.write_error,
+            };
+            if seq.next_element_seed(element)?.is_none() {
+                break;
+            }
+            length += 1;
+        }
+        return self.write_container(write_array_header, length, &buffer);
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut buffer = Vec::new();
+        let mut length = 0;
+        loop {
+            let key = KeySeed {
+                bytes: &mut buffer,
+                keys_table: &mut *self.keys_table,
+                write_error: &mut *self.write_error,
+            };
+            if map.next_key_seed(key)?.is_none() {
+                break;
+            }
+            map.next_value_seed(JsonSeed {
+                bytes: &mut buffer,
+                keys_table: &mut *self.keys_table,
+                write_error: &mut *self.write_error,
+            })?;
+            length += 1;
+        }
+        return self.write_container(write_object_header, length, &buffer);
+    }
+}
+
+/// Writes an object key in the same way as `write_key`.
+struct KeySeed<'s, 'a, W: ByteWriter> {
+    bytes: &'s mut W,
+    keys_table: &'s mut EncodeKeysTables<'a>,
+    write_error: &'s mut Option<FracJsonError>,
+}
+
+impl<'de, W: ByteWriter> DeserializeSeed<'de> for KeySeed<'_, '_, W> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de, W: ByteWriter> Visitor<'de> for KeySeed<'_, '_, W> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object key")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<(), E> {
+        match write_transient_key(v, self.bytes, self.keys_table) {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                let message = error.to_string();
+                *self.write_error = Some(error);
+                return Err(E::custom(message));
+            }
+        }
+    }
+}
+
+/// Output of a decoded file as JSON text.
+pub(crate) struct JsonTextBody<W: Write> {
+    writer: W,
+    pretty: bool,
+}
+
+impl<W: Write> JsonTextBody<W> {
+    pub fn new(writer: W, pretty: bool) -> JsonTextBody<W> {
+        JsonTextBody { writer, pretty }
+    }
+}
+
+impl<'de, W: Write> DecodeBody<'de> for JsonTextBody<W> {
+    type Output = ();
+
+    fn read_body<R: StrRead<'de>>(
+        self,
+        mut bytes: R,
+        mut keys_table: DecodeKeysTables,
+    ) -> Result<(), FracJsonError> {
+        let mut writer = BufWriter::new(self.writer);
+        let mut json = JsonTextWriter {
+            bytes: &mut bytes,
+            keys_table: &mut keys_table,
+            writer: &mut writer,
+        };
+        if self.pretty {
+            json.write_value(&mut PrettyFormatter::new())?;
+        } else {
+            json.write_value(&mut CompactFormatter)?;
+        }
+        return writer.flush().map_err(FracJsonError::Io);
+    }
+}
+
+struct JsonTextWriter<'s, 't, R, W: Write> {
+    bytes: &'s mut R,
+    keys_table: &'s mut DecodeKeysTables<'t>,
+    writer: &'s mut BufWriter<W>,
+}
+
+impl<'de, R: StrRead<'de>, W: Write> JsonTextWriter<'_, '_, R, W> {
+    fn write_value<F: Formatter>(&mut self, formatter: &mut F) -> Result<(), FracJsonError> {
+        let writer = &mut *self.writer;
+        let result = match read_value_header(self.bytes)? {
+            ValueHeader::Null => formatter.write_null(writer),
+            ValueHeader::Bool(b) => formatter.write_bool(writer, b),
+            ValueHeader::Int(n) => formatter.write_i64(writer, n),
+            ValueHeader::UInt(n) => formatter.write_u64(writer, n),
+            ValueHeader::Float(f) if f.is_finite() => formatter.write_f32(writer, f),
+            ValueHeader::Double(f) if f.is_finite() => formatter.write_f64(writer, f),
+            ValueHeader::Float(_) | ValueHeader::Double(_) => formatter.write_null(writer),
+            ValueHeader::String(length) => {
+                self.bytes.check_string_length(length)?;
+                let string = self.bytes.read_str(length)?;
+                return write_string(&string, self.writer);
+            }
+            ValueHeader::Object(length) => return self.write_object(length, formatter),
+            ValueHeader::Array(length) => return self.write_array(length, formatter),
+        };
+        return result.map_err(FracJsonError::Io);
+    }
+
+    fn write_object<F: Formatter>(
+        &mut self,
+        length: usize,
+        formatter: &mut F,
+    ) -> Result<(), FracJsonError> {
+        formatter
+            .begin_object(self.writer)
+            .map_err(FracJsonError::Io)?;
+        if length > 0 {
+            self.bytes.enter_container(length)?;
+            for i in 0..length {
+                formatter
+                    .begin_object_key(self.writer, i == 0)
+                    .map_err(FracJsonError::Io)?;
+                self.write_key()?;
+                formatter
+                    .end_object_key(self.writer)
+                    .map_err(FracJsonError::Io)?;
+                formatter
+                    .begin_object_value(self.writer)
+                    .map_err(FracJsonError::Io)?;
+                self.write_value(formatter)?;
+                formatter
+                    .end_object_value(self.writer)
+                    .map_err(FracJsonError::Io)?;
+            }
+            self.bytes.exit_container();
+        }
+        return formatter.end_object(self.writer).map_err(FracJsonError::Io);
+    }
+
+    fn write_array<F: Formatter>(
+        &mut self,
+        length: usize,
+        formatter: &mut F,
+    ) -> Result<(), FracJsonError> {
+        formatter
+            .begin_array(self.writer)
+            .map_err(FracJsonError::Io)?;
+        if length > 0 {
+            self.bytes.enter_container(length)?;
+            for i in 0..length {
+                formatter
+                    .begin_array_value(self.writer, i == 0)
+                    .map_err(FracJsonError::Io)?;
+                self.write_value(formatter)?;
+                formatter
+                    .end_array_value(self.writer)
+                    .map_err(FracJsonError::Io)?;
+            }
+            self.bytes.exit_container();
+        }
+        return formatter.end_array(self.writer).map_err(FracJsonError::Io);
+    }
+
+    fn write_key(&mut self) -> Result<(), FracJsonError> {
+        let key_offset = self.bytes.offset();
+        match read_key_ref(self.bytes)? {
+            KeyRef::Immediate(length) => {
+                let key = self.bytes.read_str(length)?;
+                self.keys_table.on_immediate_key(&key);
+                return write_string(&key, self.writer);
+            }
+            KeyRef::Local(index) => {
+                let key = self.keys_table.lookup_local_index(index, key_offset)?;
+                return write_string(key, self.writer);
+            }
+            KeyRef::Global(index) => {
+                let key = self.keys_table.lookup_global_index(index, key_offset)?;
+                return write_string(key, self.writer);
+            }
+        }
+    }
+}
+
+/// Writes `string` as a quoted and escaped JSON string. Escaping is the same for compact
+/// and pretty output.
+fn write_string<W: Write>(string: &str, writer: &mut W) -> Result<(), FracJsonError> {
+    return serde_json::to_writer(writer, string).map_err(json_error);
+}
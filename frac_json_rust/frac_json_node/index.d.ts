@@ -16,6 +16,8 @@ export interface DecodeOptions {
   globalKeysTableBytes?: Buffer
   /** pre trained zstandard dictionary */
   zstdDict?: Buffer
+  /** maximum size of decompressed data in bytes. Default is 1 GB. */
+  maxDecompressedSize?: number
 }
 /** Encode a JSON object (object, array, string, number, boolean, null) to a Buffer */
 export function encode(value: any, encodeOptions?: EncodeOptions | undefined | null): Buffer
@@ -26,5 +28,42 @@ export function decode(fracJsonBytes: Buffer, decodeOptions?: DecodeOptions | un
  * To improve performance during encoding, keys should be sorted by frequency of occurrence.
  */
 export function keysTableFromKeys(keys: Array<string>): Buffer
-/** Generate a keys table from a JSON object. */
+/**
+ * Generate a keys table from a JSON object.
+ * Only keys that save bytes are included, ordered by number of occurrences.
+ */
 export function keysTableFromJson(obj: any, maxCount?: number | undefined | null, occurrenceCutoff?: number | undefined | null): Buffer
+/**
+ * Table with all keys of `a`, followed by the keys of `b` that aren't in `a`.
+ * Files encoded with `a` can be decoded with the merged table.
+ */
+export function mergeKeysTables(a: KeysTable, b: KeysTable): KeysTable
+export interface KeysTableDiff {
+  /** keys in the new table but not in the old one */
+  added: Array<string>
+  /** keys in the old table but not in the new one */
+  removed: Array<string>
+  /** keys in both tables, but with a different index */
+  moved: Array<MovedKey>
+}
+export interface MovedKey {
+  key: string
+  oldIndex: number
+  newIndex: number
+}
+/** Compare two versions of a keys table. */
+export function diffKeysTables(oldTable: KeysTable, newTable: KeysTable): KeysTableDiff
+/** Parsed keys table, for inspecting and combining tables. */
+export class KeysTable {
+  constructor(keys: Array<string>)
+  static fromBytes(keysTableBytes: Buffer): KeysTable
+  toBytes(): Buffer
+  /** Number of keys */
+  get length(): number
+  /** All keys, in index order */
+  keys(): Array<string>
+  /** Index a key is encoded with, null if it isn't in the table */
+  indexOf(key: string): number | null
+  get(index: number): string | null
+  fingerprint(): number
+}
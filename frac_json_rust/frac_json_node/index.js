@@ -310,9 +310,12 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { encode, decode, keysTableFromKeys, keysTableFromJson } = nativeBinding
+const { encode, decode, keysTableFromKeys, keysTableFromJson, KeysTable, mergeKeysTables, diffKeysTables } = nativeBinding
 
 module.exports.encode = encode
 module.exports.decode = decode
 module.exports.keysTableFromKeys = keysTableFromKeys
 module.exports.keysTableFromJson = keysTableFromJson
+module.exports.KeysTable = KeysTable
+module.exports.mergeKeysTables = mergeKeysTables
+module.exports.diffKeysTables = diffKeysTables
@@ -3,10 +3,13 @@
 #[macro_use]
 extern crate napi_derive;
 
-use napi::{bindgen_prelude::Buffer, Error, Status};
+use napi::{bindgen_prelude::Buffer, Error};
 use serde_json::Value;
 
-use frac_json::{self, global_table_from_json_limited, global_table_from_keys};
+use frac_json::{
+  self, diff_global_tables, global_table_from_json_limited, global_table_from_keys,
+  merge_global_tables, DecodeLimits, FracJsonError, GlobalKeysTable,
+};
 
 #[napi(object)]
 #[derive(Default)]
@@ -26,6 +29,8 @@ pub struct DecodeOptions {
   pub global_keys_table_bytes: Option<Buffer>,
   /// pre trained zstandard dictionary
   pub zstd_dict: Option<Buffer>,
+  /// maximum size of decompressed data in bytes. Default is 1 GB.
+  pub max_decompressed_size: Option<i64>,
 }
 
 /// Encode a JSON object (object, array, string, number, boolean, null) to a Buffer
@@ -33,7 +38,7 @@ pub struct DecodeOptions {
 pub fn encode(
   value: Value,
   encode_options: Option<EncodeOptions>,
-) -> Result<Buffer, Error> {
+) -> Result<Buffer, Error<&'static str>> {
   let encode_options = encode_options.unwrap_or_default();
   let global_keys_table_bytes = buffer_to_vec(encode_options.global_keys_table_bytes);
   let compression_level = encode_options.compression_level;
@@ -44,13 +49,8 @@ pub fn encode(
     compression_level,
     zstd_dict.as_ref(),
   )
-  .map_err(|err| {
-    Error::new(
-      Status::GenericFailure,
-      format!("Failed to encode frac-json: {}", err),
-    )
-  })
-  .map(|vec| Buffer::from(vec))
+  .map_err(|err| to_napi_error(err, "Failed to encode frac-json"))
+  .map(Buffer::from)
 }
 
 /// Decode a Buffer to a JSON object (object, array, string, number, boolean, null).
@@ -58,58 +58,181 @@ pub fn encode(
 pub fn decode(
   frac_json_bytes: Buffer,
   decode_options: Option<DecodeOptions>,
-) -> Result<Value, Error> {
+) -> Result<Value, Error<&'static str>> {
   let decode_options = decode_options.unwrap_or_default();
-  let global_keys_table_bytes = buffer_to_vec(decode_options.global_keys_table_bytes);
-  let zstd_dict = buffer_to_vec(decode_options.zstd_dict);
-  frac_json::decode(
-    &Vec::from(frac_json_bytes),
-    global_keys_table_bytes.as_ref(),
-    zstd_dict.as_ref(),
-  )
-  .map_err(|err| {
-    Error::new(
-      Status::GenericFailure,
-      format!("Failed to decode frac-json: {}", err),
-    )
-  })
+  let mut limits = DecodeLimits::default();
+  if let Some(max_decompressed_size) = decode_options.max_decompressed_size {
+    limits.max_decompressed_size = max_decompressed_size.max(0) as usize;
+  }
+  let options = frac_json::DecodeOptions {
+    global_keys_table_bytes: decode_options.global_keys_table_bytes.as_deref(),
+    zstd_dict: decode_options.zstd_dict.as_deref(),
+    limits,
+    ..Default::default()
+  };
+  frac_json::decode_with_options(&frac_json_bytes, &options)
+    .map_err(|err| to_napi_error(err, "Failed to decode frac-json"))
 }
 
 /// Generate a keys table from a list of unique keys.  
 /// To improve performance during encoding, keys should be sorted by frequency of occurrence.
 #[napi]
-pub fn keys_table_from_keys(keys: Vec<String>) -> Result<Buffer, Error> {
+pub fn keys_table_from_keys(keys: Vec<String>) -> Result<Buffer, Error<&'static str>> {
   global_table_from_keys(keys)
-    .map_err(|err| {
-      Error::new(
-        Status::GenericFailure,
-        format!("Failed to write keys table: {}", err),
-      )
-    })
-    .map(|vec| Buffer::from(vec))
+    .map_err(|err| to_napi_error(err, "Failed to write keys table"))
+    .map(Buffer::from)
 }
 
-/// Generate a keys table from a JSON object.
+/// Generate a keys table from a JSON object.  
+/// Only keys that save bytes are included, ordered by number of occurrences.
 #[napi]
 pub fn keys_table_from_json(
   obj: Value,
   max_count: Option<i64>,
   occurrence_cutoff: Option<i64>,
-) -> Result<Buffer, Error> {
+) -> Result<Buffer, Error<&'static str>> {
   global_table_from_json_limited(
     &obj,
     max_count.map(|v| v as usize),
     occurrence_cutoff.map(|v| v as usize),
   )
-  .map_err(|err| {
-    Error::new(
-      Status::GenericFailure,
-      format!("Failed to create keys table: {}", err),
-    )
-  })
-  .map(|vec| Buffer::from(vec))
+  .map_err(|err| to_napi_error(err, "Failed to create keys table"))
+  .map(Buffer::from)
+}
+
+/// Parsed keys table, for inspecting and combining tables.
+#[napi]
+pub struct KeysTable {
+  table: GlobalKeysTable,
+}
+
+#[napi]
+impl KeysTable {
+  #[napi(constructor)]
+  pub fn new(keys: Vec<String>) -> Self {
+    KeysTable {
+      table: GlobalKeysTable::new(keys),
+    }
+  }
+
+  #[napi]
+  pub fn from_bytes(keys_table_bytes: Buffer) -> Result<Self, Error<&'static str>> {
+    let table = GlobalKeysTable::from_bytes(&keys_table_bytes)
+      .map_err(|err| to_napi_error(err, "Failed to read keys table"))?;
+    Ok(KeysTable { table })
+  }
+
+  #[napi]
+  pub fn to_bytes(&self) -> Result<Buffer, Error<&'static str>> {
+    self
+      .table
+      .to_bytes()
+      .map_err(|err| to_napi_error(err, "Failed to write keys table"))
+      .map(Buffer::from)
+  }
+
+  /// Number of keys
+  #[napi(getter)]
+  pub fn length(&self) -> u32 {
+    self.table.len() as u32
+  }
+
+  /// All keys, in index order
+  #[napi]
+  pub fn keys(&self) -> Vec<String> {
+    self.table.keys().to_vec()
+  }
+
+  /// Index a key is encoded with, null if it isn't in the table
+  #[napi]
+  pub fn index_of(&self, key: String) -> Option<u32> {
+    self.table.index_of(&key).map(|index| index as u32)
+  }
+
+  #[napi]
+  pub fn get(&self, index: u32) -> Option<String> {
+    self.table.get(index as usize).map(str::to_string)
+  }
+
+  #[napi]
+  pub fn fingerprint(&self) -> u32 {
+    self.table.fingerprint()
+  }
+}
+
+/// Table with all keys of `a`, followed by the keys of `b` that aren't in `a`.
+/// Files encoded with `a` can be decoded with the merged table.
+#[napi]
+pub fn merge_keys_tables(a: &KeysTable, b: &KeysTable) -> Result<KeysTable, Error<&'static str>> {
+  let table = merge_global_tables(&a.table, &b.table)
+    .map_err(|err| to_napi_error(err, "Failed to merge keys tables"))?;
+  Ok(KeysTable { table })
+}
+
+#[napi(object)]
+pub struct KeysTableDiff {
+  /// keys in the new table but not in the old one
+  pub added: Vec<String>,
+  /// keys in the old table but not in the new one
+  pub removed: Vec<String>,
+  /// keys in both tables, but with a different index
+  pub moved: Vec<MovedKey>,
+}
+
+#[napi(object)]
+pub struct MovedKey {
+  pub key: String,
+  pub old_index: u32,
+  pub new_index: u32,
+}
+
+/// Compare two versions of a keys table.
+#[napi]
+pub fn diff_keys_tables(old_table: &KeysTable, new_table: &KeysTable) -> KeysTableDiff {
+  let diff = diff_global_tables(&old_table.table, &new_table.table);
+  KeysTableDiff {
+    added: diff.added,
+    removed: diff.removed,
+    moved: diff
+      .moved
+      .into_iter()
+      .map(|moved_key| MovedKey {
+        key: moved_key.key,
+        old_index: moved_key.old_index as u32,
+        new_index: moved_key.new_index as u32,
+      })
+      .collect(),
+  }
 }
 
 fn buffer_to_vec(buffer: Option<Buffer>) -> Option<Vec<u8>> {
-  buffer.and_then(|buffer| Some(Vec::from(buffer)))
+  buffer.map(Vec::from)
+}
+
+/// Maps a frac_json error to a JS error, with `error.code` identifying the kind of error.
+fn to_napi_error(err: FracJsonError, context: &str) -> Error<&'static str> {
+  let code = match err {
+    FracJsonError::InvalidMagic { .. } => "InvalidMagic",
+    FracJsonError::UnsupportedVersion { .. } => "UnsupportedVersion",
+    FracJsonError::UnsupportedKeysTableConfig { .. } => "UnsupportedKeysTableConfig",
+    FracJsonError::UnexpectedEof { .. } => "UnexpectedEof",
+    FracJsonError::UnknownGlobalKeyIndex { .. } => "UnknownGlobalKeyIndex",
+    FracJsonError::UnknownLocalKeyIndex { .. } => "UnknownLocalKeyIndex",
+    FracJsonError::ReservedType { .. } => "ReservedType",
+    FracJsonError::InvalidKeyIndex { .. } => "InvalidKeyIndex",
+    FracJsonError::InvalidNumber { .. } => "InvalidNumber",
+    FracJsonError::InvalidUtf8 { .. } => "InvalidUtf8",
+    FracJsonError::InvalidArchiveIndex { .. } => "InvalidArchiveIndex",
+    FracJsonError::NotAResetPoint { .. } => "NotAResetPoint",
+    FracJsonError::LimitExceeded { .. } => "LimitExceeded",
+    FracJsonError::KeysTableMismatch { .. } => "KeysTableMismatch",
+    FracJsonError::KeysTableLayerMismatch { .. } => "KeysTableLayerMismatch",
+    FracJsonError::ChecksumMismatch { .. } => "ChecksumMismatch",
+    FracJsonError::MissingZstdDict => "MissingZstdDict",
+    FracJsonError::InvalidOptions(_) => "InvalidOptions",
+    FracJsonError::Compression(_) => "Compression",
+    FracJsonError::Io(_) => "Io",
+    FracJsonError::Serde(_) => "Serde",
+  };
+  Error::new(code, format!("{}: {}", context, err))
 }
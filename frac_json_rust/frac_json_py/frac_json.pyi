@@ -1,7 +1,22 @@
-from typing import Optional, List, Any, Union
+from typing import Optional, List, Any, Union, Tuple, Dict
 
 json_type = Union[dict, list, str, int, float, bool, None]
 
+class FracJsonError(Exception): ...
+class InvalidMagicError(FracJsonError): ...
+class UnsupportedVersionError(FracJsonError): ...
+class UnexpectedEofError(FracJsonError): ...
+class UnknownGlobalKeyIndexError(FracJsonError): ...
+class UnknownLocalKeyIndexError(FracJsonError): ...
+class ReservedTypeError(FracJsonError): ...
+class InvalidKeyIndexError(FracJsonError): ...
+class InvalidUtf8Error(FracJsonError): ...
+class LimitExceededError(FracJsonError): ...
+class CompressionError(FracJsonError): ...
+class KeysTableMismatchError(FracJsonError): ...
+class KeysTableLayerMismatchError(FracJsonError): ...
+class ChecksumMismatchError(FracJsonError): ...
+
 def encode(
     object: json_type,
     global_keys_table_bytes: Optional[bytes] = None,
@@ -14,6 +29,7 @@ def decode(
     frac_json_bytes: bytes,
     global_keys_table_bytes: Optional[bytes] = None,
     zstd_dict: Optional[bytes] = None,
+    max_decompressed_size: Optional[int] = None,
 ) -> Any:
     ...
 
@@ -26,3 +42,21 @@ def keys_table_from_json(
     occurrence_cutoff: Optional[int] = None,
 ) -> bytes:
     ...
+
+class KeysTable:
+    @staticmethod
+    def from_keys(keys: List[str]) -> "KeysTable": ...
+    @staticmethod
+    def from_bytes(keys_table_bytes: bytes) -> "KeysTable": ...
+    def to_bytes(self) -> bytes: ...
+    def __len__(self) -> int: ...
+    def keys(self) -> List[str]: ...
+    def index_of(self, key: str) -> Optional[int]: ...
+    def get(self, index: int) -> Optional[str]: ...
+    def fingerprint(self) -> int: ...
+
+def merge_keys_tables(a: KeysTable, b: KeysTable) -> KeysTable:
+    ...
+
+def diff_keys_tables(old: KeysTable, new: KeysTable) -> Dict[str, Union[List[str], List[Tuple[str, int, int]]]]:
+    ...
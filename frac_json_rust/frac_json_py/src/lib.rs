@@ -10,6 +10,50 @@ use serde_json::{Map, Number, Value};
 use ::frac_json as fj;
 
 create_exception!(frac_json, FracJsonError, pyo3::exceptions::PyException);
+create_exception!(frac_json, InvalidMagicError, FracJsonError);
+create_exception!(frac_json, UnsupportedVersionError, FracJsonError);
+create_exception!(frac_json, UnexpectedEofError, FracJsonError);
+create_exception!(frac_json, UnknownGlobalKeyIndexError, FracJsonError);
+create_exception!(frac_json, UnknownLocalKeyIndexError, FracJsonError);
+create_exception!(frac_json, ReservedTypeError, FracJsonError);
+create_exception!(frac_json, InvalidKeyIndexError, FracJsonError);
+create_exception!(frac_json, InvalidUtf8Error, FracJsonError);
+create_exception!(frac_json, LimitExceededError, FracJsonError);
+create_exception!(frac_json, CompressionError, FracJsonError);
+create_exception!(frac_json, KeysTableMismatchError, FracJsonError);
+create_exception!(frac_json, KeysTableLayerMismatchError, FracJsonError);
+create_exception!(frac_json, ChecksumMismatchError, FracJsonError);
+
+fn to_py_err(err: fj::FracJsonError) -> PyErr {
+    let message = err.to_string();
+    match err {
+        fj::FracJsonError::InvalidMagic { .. } => InvalidMagicError::new_err(message),
+        fj::FracJsonError::UnsupportedVersion { .. }
+        | fj::FracJsonError::UnsupportedKeysTableConfig { .. } => {
+            UnsupportedVersionError::new_err(message)
+        }
+        fj::FracJsonError::UnexpectedEof { .. } => UnexpectedEofError::new_err(message),
+        fj::FracJsonError::UnknownGlobalKeyIndex { .. } => {
+            UnknownGlobalKeyIndexError::new_err(message)
+        }
+        fj::FracJsonError::UnknownLocalKeyIndex { .. } => {
+            UnknownLocalKeyIndexError::new_err(message)
+        }
+        fj::FracJsonError::ReservedType { .. } => ReservedTypeError::new_err(message),
+        fj::FracJsonError::InvalidKeyIndex { .. } => InvalidKeyIndexError::new_err(message),
+        fj::FracJsonError::InvalidUtf8 { .. } => InvalidUtf8Error::new_err(message),
+        fj::FracJsonError::LimitExceeded { .. } => LimitExceededError::new_err(message),
+        fj::FracJsonError::MissingZstdDict | fj::FracJsonError::Compression(_) => {
+            CompressionError::new_err(message)
+        }
+        fj::FracJsonError::KeysTableMismatch { .. } => KeysTableMismatchError::new_err(message),
+        fj::FracJsonError::ChecksumMismatch { .. } => ChecksumMismatchError::new_err(message),
+        fj::FracJsonError::KeysTableLayerMismatch { .. } => {
+            KeysTableLayerMismatchError::new_err(message)
+        }
+        _ => FracJsonError::new_err(message),
+    }
+}
 
 #[pyfunction]
 pub fn encode(
@@ -19,10 +63,10 @@ pub fn encode(
     compression_level: Option<i32>,
     zstd_dict: Option<Vec<u8>>,
 ) -> PyResult<Cow<[u8]>> {
-    let value = py_to_json(py, &object).map_err(|err| FracJsonError::new_err(err))?;
+    let value = py_to_json(py, &object).map_err(FracJsonError::new_err)?;
     fj::encode(&value, global_keys_table_bytes.as_ref(), compression_level, zstd_dict.as_ref())
-        .map(|vec| Cow::from(vec))
-        .map_err(|err| FracJsonError::new_err(err))
+        .map(Cow::from)
+        .map_err(to_py_err)
 }
 
 #[pyfunction]
@@ -31,17 +75,27 @@ pub fn decode(
     frac_json_bytes: Vec<u8>,
     global_keys_table_bytes: Option<Vec<u8>>,
     zstd_dict: Option<Vec<u8>>,
+    max_decompressed_size: Option<usize>,
 ) -> PyResult<PyObject> {
-    let value = fj::decode(frac_json_bytes.as_ref(), global_keys_table_bytes.as_ref(), zstd_dict.as_ref())
-        .map_err(|err| FracJsonError::new_err(err))?;
-    Ok(json_to_py(py, &value).map_err(|err| FracJsonError::new_err(err))?)
+    let mut limits = fj::DecodeLimits::default();
+    if let Some(max_decompressed_size) = max_decompressed_size {
+        limits.max_decompressed_size = max_decompressed_size;
+    }
+    let options = fj::DecodeOptions {
+        global_keys_table_bytes: global_keys_table_bytes.as_deref(),
+        zstd_dict: zstd_dict.as_deref(),
+        limits,
+        ..Default::default()
+    };
+    let value = fj::decode_with_options(&frac_json_bytes, &options).map_err(to_py_err)?;
+    json_to_py(py, &value).map_err(FracJsonError::new_err)
 }
 
 #[pyfunction]
 pub fn keys_table_from_keys(_py: Python, keys: Vec<String>) -> PyResult<Cow<[u8]>> {
     fj::global_table_from_keys(keys)
-        .map(|vec| Cow::from(vec))
-        .map_err(|err| FracJsonError::new_err(err))
+        .map(Cow::from)
+        .map_err(to_py_err)
 }
 
 #[pyfunction]
@@ -51,29 +105,117 @@ pub fn keys_table_from_json(
     max_count: Option<i64>,
     occurrence_cutoff: Option<i64>,
 ) -> PyResult<Cow<[u8]>> {
-    let value = py_to_json(py, &object).map_err(|err| FracJsonError::new_err(err))?;
+    let value = py_to_json(py, &object).map_err(FracJsonError::new_err)?;
     fj::global_table_from_json_limited(
         &value,
         max_count.map(|v| v as usize),
         occurrence_cutoff.map(|v| v as usize),
     )
-    .map(|vec| Cow::from(vec))
-    .map_err(|err| FracJsonError::new_err(err))
+    .map(Cow::from)
+    .map_err(to_py_err)
+}
+
+/// Parsed global keys table, for inspecting and combining tables.
+#[pyclass]
+pub struct KeysTable {
+    table: fj::GlobalKeysTable,
+}
+
+#[pymethods]
+impl KeysTable {
+    #[staticmethod]
+    fn from_keys(keys: Vec<String>) -> Self {
+        KeysTable {
+            table: fj::GlobalKeysTable::new(keys),
+        }
+    }
+
+    #[staticmethod]
+    fn from_bytes(keys_table_bytes: Vec<u8>) -> PyResult<Self> {
+        let table = fj::GlobalKeysTable::from_bytes(&keys_table_bytes).map_err(to_py_err)?;
+        Ok(KeysTable { table })
+    }
+
+    fn to_bytes(&self) -> PyResult<Cow<'static, [u8]>> {
+        self.table.to_bytes().map(Cow::from).map_err(to_py_err)
+    }
+
+    fn __len__(&self) -> usize {
+        self.table.len()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.table.keys().to_vec()
+    }
+
+    fn index_of(&self, key: &str) -> Option<usize> {
+        self.table.index_of(key)
+    }
+
+    fn get(&self, index: usize) -> Option<String> {
+        self.table.get(index).map(str::to_string)
+    }
+
+    fn fingerprint(&self) -> u32 {
+        self.table.fingerprint()
+    }
+}
+
+#[pyfunction]
+pub fn merge_keys_tables(a: PyRef<KeysTable>, b: PyRef<KeysTable>) -> PyResult<KeysTable> {
+    let table = fj::merge_global_tables(&a.table, &b.table).map_err(to_py_err)?;
+    Ok(KeysTable { table })
+}
+
+#[pyfunction]
+pub fn diff_keys_tables(
+    py: Python,
+    old: PyRef<KeysTable>,
+    new: PyRef<KeysTable>,
+) -> PyResult<PyObject> {
+    let diff = fj::diff_global_tables(&old.table, &new.table);
+    let moved: Vec<(String, usize, usize)> = diff
+        .moved
+        .into_iter()
+        .map(|moved_key| (moved_key.key, moved_key.old_index, moved_key.new_index))
+        .collect();
+    let dict = PyDict::new_bound(py);
+    dict.set_item("added", diff.added)?;
+    dict.set_item("removed", diff.removed)?;
+    dict.set_item("moved", moved)?;
+    Ok(dict.to_object(py))
 }
 
 #[pymodule]
-fn frac_json(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
+fn frac_json(py: Python, m: &Bound<PyModule>) -> PyResult<()> {
+    m.add("FracJsonError", py.get_type_bound::<FracJsonError>())?;
+    m.add("InvalidMagicError", py.get_type_bound::<InvalidMagicError>())?;
+    m.add("UnsupportedVersionError", py.get_type_bound::<UnsupportedVersionError>())?;
+    m.add("UnexpectedEofError", py.get_type_bound::<UnexpectedEofError>())?;
+    m.add("UnknownGlobalKeyIndexError", py.get_type_bound::<UnknownGlobalKeyIndexError>())?;
+    m.add("UnknownLocalKeyIndexError", py.get_type_bound::<UnknownLocalKeyIndexError>())?;
+    m.add("ReservedTypeError", py.get_type_bound::<ReservedTypeError>())?;
+    m.add("InvalidKeyIndexError", py.get_type_bound::<InvalidKeyIndexError>())?;
+    m.add("InvalidUtf8Error", py.get_type_bound::<InvalidUtf8Error>())?;
+    m.add("LimitExceededError", py.get_type_bound::<LimitExceededError>())?;
+    m.add("CompressionError", py.get_type_bound::<CompressionError>())?;
+    m.add("KeysTableMismatchError", py.get_type_bound::<KeysTableMismatchError>())?;
+    m.add("KeysTableLayerMismatchError", py.get_type_bound::<KeysTableLayerMismatchError>())?;
+    m.add("ChecksumMismatchError", py.get_type_bound::<ChecksumMismatchError>())?;
     m.add_function(wrap_pyfunction!(encode, m)?)?;
     m.add_function(wrap_pyfunction!(decode, m)?)?;
     m.add_function(wrap_pyfunction!(keys_table_from_keys, m)?)?;
     m.add_function(wrap_pyfunction!(keys_table_from_json, m)?)?;
+    m.add_class::<KeysTable>()?;
+    m.add_function(wrap_pyfunction!(merge_keys_tables, m)?)?;
+    m.add_function(wrap_pyfunction!(diff_keys_tables, m)?)?;
     Ok(())
 }
 
 fn py_to_json(py: Python, obj: &PyObject) -> Result<serde_json::Value, String> {
     macro_rules! return_cast {
         ($t:ty, $f:expr) => {
-            if let Ok(val) = obj.downcast::<$t>(py) {
+            if let Ok(val) = obj.bind(py).downcast::<$t>() {
                 return $f(val);
             }
         };
@@ -95,7 +237,7 @@ fn py_to_json(py: Python, obj: &PyObject) -> Result<serde_json::Value, String> {
     return_to_value!(bool);
     return_to_value!(u64);
     return_to_value!(i64);
-    return_cast!(PyFloat, |x: &PyFloat| {
+    return_cast!(PyFloat, |x: &Bound<PyFloat>| {
         match Number::from_f64(x.value()) {
             Some(n) => Ok(Value::Number(n)),
             None => Err("Failed to convert float to JSON number".to_string()),
@@ -103,17 +245,14 @@ fn py_to_json(py: Python, obj: &PyObject) -> Result<serde_json::Value, String> {
     });
 
     // dict
-    return_cast!(PyDict, |x: &PyDict| {
+    return_cast!(PyDict, |x: &Bound<PyDict>| {
         let mut map = Map::new();
         for (key_obj, value) in x.iter() {
             let key = if let Ok(val) = key_obj.downcast::<PyString>() {
                 Ok(val.to_str().map_err(|e| e.to_string())?)
             } else {
                 match key_obj.str() {
-                    Ok(val) => Err(format!(
-                        "Failed to convert key {} to string",
-                        val.to_string()
-                    )),
+                    Ok(val) => Err(format!("Failed to convert key {} to string", val)),
                     Err(_) => Err("Failed to convert key to string".to_string()),
                 }
             };
@@ -123,7 +262,7 @@ fn py_to_json(py: Python, obj: &PyObject) -> Result<serde_json::Value, String> {
     });
 
     // list
-    return_cast!(PyList, |x: &PyList| Ok(Value::Array(
+    return_cast!(PyList, |x: &Bound<PyList>| Ok(Value::Array(
         x.iter()
             .map(|x| py_to_json(py, &x.to_object(py)))
             .collect::<Result<Vec<_>, _>>()?
@@ -131,11 +270,10 @@ fn py_to_json(py: Python, obj: &PyObject) -> Result<serde_json::Value, String> {
 
     // At this point we can't cast it, set up the error object
     Err(obj
-        .to_object(py)
-        .as_ref(py)
+        .bind(py)
         .get_type()
         .name()
-        .and_then(|name| Ok(format!("Failed to convert {} to JSON", name)))
+        .map(|name| format!("Failed to convert {} to JSON", name))
         .unwrap_or("Failed to convert object to JSON".to_string()))
 }
 
@@ -156,7 +294,7 @@ fn json_to_py(py: Python, value: &serde_json::Value) -> Result<PyObject, String>
         }
         Value::String(s) => Ok(s.to_object(py)),
         Value::Array(a) => {
-            let list = PyList::empty(py);
+            let list = PyList::empty_bound(py);
             for item in a {
                 list.append(json_to_py(py, item)?)
                     .map_err(|err| err.to_string())?;
@@ -164,7 +302,7 @@ fn json_to_py(py: Python, value: &serde_json::Value) -> Result<PyObject, String>
             Ok(list.to_object(py))
         }
         Value::Object(o) => {
-            let dict = PyDict::new(py);
+            let dict = PyDict::new_bound(py);
             for (key, value) in o {
                 dict.set_item(key, json_to_py(py, value)?)
                     .map_err(|err| err.to_string())?;